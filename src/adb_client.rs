@@ -0,0 +1,452 @@
+use crate::adb_utils::AdbOutput;
+use std::env;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Address of the adb server when no environment variable overrides it
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 5037;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Shell protocol v2 packet ids
+const SHELL_STDOUT: u8 = 1;
+const SHELL_STDERR: u8 = 2;
+const SHELL_EXIT: u8 = 3;
+const SHELL_CLOSE_STDIN: u8 = 4;
+
+/// Why a command could not be run through the adb server
+#[derive(Debug)]
+pub enum ClientError {
+    /// The adb server could not be reached; nothing was sent to the device
+    Unavailable(io::Error),
+    /// The server refused the request, e.g. `device offline` or `device unauthorized`
+    Rejected(String),
+    /// The device does not offer the service (e.g. shell protocol v2 before Android 7)
+    Unsupported(String),
+    /// The connection broke while the command was running
+    Io(io::Error),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Unavailable(e) => write!(f, "Cannot connect to the adb server: {}", e),
+            ClientError::Rejected(msg) => write!(f, "{}", msg),
+            ClientError::Unsupported(msg) => write!(f, "Service not supported: {}", msg),
+            ClientError::Io(e) => write!(f, "Connection to the adb server failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+/// Client for the adb server's smart-socket protocol.
+///
+/// Each request is sent as four hex digits of length followed by the request and is
+/// answered with `OKAY` or with `FAIL` and a length-prefixed message. A connection is
+/// switched to a device with `host:transport:<serial>` and then opened to a service
+/// on it (`shell,v2,raw:`, `exec:`, `sync:`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdbClient {
+    address: String,
+}
+
+impl AdbClient {
+    pub fn new(address: impl Into<String>) -> AdbClient {
+        AdbClient {
+            address: address.into(),
+        }
+    }
+
+    /// The server given by `ADB_SERVER_SOCKET=tcp:host:port`, or by
+    /// `ANDROID_ADB_SERVER_ADDRESS` and `ANDROID_ADB_SERVER_PORT`, like the adb binary does
+    pub fn from_env() -> AdbClient {
+        if let Some(address) = env::var("ADB_SERVER_SOCKET")
+            .ok()
+            .and_then(|socket| socket.strip_prefix("tcp:").map(str::to_string))
+        {
+            return AdbClient::new(address);
+        }
+        let host = env::var("ANDROID_ADB_SERVER_ADDRESS").unwrap_or(DEFAULT_HOST.to_string());
+        let port = env::var("ANDROID_ADB_SERVER_PORT")
+            .ok()
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(DEFAULT_PORT);
+        AdbClient::new(format!("{}:{}", host, port))
+    }
+
+    /// Run a shell command with shell protocol v2, which keeps stderr and the exit status
+    pub fn shell(&self, serial: Option<&str>, command: &str) -> Result<AdbOutput, ClientError> {
+        let mut stream = self.open(serial, &format!("shell,v2,raw:{}", command))?;
+        stream
+            .write_all(&[SHELL_CLOSE_STDIN, 0, 0, 0, 0])
+            .map_err(ClientError::Io)?;
+        read_shell_v2(&mut stream).map_err(ClientError::Io)
+    }
+
+    /// Run a command and return its raw stdout, like `adb exec-out`
+    pub fn exec(&self, serial: Option<&str>, command: &str) -> Result<AdbOutput, ClientError> {
+        let mut stream = self.open(serial, &format!("exec:{}", command))?;
+        let mut stdout = Vec::new();
+        stream.read_to_end(&mut stdout).map_err(ClientError::Io)?;
+        Ok(AdbOutput {
+            success: true,
+            stdout,
+            stderr: Vec::new(),
+        })
+    }
+
+    /// Open `exec:<command>` and return the connection, which carries the command's
+    /// stdin and stdout in both directions
+    pub fn exec_stream(
+        &self,
+        serial: Option<&str>,
+        command: &str,
+    ) -> Result<TcpStream, ClientError> {
+        self.open(serial, &format!("exec:{}", command))
+    }
+
+    /// Read a file from the device with the sync service
+    pub fn pull(&self, serial: Option<&str>, path: &str) -> Result<AdbOutput, ClientError> {
+        let mut stream = self.open(serial, "sync:")?;
+        write_sync_request(&mut stream, b"RECV", path.as_bytes()).map_err(ClientError::Io)?;
+        let output = match read_sync_data(&mut stream).map_err(ClientError::Io)? {
+            Ok(stdout) => AdbOutput {
+                success: true,
+                stdout,
+                stderr: Vec::new(),
+            },
+            Err(message) => AdbOutput {
+                success: false,
+                stdout: Vec::new(),
+                stderr: message.into_bytes(),
+            },
+        };
+        write_sync_request(&mut stream, b"QUIT", b"").map_err(ClientError::Io)?;
+        Ok(output)
+    }
+
+    /// Connect to the server, select the device and open the service on it
+    fn open(&self, serial: Option<&str>, service: &str) -> Result<TcpStream, ClientError> {
+        let mut stream = self.connect().map_err(ClientError::Unavailable)?;
+
+        let transport = match serial {
+            Some(serial) => format!("host:transport:{}", serial),
+            None => "host:transport-any".to_string(),
+        };
+        request(&mut stream, &transport)
+            .map_err(ClientError::Io)?
+            .map_err(ClientError::Rejected)?;
+
+        request(&mut stream, service)
+            .map_err(ClientError::Io)?
+            .map_err(|message| {
+                if service.starts_with("shell,v2") {
+                    ClientError::Unsupported(message)
+                } else {
+                    ClientError::Rejected(message)
+                }
+            })?;
+        Ok(stream)
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let addresses: Vec<SocketAddr> = self.address.to_socket_addrs()?.collect();
+        let mut last_error = io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("adb server address {} did not resolve", self.address),
+        );
+        for address in addresses {
+            match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+}
+
+/// Send a request and read the server's `OKAY` or `FAIL` answer.
+/// The outer error is a connection problem, the inner one the server's message.
+fn request(stream: &mut (impl Read + Write), request: &str) -> io::Result<Result<(), String>> {
+    write!(stream, "{:04x}{}", request.len(), request)?;
+    stream.flush()?;
+
+    let mut status = [0u8; 4];
+    stream.read_exact(&mut status)?;
+    match &status {
+        b"OKAY" => Ok(Ok(())),
+        b"FAIL" => {
+            let mut length = [0u8; 4];
+            stream.read_exact(&mut length)?;
+            let length = std::str::from_utf8(&length)
+                .ok()
+                .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| invalid_data("invalid FAIL message length"))?;
+            let mut message = vec![0u8; length];
+            stream.read_exact(&mut message)?;
+            Ok(Err(String::from_utf8_lossy(&message).trim().to_string()))
+        }
+        other => Err(invalid_data(&format!(
+            "unexpected adb server status {:?}",
+            String::from_utf8_lossy(other)
+        ))),
+    }
+}
+
+/// Collect stdout, stderr and the exit status from shell protocol v2 packets:
+/// one id byte, a little-endian u32 length and the data
+fn read_shell_v2(stream: &mut impl Read) -> io::Result<AdbOutput> {
+    let mut output = AdbOutput::default();
+    loop {
+        let mut header = [0u8; 5];
+        stream.read_exact(&mut header)?;
+        let length = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        let mut data = vec![0u8; length];
+        stream.read_exact(&mut data)?;
+        match header[0] {
+            SHELL_STDOUT => output.stdout.extend(data),
+            SHELL_STDERR => output.stderr.extend(data),
+            SHELL_EXIT => {
+                output.success = data.first() == Some(&0);
+                return Ok(output);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Sync requests are a four-letter id, a little-endian u32 length and the data
+fn write_sync_request(stream: &mut impl Write, id: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    stream.write_all(id)?;
+    stream.write_all(&(data.len() as u32).to_le_bytes())?;
+    stream.write_all(data)?;
+    stream.flush()
+}
+
+/// Read the `DATA` chunks of a `RECV` up to `DONE`, or the message of a `FAIL`
+fn read_sync_data(stream: &mut impl Read) -> io::Result<Result<Vec<u8>, String>> {
+    let mut content = Vec::new();
+    loop {
+        let mut header = [0u8; 8];
+        stream.read_exact(&mut header)?;
+        let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        match &header[..4] {
+            b"DATA" => {
+                let start = content.len();
+                content.resize(start + length, 0);
+                stream.read_exact(&mut content[start..])?;
+            }
+            b"DONE" => return Ok(Ok(content)),
+            b"FAIL" => {
+                let mut message = vec![0u8; length];
+                stream.read_exact(&mut message)?;
+                return Ok(Err(String::from_utf8_lossy(&message).to_string()));
+            }
+            other => {
+                return Err(invalid_data(&format!(
+                    "unexpected sync response {:?}",
+                    String::from_utf8_lossy(other)
+                )))
+            }
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// In-memory stream: reads from `input`, records writes in `written`
+    struct FakeStream {
+        input: Cursor<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl FakeStream {
+        fn new(input: &[u8]) -> FakeStream {
+            FakeStream {
+                input: Cursor::new(input.to_vec()),
+                written: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for FakeStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for FakeStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn shell_packet(id: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![id];
+        packet.extend((data.len() as u32).to_le_bytes());
+        packet.extend(data);
+        packet
+    }
+
+    #[test]
+    fn test_request_okay() {
+        let mut stream = FakeStream::new(b"OKAY");
+        assert_eq!(request(&mut stream, "host:transport-any").unwrap(), Ok(()));
+        assert_eq!(stream.written, b"0012host:transport-any");
+    }
+
+    #[test]
+    fn test_request_fail_message() {
+        let mut stream = FakeStream::new(b"FAIL000edevice offline");
+        assert_eq!(
+            request(&mut stream, "host:transport:emulator-5554").unwrap(),
+            Err("device offline".to_string())
+        );
+    }
+
+    #[test]
+    fn test_request_unexpected_status() {
+        let mut stream = FakeStream::new(b"WHAT");
+        assert!(request(&mut stream, "host:version").is_err());
+    }
+
+    #[test]
+    fn test_read_shell_v2() {
+        let mut input = shell_packet(SHELL_STDOUT, b"Physical ");
+        input.extend(shell_packet(SHELL_STDERR, b"warning\n"));
+        input.extend(shell_packet(SHELL_STDOUT, b"size: 1080x1920\n"));
+        input.extend(shell_packet(SHELL_EXIT, &[0]));
+        let output = read_shell_v2(&mut Cursor::new(input)).unwrap();
+        assert!(output.success);
+        assert_eq!(output.stdout, b"Physical size: 1080x1920\n");
+        assert_eq!(output.stderr, b"warning\n");
+
+        let failed = read_shell_v2(&mut Cursor::new(shell_packet(SHELL_EXIT, &[1]))).unwrap();
+        assert!(!failed.success);
+    }
+
+    #[test]
+    fn test_read_shell_v2_without_exit_status() {
+        let input = shell_packet(SHELL_STDOUT, b"partial");
+        assert!(read_shell_v2(&mut Cursor::new(input)).is_err());
+    }
+
+    #[test]
+    fn test_sync_recv() {
+        let mut stream =
+            FakeStream::new(b"DATA\x03\x00\x00\x00<a>DATA\x04\x00\x00\x00</a>DONE\x00\x00\x00\x00");
+        write_sync_request(&mut stream, b"RECV", b"/sdcard/x.xml").unwrap();
+        assert_eq!(stream.written, b"RECV\x0d\x00\x00\x00/sdcard/x.xml");
+        assert_eq!(
+            read_sync_data(&mut stream).unwrap(),
+            Ok(b"<a></a>".to_vec())
+        );
+
+        let mut stream = FakeStream::new(b"FAIL\x0e\x00\x00\x00No such file!!");
+        assert_eq!(
+            read_sync_data(&mut stream).unwrap(),
+            Err("No such file!!".to_string())
+        );
+    }
+
+    /// Serve one connection: answer the transport and service requests, then send `reply`
+    fn fake_server(
+        transport_reply: &'static [u8],
+        reply: Vec<u8>,
+    ) -> (String, thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            let mut read_request = |stream: &mut TcpStream| {
+                let mut length = [0u8; 4];
+                stream.read_exact(&mut length).unwrap();
+                let length =
+                    usize::from_str_radix(std::str::from_utf8(&length).unwrap(), 16).unwrap();
+                let mut request = vec![0u8; length];
+                stream.read_exact(&mut request).unwrap();
+                received.extend(&request);
+                received.push(b'\n');
+            };
+            read_request(&mut stream);
+            stream.write_all(transport_reply).unwrap();
+            if transport_reply == b"OKAY" {
+                read_request(&mut stream);
+                stream.write_all(b"OKAY").unwrap();
+                stream.write_all(&reply).unwrap();
+            }
+            received
+        });
+        (address, handle)
+    }
+
+    #[test]
+    fn test_shell_through_server() {
+        let mut reply = shell_packet(SHELL_STDOUT, b"ok\n");
+        reply.extend(shell_packet(SHELL_EXIT, &[0]));
+        let (address, server) = fake_server(b"OKAY", reply);
+
+        let output = AdbClient::new(address)
+            .shell(Some("emulator-5554"), "input tap 10 20")
+            .unwrap();
+        assert!(output.success);
+        assert_eq!(output.stdout, b"ok\n");
+        assert_eq!(
+            server.join().unwrap(),
+            b"host:transport:emulator-5554\nshell,v2,raw:input tap 10 20\n"
+        );
+    }
+
+    #[test]
+    fn test_exec_through_server() {
+        let (address, server) = fake_server(b"OKAY", b"<hierarchy />".to_vec());
+        let output = AdbClient::new(address)
+            .exec(None, "uiautomator dump /dev/tty")
+            .unwrap();
+        assert_eq!(output.stdout, b"<hierarchy />");
+        assert_eq!(
+            server.join().unwrap(),
+            b"host:transport-any\nexec:uiautomator dump /dev/tty\n"
+        );
+    }
+
+    #[test]
+    fn test_transport_rejected() {
+        let (address, server) = fake_server(b"FAIL0014device unauthorized.", Vec::new());
+        let result = AdbClient::new(address).shell(None, "wm size");
+        assert!(
+            matches!(result, Err(ClientError::Rejected(ref message)) if message == "device unauthorized.")
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_server_unavailable() {
+        // Bind and drop a listener to get a port nobody listens on
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let result = AdbClient::new(address).exec(None, "true");
+        assert!(matches!(result, Err(ClientError::Unavailable(_))));
+    }
+}
@@ -1,18 +1,202 @@
+use crate::adb_client::{AdbClient, ClientError};
+use crate::error::BochiError;
+use crate::session::shell_join;
+use std::env;
 use std::io;
-use std::process::Command;
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
 
-pub fn get_adb_command(serial: Option<&str>) -> io::Result<Command> {
+/// Result of a command run on the device
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AdbOutput {
+    pub success: bool,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Run `adb shell <args>`. Each argument is quoted, since adb hands the device
+/// shell a single command line whether it is run natively or as a process.
+pub fn adb_shell(serial: Option<&str>, args: &[&str]) -> Result<AdbOutput, BochiError> {
+    let command = shell_join(args);
+    run_native(serial, |client, serial| client.shell(serial, &command))
+        .unwrap_or_else(|| run_subprocess(serial, &["shell", &command]))
+}
+
+/// Run `adb exec-out <args>`, which returns stdout without terminal translation
+pub fn adb_exec_out(serial: Option<&str>, args: &[&str]) -> Result<AdbOutput, BochiError> {
+    let command = shell_join(args);
+    run_native(serial, |client, serial| client.exec(serial, &command))
+        .unwrap_or_else(|| run_subprocess(serial, &["exec-out", &command]))
+}
+
+/// Read a file from the device
+pub fn adb_read_file(serial: Option<&str>, path: &str) -> Result<AdbOutput, BochiError> {
+    run_native(serial, |client, serial| client.pull(serial, path))
+        .unwrap_or_else(|| run_subprocess(serial, &["shell", "cat", path]))
+}
+
+/// A `sh` running on the device, with its stdin and stdout (and stderr for a process)
+pub enum ShellChannel {
+    /// An `exec:sh` connection to the adb server
+    Socket(TcpStream),
+    /// An `adb shell sh` process
+    Process(Child),
+}
+
+/// Start a `sh` on the device that reads commands from its stdin
+pub fn adb_open_shell(serial: Option<&str>) -> Result<ShellChannel, BochiError> {
+    if let Some((client, serial)) = native_client(serial) {
+        match client.exec_stream(serial.as_deref(), "sh") {
+            Ok(stream) => return Ok(ShellChannel::Socket(stream)),
+            Err(ClientError::Rejected(message)) => {
+                return Err(adb_failure(message.as_bytes(), |msg| {
+                    BochiError::Other(format!("Failed to start adb shell: {}", msg))
+                }))
+            }
+            Err(e @ ClientError::Io(_)) => return Err(BochiError::Other(e.to_string())),
+            Err(ClientError::Unavailable(_)) | Err(ClientError::Unsupported(_)) => {}
+        }
+    }
+    // Without a terminal on stdin, adb runs the shell without a pty
+    let child = get_adb_command(serial)
+        .args(["shell", "sh"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(adb_spawn_error)?;
+    Ok(ShellChannel::Process(child))
+}
+
+/// The adb server client and the serial to use with it, or None when
+/// `BOCHI_ADB=subprocess` asks for the adb executable
+fn native_client(serial: Option<&str>) -> Option<(AdbClient, Option<String>)> {
+    if env::var("BOCHI_ADB").is_ok_and(|backend| backend == "subprocess") {
+        return None;
+    }
+    let serial = serial
+        .map(str::to_string)
+        .or_else(|| env::var("ANDROID_SERIAL").ok());
+    Some((AdbClient::from_env(), serial))
+}
+
+/// Run a command through the adb server socket.
+/// Returns None when it should be run with the adb executable instead: the server is not
+/// running (adb starts it), the device lacks the service, or `BOCHI_ADB=subprocess` is set.
+fn run_native(
+    serial: Option<&str>,
+    run: impl FnOnce(&AdbClient, Option<&str>) -> Result<AdbOutput, ClientError>,
+) -> Option<Result<AdbOutput, BochiError>> {
+    let (client, serial) = native_client(serial)?;
+    match run(&client, serial.as_deref()) {
+        Ok(output) => Some(Ok(output)),
+        Err(ClientError::Rejected(message)) => Some(Ok(AdbOutput {
+            success: false,
+            stdout: Vec::new(),
+            stderr: message.into_bytes(),
+        })),
+        Err(ClientError::Unavailable(_)) | Err(ClientError::Unsupported(_)) => None,
+        Err(e @ ClientError::Io(_)) => Some(Err(BochiError::Other(e.to_string()))),
+    }
+}
+
+fn run_subprocess(serial: Option<&str>, args: &[&str]) -> Result<AdbOutput, BochiError> {
+    let output = get_adb_command(serial)
+        .args(args)
+        .output()
+        .map_err(adb_spawn_error)?;
+    Ok(AdbOutput {
+        success: output.status.success(),
+        stdout: output.stdout,
+        stderr: output.stderr,
+    })
+}
+
+fn get_adb_command(serial: Option<&str>) -> Command {
     let mut cmd = Command::new("adb");
     if let Some(s) = serial {
         cmd.arg("-s").arg(s);
     }
-    Ok(cmd)
+    cmd
 }
 
-pub fn format_adb_error(e: &io::Error) -> String {
+/// Map a failure to start adb to an error
+fn adb_spawn_error(e: io::Error) -> BochiError {
     if e.kind() == io::ErrorKind::NotFound {
-        "adb is not available in the $PATH directories".to_string()
+        BochiError::AdbNotFound
     } else {
-        format!("Failed to execute adb: {}", e)
+        BochiError::Other(format!("Failed to execute adb: {}", e))
+    }
+}
+
+/// Map the stderr of a failed adb invocation to an error.
+/// Device connection problems are recognized from adb's messages;
+/// any other failure is built by `fallback` from the stderr text.
+pub fn adb_failure(stderr: &[u8], fallback: impl FnOnce(String) -> BochiError) -> BochiError {
+    let message = String::from_utf8_lossy(stderr).trim().to_string();
+    let lower = message.to_lowercase();
+    if lower.contains("unauthorized") {
+        BochiError::DeviceUnauthorized(message)
+    } else if lower.contains("device offline")
+        || lower.contains("no devices/emulators found")
+        || (lower.contains("device '") && lower.contains("not found"))
+    {
+        BochiError::DeviceOffline(message)
+    } else {
+        fallback(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_command_keeps_arguments() {
+        // The native and the subprocess backends both send this line to the device shell
+        let args = ["printf", "%s|", "don't", "a;b", "$HOME", "two words", ""];
+        let output = Command::new("sh")
+            .args(["-c", &shell_join(&args)])
+            .output()
+            .unwrap();
+        assert_eq!(output.stdout, b"don't|a;b|$HOME|two words||");
+    }
+
+    #[test]
+    fn test_adb_spawn_error_not_found() {
+        let e = io::Error::new(io::ErrorKind::NotFound, "not found");
+        assert_eq!(adb_spawn_error(e), BochiError::AdbNotFound);
+    }
+
+    #[test]
+    fn test_adb_failure_device_states() {
+        let fallback = |msg: String| BochiError::InputFailed(msg);
+        assert!(matches!(
+            adb_failure(b"error: device unauthorized.\n", fallback),
+            BochiError::DeviceUnauthorized(_)
+        ));
+        assert!(matches!(
+            adb_failure(b"error: device offline\n", fallback),
+            BochiError::DeviceOffline(_)
+        ));
+        assert!(matches!(
+            adb_failure(b"error: no devices/emulators found\n", fallback),
+            BochiError::DeviceOffline(_)
+        ));
+        assert!(matches!(
+            adb_failure(b"error: device 'emulator-5556' not found\n", fallback),
+            BochiError::DeviceOffline(_)
+        ));
+    }
+
+    #[test]
+    fn test_adb_failure_fallback() {
+        let error = adb_failure(b"Error: Unknown command: foo\n", |msg| {
+            BochiError::InputFailed(format!("Tap command failed: {}", msg))
+        });
+        assert_eq!(
+            error,
+            BochiError::InputFailed("Tap command failed: Error: Unknown command: foo".to_string())
+        );
     }
 }
@@ -0,0 +1,331 @@
+use crate::adb_utils::{adb_exec_out, adb_failure, adb_shell};
+use crate::error::BochiError;
+use crate::ui_element::get_ui_hierarchy;
+use std::cell::{Cell, RefCell};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The device operations bochi's commands are built on.
+///
+/// `AdbDevice` talks to a real device; `FakeDevice` serves XML fixtures and records
+/// the injected input so the polling and scrolling logic can be tested without one.
+pub trait DeviceBackend {
+    /// Fetch the current UI hierarchy XML
+    fn ui_hierarchy(&self) -> Result<String, BochiError>;
+
+    /// Tap a screen position
+    fn tap(&self, x: i32, y: i32) -> Result<(), BochiError>;
+
+    /// Swipe from one position to another; the same start and end position is a long press
+    fn swipe(&self, x1: i32, y1: i32, x2: i32, y2: i32, duration_ms: u64)
+        -> Result<(), BochiError>;
+
+    /// Type text into the focused element
+    fn input_text(&self, text: &str) -> Result<(), BochiError>;
+
+    /// Screen dimensions (width, height)
+    fn screen_size(&self) -> Result<(i32, i32), BochiError>;
+
+    /// Run a shell command and return its stdout
+    fn shell(&self, args: &[&str]) -> Result<String, BochiError>;
+
+    /// Capture the screen as PNG
+    fn screenshot(&self) -> Result<Vec<u8>, BochiError>;
+
+    /// Current time of the clock timeouts are measured with
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// Wait between polls and input events
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// A device reached through adb
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AdbDevice {
+    serial: Option<String>,
+}
+
+impl AdbDevice {
+    /// The device with the given serial, or the only connected one when None
+    pub fn new(serial: Option<&str>) -> AdbDevice {
+        AdbDevice {
+            serial: serial.map(str::to_string),
+        }
+    }
+
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    /// Run an `input` subcommand, mapping a failure to `InputFailed` with `name`
+    fn input(&self, args: &[&str], name: &str) -> Result<(), BochiError> {
+        let output = adb_shell(self.serial(), &[&["input"], args].concat())?;
+
+        if !output.success {
+            return Err(adb_failure(&output.stderr, |msg| {
+                BochiError::InputFailed(format!("{} command failed: {}", name, msg))
+            }));
+        }
+
+        Ok(())
+    }
+}
+
+impl DeviceBackend for AdbDevice {
+    fn ui_hierarchy(&self) -> Result<String, BochiError> {
+        get_ui_hierarchy(self.serial())
+    }
+
+    fn tap(&self, x: i32, y: i32) -> Result<(), BochiError> {
+        self.input(&["tap", &x.to_string(), &y.to_string()], "Tap")
+    }
+
+    fn swipe(
+        &self,
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+        duration_ms: u64,
+    ) -> Result<(), BochiError> {
+        self.input(
+            &[
+                "swipe",
+                &x1.to_string(),
+                &y1.to_string(),
+                &x2.to_string(),
+                &y2.to_string(),
+                &duration_ms.to_string(),
+            ],
+            "Swipe",
+        )
+    }
+
+    fn input_text(&self, text: &str) -> Result<(), BochiError> {
+        self.input(&["text", text], "Input text")
+    }
+
+    fn screen_size(&self) -> Result<(i32, i32), BochiError> {
+        let output = self.shell(&["wm", "size"]).map_err(|e| match e {
+            BochiError::Other(msg) => {
+                BochiError::Other(format!("Failed to get screen size: {}", msg))
+            }
+            e => e,
+        })?;
+        parse_screen_size(&output).ok_or_else(|| {
+            BochiError::Other(format!("Could not parse screen size from: {}", output))
+        })
+    }
+
+    fn shell(&self, args: &[&str]) -> Result<String, BochiError> {
+        let output = adb_shell(self.serial(), args)?;
+
+        if !output.success {
+            return Err(adb_failure(&output.stderr, BochiError::Other));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn screenshot(&self) -> Result<Vec<u8>, BochiError> {
+        screencap(self.serial())
+    }
+}
+
+/// Capture the screen with `screencap -p`, streamed back through `adb exec-out`
+pub(crate) fn screencap(serial: Option<&str>) -> Result<Vec<u8>, BochiError> {
+    let output = adb_exec_out(serial, &["screencap", "-p"])?;
+
+    if !output.success {
+        return Err(adb_failure(&output.stderr, |msg| {
+            BochiError::Other(format!("screencap failed: {}", msg))
+        }));
+    }
+
+    Ok(output.stdout)
+}
+
+/// Parse the output of `wm size`: "Physical size: 1080x1920", possibly followed by
+/// "Override size: ..."; the first parsable size wins
+pub(crate) fn parse_screen_size(output: &str) -> Option<(i32, i32)> {
+    output.lines().find_map(|line| {
+        let (_, size) = line.split_once("size: ")?;
+        let (width, height) = size.split_once('x')?;
+        Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+    })
+}
+
+/// Input recorded by `FakeDevice`
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceEvent {
+    Tap(i32, i32),
+    Swipe {
+        from: (i32, i32),
+        to: (i32, i32),
+        duration_ms: u64,
+    },
+    Text(String),
+    Shell(String),
+    Screenshot,
+}
+
+/// In-memory device for tests.
+///
+/// Each hierarchy fetch serves the next XML fixture and the last one is repeated once
+/// the sequence is exhausted. Input is recorded instead of injected, and `sleep`
+/// advances a fake clock so timeouts expire without waiting.
+#[derive(Debug)]
+pub struct FakeDevice {
+    screens: Vec<String>,
+    fetches: Cell<usize>,
+    screen_size: (i32, i32),
+    events: RefCell<Vec<DeviceEvent>>,
+    start: Instant,
+    elapsed: Cell<Duration>,
+}
+
+impl FakeDevice {
+    /// A 1080x1920 device showing the given hierarchies in order
+    pub fn new<S: Into<String>>(screens: impl IntoIterator<Item = S>) -> FakeDevice {
+        FakeDevice {
+            screens: screens.into_iter().map(Into::into).collect(),
+            fetches: Cell::new(0),
+            screen_size: (1080, 1920),
+            events: RefCell::new(Vec::new()),
+            start: Instant::now(),
+            elapsed: Cell::new(Duration::ZERO),
+        }
+    }
+
+    pub fn with_screen_size(mut self, width: i32, height: i32) -> FakeDevice {
+        self.screen_size = (width, height);
+        self
+    }
+
+    /// Input received so far, oldest first
+    pub fn events(&self) -> Vec<DeviceEvent> {
+        self.events.borrow().clone()
+    }
+
+    /// Number of hierarchy fetches so far
+    pub fn fetches(&self) -> usize {
+        self.fetches.get()
+    }
+
+    /// Time spent in `sleep` so far
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed.get()
+    }
+
+    fn record(&self, event: DeviceEvent) {
+        self.events.borrow_mut().push(event);
+    }
+}
+
+impl DeviceBackend for FakeDevice {
+    fn ui_hierarchy(&self) -> Result<String, BochiError> {
+        let fetch = self.fetches.get();
+        self.fetches.set(fetch + 1);
+        self.screens
+            .get(fetch.min(self.screens.len().saturating_sub(1)))
+            .cloned()
+            .ok_or_else(|| BochiError::DumpFailed("FakeDevice has no screens".to_string()))
+    }
+
+    fn tap(&self, x: i32, y: i32) -> Result<(), BochiError> {
+        self.record(DeviceEvent::Tap(x, y));
+        Ok(())
+    }
+
+    fn swipe(
+        &self,
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+        duration_ms: u64,
+    ) -> Result<(), BochiError> {
+        self.record(DeviceEvent::Swipe {
+            from: (x1, y1),
+            to: (x2, y2),
+            duration_ms,
+        });
+        Ok(())
+    }
+
+    fn input_text(&self, text: &str) -> Result<(), BochiError> {
+        self.record(DeviceEvent::Text(text.to_string()));
+        Ok(())
+    }
+
+    fn screen_size(&self) -> Result<(i32, i32), BochiError> {
+        Ok(self.screen_size)
+    }
+
+    fn shell(&self, args: &[&str]) -> Result<String, BochiError> {
+        self.record(DeviceEvent::Shell(args.join(" ")));
+        Ok(String::new())
+    }
+
+    /// Records the request and returns just the PNG signature
+    fn screenshot(&self) -> Result<Vec<u8>, BochiError> {
+        self.record(DeviceEvent::Screenshot);
+        Ok(b"\x89PNG\r\n\x1a\n".to_vec())
+    }
+
+    fn now(&self) -> Instant {
+        self.start + self.elapsed.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.elapsed.set(self.elapsed.get() + duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_screen_size() {
+        assert_eq!(
+            parse_screen_size("Physical size: 1080x1920\n"),
+            Some((1080, 1920))
+        );
+        assert_eq!(
+            parse_screen_size("Physical size: 1440x3120\nOverride size: 1080x2340\n"),
+            Some((1440, 3120))
+        );
+        assert_eq!(parse_screen_size("error: no devices\n"), None);
+    }
+
+    #[test]
+    fn test_fake_device_serves_screens_in_order() {
+        let device = FakeDevice::new(["<a/>", "<b/>"]);
+        assert_eq!(device.ui_hierarchy().unwrap(), "<a/>");
+        assert_eq!(device.ui_hierarchy().unwrap(), "<b/>");
+        // The last screen stays on display
+        assert_eq!(device.ui_hierarchy().unwrap(), "<b/>");
+        assert_eq!(device.fetches(), 3);
+
+        let empty = FakeDevice::new(Vec::<String>::new());
+        assert!(matches!(
+            empty.ui_hierarchy(),
+            Err(BochiError::DumpFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_fake_device_clock() {
+        let device = FakeDevice::new(["<a/>"]);
+        let start = device.now();
+        device.sleep(Duration::from_millis(500));
+        device.sleep(Duration::from_millis(500));
+        assert_eq!(device.now() - start, Duration::from_secs(1));
+        assert_eq!(device.elapsed(), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,539 @@
+use crate::backend::DeviceBackend;
+use crate::error::BochiError;
+use crate::selector::Selector;
+use crate::suggest::{suggest, Suggestion, SUGGESTION_LIMIT};
+use crate::ui_element::{
+    find_elements, find_elements_with_descendants, is_element_visible, UiElement,
+};
+use std::time::Duration;
+
+/// Press duration of the longTap command
+pub const LONG_TAP_DURATION_MS: u64 = 1000;
+
+/// Tap the center of the element
+pub fn tap_element(device: &dyn DeviceBackend, element: &UiElement) -> Result<(), BochiError> {
+    let (center_x, center_y) = element.center();
+    device.tap(center_x, center_y)
+}
+
+/// Press the center of the element for `duration_ms` milliseconds
+pub fn long_tap_element(
+    device: &dyn DeviceBackend,
+    element: &UiElement,
+    duration_ms: u64,
+) -> Result<(), BochiError> {
+    let (center_x, center_y) = element.center();
+
+    // Use swipe with same start and end position to simulate a long press
+    device.swipe(center_x, center_y, center_x, center_y, duration_ms)
+}
+
+/// A position on the screen: a point, or the center of an element
+pub trait ScreenPoint {
+    fn screen_point(&self) -> (i32, i32);
+}
+
+impl ScreenPoint for (i32, i32) {
+    fn screen_point(&self) -> (i32, i32) {
+        *self
+    }
+}
+
+impl ScreenPoint for UiElement {
+    fn screen_point(&self) -> (i32, i32) {
+        self.center()
+    }
+}
+
+impl<T: ScreenPoint + ?Sized> ScreenPoint for &T {
+    fn screen_point(&self) -> (i32, i32) {
+        (**self).screen_point()
+    }
+}
+
+/// Swipe from one point or element center to another in `duration_ms` milliseconds
+///
+/// ```
+/// use bochi::{find_elements, swipe, DeviceEvent, FakeDevice, Selector};
+///
+/// # fn main() -> Result<(), bochi::BochiError> {
+/// let xml = r#"<hierarchy rotation="0"><node text="Item 1" bounds="[0,0][100,100]" /><node text="Trash" bounds="[0,900][100,1000]" /></hierarchy>"#;
+/// let device = FakeDevice::new([xml]);
+/// let item = &find_elements(xml, &Selector::parse("[text=\"Item 1\"]")?)?[0];
+/// let trash = &find_elements(xml, &Selector::parse("[text=Trash]")?)?[0];
+///
+/// // Drag the item onto the trash, then swipe up from the bottom of the screen
+/// swipe(&device, item, trash, 800)?;
+/// swipe(&device, (540, 1800), (540, 600), 300)?;
+/// assert_eq!(
+///     device.events()[0],
+///     DeviceEvent::Swipe { from: (50, 50), to: (50, 950), duration_ms: 800 }
+/// );
+/// # Ok(())
+/// # }
+/// ```
+pub fn swipe(
+    device: &dyn DeviceBackend,
+    from: impl ScreenPoint,
+    to: impl ScreenPoint,
+    duration_ms: u64,
+) -> Result<(), BochiError> {
+    let (x1, y1) = from.screen_point();
+    let (x2, y2) = to.screen_point();
+    device.swipe(x1, y1, x2, y2, duration_ms)
+}
+
+/// Tap the center of the element twice in quick succession
+pub fn double_tap_element(
+    device: &dyn DeviceBackend,
+    element: &UiElement,
+) -> Result<(), BochiError> {
+    // First tap
+    tap_element(device, element)?;
+
+    // Small delay between taps (typical double tap timing)
+    device.sleep(Duration::from_millis(100));
+
+    // Second tap
+    tap_element(device, element)
+}
+
+/// Scroll gradually until the target element is visible.
+/// Returns the visible target element and the number of swipes performed.
+pub fn scroll_until_visible(
+    device: &dyn DeviceBackend,
+    scroll_selector: &Selector,
+    target_selector: &Selector,
+    timeout_secs: u64,
+    scroll_up: bool,
+    scroll_index: usize,
+) -> Result<(UiElement, usize), BochiError> {
+    let start = device.now();
+    let timeout = Duration::from_secs(timeout_secs);
+
+    // Get screen dimensions
+    let (screen_width, screen_height) = device.screen_size()?;
+
+    // Calculate swipe parameters
+    // Swipe from 70% to 30% of screen height (or reverse for scroll up)
+    let start_y = if scroll_up {
+        screen_height * 3 / 10 // Start from 30% from top
+    } else {
+        screen_height * 7 / 10 // Start from 70% from top
+    };
+    let end_y = if scroll_up {
+        screen_height * 7 / 10 // End at 70% from top (swiping down)
+    } else {
+        screen_height * 3 / 10 // End at 30% from top (swiping up)
+    };
+    let _center_x = screen_width / 2;
+
+    // Swipe duration in ms - moderate speed for smooth scrolling
+    let swipe_duration = 300;
+    let mut swipes = 0;
+    let mut last_xml: Option<String> = None;
+
+    loop {
+        if device.now() - start > timeout {
+            return Err(BochiError::ElementTimeout(
+                format!(
+                    "Timeout waiting for target element to become visible: {}",
+                    target_selector
+                ),
+                timeout_suggestions(last_xml.as_deref(), target_selector),
+            ));
+        }
+
+        // Get current UI hierarchy
+        let xml: &str = last_xml.insert(device.ui_hierarchy()?);
+
+        // First, check if target is already visible
+        let target_elements = find_elements(xml, target_selector)?;
+        if let Some(target) = target_elements.into_iter().next() {
+            if is_element_visible(&target, screen_width, screen_height) {
+                return Ok((target, swipes));
+            }
+        }
+
+        // Find scrollable element (the element we swipe on)
+        let scroll_elements = find_elements(xml, scroll_selector)?;
+        let Some(scroll_element) = scroll_elements.get(scroll_index) else {
+            return Err(BochiError::ElementNotFound(format!(
+                "Scroll element not found with selector: {}{}",
+                scroll_selector,
+                index_note(scroll_index, scroll_elements.len())
+            )));
+        };
+
+        // Perform swipe on the selected scrollable element's center area
+        let (ex1, ey1, ex2, ey2) = scroll_element.bounds;
+        let swipe_x = (ex1 + ex2) / 2;
+
+        // Calculate swipe coordinates relative to the scrollable element
+        let actual_start_y = if scroll_up {
+            ey1 + screen_height / 5 // Start lower within the element
+        } else {
+            ey2 - screen_height / 5 // Start higher within the element
+        };
+        let actual_end_y = if scroll_up {
+            (ey2 - screen_height / 5).min(start_y + (end_y - start_y).abs())
+        } else {
+            (ey1 + screen_height / 5).max(start_y - (end_y - start_y).abs())
+        };
+
+        // Clamp coordinates to be within screen bounds
+        let actual_start_y = actual_start_y.max(0).min(screen_height);
+        let actual_end_y = actual_end_y.max(0).min(screen_height);
+
+        device.swipe(
+            swipe_x,
+            actual_start_y,
+            swipe_x,
+            actual_end_y,
+            swipe_duration,
+        )?;
+        swipes += 1;
+
+        // Small delay between swipes to let UI settle
+        device.sleep(Duration::from_millis(500));
+    }
+}
+
+/// Tap the element to focus it, then type the text
+pub fn input_text_element(
+    device: &dyn DeviceBackend,
+    element: &UiElement,
+    text: &str,
+) -> Result<(), BochiError> {
+    // First tap to focus on the element
+    tap_element(device, element)?;
+
+    // Small delay to ensure the element is focused
+    device.sleep(Duration::from_millis(100));
+
+    // Then type the text
+    device.input_text(text)
+}
+
+/// Wait for the `index`-th element (0-based) matching the selector.
+/// Returns the element and the number of UI hierarchy fetches it took.
+pub fn wait_for_element(
+    device: &dyn DeviceBackend,
+    selector: &Selector,
+    timeout_secs: u64,
+    index: usize,
+) -> Result<(UiElement, usize), BochiError> {
+    wait_for_elements(device, selector, timeout_secs, false, index + 1)
+        .map(|(elements, polls)| (elements.into_iter().nth(index).unwrap(), polls))
+}
+
+/// Poll the UI hierarchy until at least `min_count` elements match the selector.
+/// Returns all matched elements and the number of UI hierarchy fetches it took.
+pub fn wait_for_elements(
+    device: &dyn DeviceBackend,
+    selector: &Selector,
+    timeout_secs: u64,
+    with_descendants: bool,
+    min_count: usize,
+) -> Result<(Vec<UiElement>, usize), BochiError> {
+    let start = device.now();
+    let timeout = Duration::from_secs(timeout_secs);
+    let mut polls = 0;
+    let mut found = 0;
+    let mut last_xml: Option<String> = None;
+
+    loop {
+        if device.now() - start > timeout {
+            return Err(BochiError::ElementTimeout(
+                format!(
+                    "Timeout waiting for element with selector: {}{}",
+                    selector,
+                    index_note(min_count - 1, found)
+                ),
+                timeout_suggestions(last_xml.as_deref(), selector),
+            ));
+        }
+
+        let xml: &str = last_xml.insert(device.ui_hierarchy()?);
+        polls += 1;
+        let elements = if with_descendants {
+            find_elements_with_descendants(xml, selector)?
+        } else {
+            find_elements(xml, selector)?
+        };
+        if elements.len() >= min_count.max(1) {
+            return Ok((elements, polls));
+        }
+        found = elements.len();
+        device.sleep(Duration::from_millis(500));
+    }
+}
+
+/// Elements of the last fetched hierarchy resembling what the selector looks for
+fn timeout_suggestions(last_xml: Option<&str>, selector: &Selector) -> Vec<Suggestion> {
+    last_xml.map_or_else(Vec::new, |xml| suggest(xml, selector, SUGGESTION_LIMIT))
+}
+
+/// Explanation appended to "not found" errors when `--index` asks for more matches than exist
+pub fn index_note(index: usize, found: usize) -> String {
+    if index == 0 {
+        String::new()
+    } else {
+        format!(
+            " (--index {} needs {} matches, found {})",
+            index,
+            index + 1,
+            found
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{DeviceEvent, FakeDevice};
+
+    const LOADING: &str = r##"<?xml version="1.0" encoding="UTF-8"?><hierarchy rotation="0"><node class="android.widget.ProgressBar" bounds="[440,860][640,1060]" /></hierarchy>"##;
+    const READY: &str = r##"<?xml version="1.0" encoding="UTF-8"?><hierarchy rotation="0"><node class="android.widget.FrameLayout" bounds="[0,0][1080,1920]"><node class="android.widget.EditText" resource-id="com.example:id/name" bounds="[0,100][1080,200]" /><node class="android.widget.Button" text="Submit" bounds="[0,300][200,400]" /></node></hierarchy>"##;
+    const LIST_BEFORE: &str = r##"<?xml version="1.0" encoding="UTF-8"?><hierarchy rotation="0"><node class="androidx.recyclerview.widget.RecyclerView" scrollable="true" bounds="[0,200][1080,1800]"><node class="android.widget.TextView" text="Item 1" bounds="[0,200][1080,300]" /><node class="android.widget.TextView" text="Item 30" bounds="[0,2000][1080,2100]" /></node></hierarchy>"##;
+    const LIST_AFTER: &str = r##"<?xml version="1.0" encoding="UTF-8"?><hierarchy rotation="0"><node class="androidx.recyclerview.widget.RecyclerView" scrollable="true" bounds="[0,200][1080,1800]"><node class="android.widget.TextView" text="Item 30" bounds="[0,1000][1080,1100]" /></node></hierarchy>"##;
+
+    fn selector(s: &str) -> Selector {
+        Selector::parse(s).unwrap()
+    }
+
+    fn element(bounds: (i32, i32, i32, i32)) -> UiElement {
+        UiElement {
+            bounds,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_swipe_between_points_and_elements() {
+        let device = FakeDevice::new([READY]);
+        let from = element((0, 0, 100, 200));
+        swipe(&device, &from, (500, 600), 250).unwrap();
+        swipe(&device, (1, 2), element((10, 10, 30, 50)), 100).unwrap();
+        assert_eq!(
+            device.events(),
+            vec![
+                DeviceEvent::Swipe {
+                    from: (50, 100),
+                    to: (500, 600),
+                    duration_ms: 250
+                },
+                DeviceEvent::Swipe {
+                    from: (1, 2),
+                    to: (20, 30),
+                    duration_ms: 100
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wait_for_element_polls_until_present() {
+        let device = FakeDevice::new([LOADING, LOADING, READY]);
+        let (button, polls) =
+            wait_for_element(&device, &selector(r#"[text="Submit"]"#), 10, 0).unwrap();
+        assert_eq!(button.text, "Submit");
+        assert_eq!(polls, 3);
+        assert_eq!(device.elapsed(), Duration::from_secs(1));
+        assert!(device.events().is_empty());
+    }
+
+    #[test]
+    fn test_wait_for_element_timeout() {
+        let device = FakeDevice::new([READY]);
+        let error = wait_for_element(&device, &selector(r#"[text="Submitt"]"#), 2, 0).unwrap_err();
+        let BochiError::ElementTimeout(message, suggestions) = error else {
+            panic!("expected a timeout, got {:?}", error);
+        };
+        assert_eq!(
+            message,
+            r#"Timeout waiting for element with selector: [text="Submitt"]"#
+        );
+        assert_eq!(suggestions[0].selector, r#"[text="Submit"]"#);
+        // Polls at 0, 0.5, 1, 1.5 and 2 seconds; the timeout is noticed after the last sleep
+        assert_eq!(device.fetches(), 5);
+        assert_eq!(device.elapsed(), Duration::from_millis(2500));
+    }
+
+    #[test]
+    fn test_wait_for_elements_min_count() {
+        let device = FakeDevice::new([LOADING, READY]);
+        let (elements, polls) =
+            wait_for_elements(&device, &selector("[class$=Button]"), 5, false, 1).unwrap();
+        assert_eq!(elements.len(), 1);
+        assert_eq!(polls, 2);
+
+        let device = FakeDevice::new([READY]);
+        let error = wait_for_element(&device, &selector("[class$=Button]"), 0, 1).unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("(--index 1 needs 2 matches, found 1)"));
+    }
+
+    #[test]
+    fn test_wait_for_elements_with_descendants() {
+        let device = FakeDevice::new([READY]);
+        let (elements, _) =
+            wait_for_elements(&device, &selector("[class$=FrameLayout]"), 0, true, 1).unwrap();
+        assert!(elements[0].raw_xml.contains(r#"text="Submit""#));
+    }
+
+    #[test]
+    fn test_input_events() {
+        let device = FakeDevice::new([READY]);
+        let button = element((0, 300, 200, 400));
+        tap_element(&device, &button).unwrap();
+        long_tap_element(&device, &button, LONG_TAP_DURATION_MS).unwrap();
+        double_tap_element(&device, &button).unwrap();
+        input_text_element(&device, &button, "hello").unwrap();
+        assert_eq!(
+            device.events(),
+            vec![
+                DeviceEvent::Tap(100, 350),
+                DeviceEvent::Swipe {
+                    from: (100, 350),
+                    to: (100, 350),
+                    duration_ms: 1000
+                },
+                DeviceEvent::Tap(100, 350),
+                DeviceEvent::Tap(100, 350),
+                DeviceEvent::Tap(100, 350),
+                DeviceEvent::Text("hello".to_string()),
+            ]
+        );
+        assert_eq!(device.elapsed(), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_scroll_down_until_visible() {
+        let device = FakeDevice::new([LIST_BEFORE, LIST_AFTER]);
+        let (target, swipes) = scroll_until_visible(
+            &device,
+            &selector("[scrollable=true]"),
+            &selector(r#"[text="Item 30"]"#),
+            10,
+            false,
+            0,
+        )
+        .unwrap();
+        assert_eq!(target.bounds, (0, 1000, 1080, 1100));
+        assert_eq!(swipes, 1);
+        // Swipe up over the list: from 1/5 screen above its bottom to 1/5 below its top
+        assert_eq!(
+            device.events(),
+            vec![DeviceEvent::Swipe {
+                from: (540, 1416),
+                to: (540, 584),
+                duration_ms: 300
+            }]
+        );
+    }
+
+    #[test]
+    fn test_scroll_up_swipes_down() {
+        let device = FakeDevice::new([LIST_BEFORE, LIST_AFTER]);
+        scroll_until_visible(
+            &device,
+            &selector("[scrollable=true]"),
+            &selector(r#"[text="Item 30"]"#),
+            10,
+            true,
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            device.events(),
+            vec![DeviceEvent::Swipe {
+                from: (540, 584),
+                to: (540, 1344),
+                duration_ms: 300
+            }]
+        );
+    }
+
+    #[test]
+    fn test_scroll_target_already_visible() {
+        let device = FakeDevice::new([LIST_BEFORE]);
+        let (target, swipes) = scroll_until_visible(
+            &device,
+            &selector("[scrollable=true]"),
+            &selector(r#"[text="Item 1"]"#),
+            10,
+            false,
+            0,
+        )
+        .unwrap();
+        assert_eq!(target.text, "Item 1");
+        assert_eq!(swipes, 0);
+        assert!(device.events().is_empty());
+    }
+
+    #[test]
+    fn test_scroll_element_not_found() {
+        let device = FakeDevice::new([LIST_BEFORE]);
+        let error = scroll_until_visible(
+            &device,
+            &selector("[scrollable=true]"),
+            &selector(r#"[text="Item 99"]"#),
+            10,
+            false,
+            1,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            BochiError::ElementNotFound(
+                "Scroll element not found with selector: [scrollable=\"true\"] (--index 1 needs 2 matches, found 1)"
+                    .to_string()
+            )
+        );
+        assert!(device.events().is_empty());
+    }
+
+    #[test]
+    fn test_scroll_timeout() {
+        let device = FakeDevice::new([LIST_BEFORE]);
+        let error = scroll_until_visible(
+            &device,
+            &selector("[scrollable=true]"),
+            &selector(r#"[text="Item 99"]"#),
+            1,
+            false,
+            0,
+        )
+        .unwrap_err();
+        assert!(matches!(error, BochiError::ElementTimeout(..)));
+        // Swipes at 0, 0.5 and 1 seconds
+        assert_eq!(device.events().len(), 3);
+    }
+
+    #[test]
+    fn test_scroll_respects_screen_size() {
+        // On a 960 px high screen "Item 30" at y=1000 is still below the bottom edge
+        let device = FakeDevice::new([LIST_BEFORE, LIST_AFTER]).with_screen_size(1080, 960);
+        let error = scroll_until_visible(
+            &device,
+            &selector("[scrollable=true]"),
+            &selector(r#"[text="Item 30"]"#),
+            0,
+            false,
+            0,
+        )
+        .unwrap_err();
+        assert!(matches!(error, BochiError::ElementTimeout(..)));
+        // The start position is clamped to the screen
+        assert_eq!(
+            device.events(),
+            vec![DeviceEvent::Swipe {
+                from: (540, 960),
+                to: (540, 392),
+                duration_ms: 300
+            }]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use crate::error::BochiError;
+use crate::ui_element::{parse_bounds, parse_hierarchy};
+use roxmltree::Node;
+use serde_json::{json, Map, Value};
+
+/// Output format of the `dump` command
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq)]
+pub enum DumpFormat {
+    /// Raw uiautomator XML
+    #[value(name = "xml")]
+    Xml,
+    /// Indented human-readable tree, one element per line
+    #[value(name = "tree")]
+    Tree,
+    /// Nested JSON objects with parsed bounds
+    #[value(name = "json")]
+    Json,
+}
+
+/// Render the UI hierarchy XML in the requested format
+pub fn format_hierarchy(xml: &str, format: &DumpFormat) -> Result<String, BochiError> {
+    match format {
+        DumpFormat::Xml => Ok(xml.trim_end().to_string()),
+        DumpFormat::Tree => format_tree(xml),
+        DumpFormat::Json => format_json(xml),
+    }
+}
+
+/// Render the hierarchy as an indented tree.
+/// Each line shows class, resource-id, text, content-desc and bounds; empty attributes are omitted.
+fn format_tree(xml: &str) -> Result<String, BochiError> {
+    let doc = parse_hierarchy(xml)?;
+    let mut lines = Vec::new();
+    for child in doc.root_element().children().filter(|n| n.is_element()) {
+        collect_tree_lines(child, 0, &mut lines);
+    }
+    Ok(lines.join("\n"))
+}
+
+fn collect_tree_lines(node: Node, depth: usize, lines: &mut Vec<String>) {
+    let mut line = "  ".repeat(depth);
+    line.push_str(node.attribute("class").unwrap_or(node.tag_name().name()));
+
+    for (label, attr) in [
+        ("id", "resource-id"),
+        ("text", "text"),
+        ("desc", "content-desc"),
+    ] {
+        if let Some(value) = node.attribute(attr).filter(|v| !v.is_empty()) {
+            line.push_str(&format!(" {}={:?}", label, value));
+        }
+    }
+
+    if let Some(bounds) = node.attribute("bounds") {
+        line.push(' ');
+        line.push_str(bounds);
+    }
+    lines.push(line);
+
+    for child in node.children().filter(|n| n.is_element()) {
+        collect_tree_lines(child, depth + 1, lines);
+    }
+}
+
+/// Render the hierarchy as nested JSON objects.
+/// All XML attributes are kept as strings except `bounds`, which becomes `[x1, y1, x2, y2]`.
+fn format_json(xml: &str) -> Result<String, BochiError> {
+    serde_json::to_string_pretty(&hierarchy_to_json(xml)?)
+        .map_err(|e| BochiError::Other(format!("Failed to serialize JSON: {}", e)))
+}
+
+/// Convert the hierarchy XML into nested JSON objects
+pub fn hierarchy_to_json(xml: &str) -> Result<Value, BochiError> {
+    let doc = parse_hierarchy(xml)?;
+    Ok(node_to_json(doc.root_element()))
+}
+
+fn node_to_json(node: Node) -> Value {
+    let mut object = Map::new();
+    for attr in node.attributes() {
+        let value = match (attr.name(), parse_bounds(attr.value())) {
+            ("bounds", Some((x1, y1, x2, y2))) => json!([x1, y1, x2, y2]),
+            _ => Value::String(attr.value().to_string()),
+        };
+        object.insert(attr.name().to_string(), value);
+    }
+
+    let children: Vec<Value> = node
+        .children()
+        .filter(|n| n.is_element())
+        .map(node_to_json)
+        .collect();
+    if !children.is_empty() {
+        object.insert("children".to_string(), Value::Array(children));
+    }
+
+    Value::Object(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = r##"<?xml version="1.0" encoding="UTF-8"?><hierarchy rotation="0"><node class="android.widget.FrameLayout" text="" resource-id="" content-desc="" bounds="[0,0][1080,1920]"><node class="android.widget.Button" text="OK" resource-id="com.example:id/ok" content-desc="Confirm" bounds="[10,20][110,80]" /></node></hierarchy>"##;
+
+    #[test]
+    fn test_format_xml_is_unchanged() {
+        let result = format_hierarchy(XML, &DumpFormat::Xml).unwrap();
+        assert_eq!(result, XML);
+    }
+
+    #[test]
+    fn test_format_tree() {
+        let result = format_hierarchy(XML, &DumpFormat::Tree).unwrap();
+        assert_eq!(
+            result,
+            "android.widget.FrameLayout [0,0][1080,1920]\n  android.widget.Button id=\"com.example:id/ok\" text=\"OK\" desc=\"Confirm\" [10,20][110,80]"
+        );
+    }
+
+    #[test]
+    fn test_format_json() {
+        let result = format_hierarchy(XML, &DumpFormat::Json).unwrap();
+        let value: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(value["rotation"], "0");
+        let button = &value["children"][0]["children"][0];
+        assert_eq!(button["text"], "OK");
+        assert_eq!(button["bounds"], json!([10, 20, 110, 80]));
+        assert!(button.get("children").is_none());
+    }
+
+    #[test]
+    fn test_format_invalid_xml() {
+        assert!(format_hierarchy("<hierarchy>", &DumpFormat::Tree).is_err());
+        assert!(format_hierarchy("<hierarchy>", &DumpFormat::Json).is_err());
+    }
+}
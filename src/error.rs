@@ -0,0 +1,221 @@
+use crate::selector::SelectorError;
+use crate::suggest::{suggestion_lines, Suggestion};
+use serde_json::{json, Value};
+use std::fmt;
+
+/// Errors reported by bochi commands.
+/// Each variant maps to a distinct process exit code so that callers can tell failures apart.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BochiError {
+    /// Missing or conflicting command line parameters
+    InvalidArgument(String),
+    /// The selector string could not be parsed
+    SelectorParse(SelectorError),
+    /// No matching element appeared before the timeout.
+    /// Carries the closest elements of the last fetched hierarchy.
+    ElementTimeout(String, Vec<Suggestion>),
+    /// No matching element in a static hierarchy (e.g. `--xml-file`) or scroll container
+    ElementNotFound(String),
+    /// The adb executable is not in $PATH
+    AdbNotFound,
+    /// The device is offline, disconnected or not found
+    DeviceOffline(String),
+    /// The device has not authorized this host for debugging
+    DeviceUnauthorized(String),
+    /// uiautomator failed to produce a hierarchy dump
+    DumpFailed(String),
+    /// The hierarchy dump is not valid XML
+    XmlParse(String),
+    /// An `input` command was rejected by the device
+    InputFailed(String),
+    /// A flow assertion did not hold before the timeout
+    AssertionFailed(String),
+    /// Any other failure
+    Other(String),
+}
+
+impl BochiError {
+    /// Process exit code for this error. 0 is success and 2 matches clap's usage errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BochiError::Other(_) => 1,
+            BochiError::InvalidArgument(_) => 2,
+            BochiError::SelectorParse(_) => 3,
+            BochiError::ElementTimeout(..) => 4,
+            BochiError::ElementNotFound(_) => 5,
+            BochiError::AdbNotFound => 6,
+            BochiError::DeviceOffline(_) => 7,
+            BochiError::DeviceUnauthorized(_) => 8,
+            BochiError::DumpFailed(_) => 9,
+            BochiError::XmlParse(_) => 10,
+            BochiError::InputFailed(_) => 11,
+            BochiError::AssertionFailed(_) => 12,
+        }
+    }
+
+    /// Stable machine-readable name of the error kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BochiError::Other(_) => "other",
+            BochiError::InvalidArgument(_) => "invalid_argument",
+            BochiError::SelectorParse(_) => "selector_parse",
+            BochiError::ElementTimeout(..) => "element_timeout",
+            BochiError::ElementNotFound(_) => "element_not_found",
+            BochiError::AdbNotFound => "adb_not_found",
+            BochiError::DeviceOffline(_) => "device_offline",
+            BochiError::DeviceUnauthorized(_) => "device_unauthorized",
+            BochiError::DumpFailed(_) => "dump_failed",
+            BochiError::XmlParse(_) => "xml_parse",
+            BochiError::InputFailed(_) => "input_failed",
+            BochiError::AssertionFailed(_) => "assertion_failed",
+        }
+    }
+
+    /// JSON error body printed with `--output json`
+    pub fn to_json(&self, command: &str) -> Value {
+        let mut error = json!({
+            "kind": self.kind(),
+            "message": self.to_string(),
+            "exit_code": self.exit_code(),
+        });
+        match self {
+            BochiError::SelectorParse(e) => {
+                error["selector"] = json!(e.input);
+                error["position"] = json!(e.offset);
+                error["expected"] = json!(e.expected);
+            }
+            BochiError::ElementTimeout(msg, suggestions) => {
+                // Suggestions are listed as structured data instead of in the message
+                error["message"] = json!(msg);
+                error["suggestions"] = suggestions.iter().map(Suggestion::to_json).collect();
+            }
+            _ => {}
+        }
+        json!({
+            "command": command,
+            "success": false,
+            "error": error,
+        })
+    }
+}
+
+impl fmt::Display for BochiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BochiError::AdbNotFound => write!(f, "adb is not available in the $PATH directories"),
+            BochiError::SelectorParse(e) => write!(f, "Invalid selector: {}", e),
+            BochiError::ElementTimeout(msg, suggestions) => {
+                write!(f, "{}", msg)?;
+                for line in suggestion_lines(suggestions) {
+                    write!(f, "\n{}", line)?;
+                }
+                Ok(())
+            }
+            BochiError::InvalidArgument(msg)
+            | BochiError::ElementNotFound(msg)
+            | BochiError::DeviceOffline(msg)
+            | BochiError::DeviceUnauthorized(msg)
+            | BochiError::DumpFailed(msg)
+            | BochiError::XmlParse(msg)
+            | BochiError::InputFailed(msg)
+            | BochiError::AssertionFailed(msg)
+            | BochiError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for BochiError {}
+
+impl From<SelectorError> for BochiError {
+    fn from(e: SelectorError) -> Self {
+        BochiError::SelectorParse(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selector::Selector;
+
+    fn all_errors() -> Vec<BochiError> {
+        vec![
+            BochiError::InvalidArgument(String::new()),
+            BochiError::SelectorParse(Selector::parse("").unwrap_err()),
+            BochiError::ElementTimeout(String::new(), Vec::new()),
+            BochiError::ElementNotFound(String::new()),
+            BochiError::AdbNotFound,
+            BochiError::DeviceOffline(String::new()),
+            BochiError::DeviceUnauthorized(String::new()),
+            BochiError::DumpFailed(String::new()),
+            BochiError::XmlParse(String::new()),
+            BochiError::InputFailed(String::new()),
+            BochiError::AssertionFailed(String::new()),
+            BochiError::Other(String::new()),
+        ]
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let mut codes: Vec<i32> = all_errors().iter().map(|e| e.exit_code()).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), all_errors().len());
+        assert!(!codes.contains(&0));
+    }
+
+    #[test]
+    fn test_kinds_are_distinct() {
+        let mut kinds: Vec<&str> = all_errors().iter().map(|e| e.kind()).collect();
+        kinds.sort();
+        kinds.dedup();
+        assert_eq!(kinds.len(), all_errors().len());
+    }
+
+    #[test]
+    fn test_error_json() {
+        let error =
+            BochiError::ElementTimeout("Timeout waiting for element".to_string(), Vec::new());
+        let value = error.to_json("tap");
+        assert_eq!(value["command"], "tap");
+        assert_eq!(value["success"], false);
+        assert_eq!(value["error"]["kind"], "element_timeout");
+        assert_eq!(value["error"]["message"], "Timeout waiting for element");
+        assert_eq!(value["error"]["exit_code"], 4);
+    }
+
+    #[test]
+    fn test_timeout_suggestions() {
+        let suggestion = Suggestion {
+            selector: r#"[text="Cancel"]"#.to_string(),
+            xml: r#"<node text="Cancel" />"#.to_string(),
+            score: 0.8,
+        };
+        let error = BochiError::ElementTimeout("Timeout".to_string(), vec![suggestion]);
+        assert_eq!(
+            error.to_string(),
+            "Timeout\nDid you mean:\n  [text=\"Cancel\"]  <node text=\"Cancel\" />"
+        );
+        let value = error.to_json("tap");
+        assert_eq!(value["error"]["message"], "Timeout");
+        assert_eq!(
+            value["error"]["suggestions"][0]["selector"],
+            r#"[text="Cancel"]"#
+        );
+        assert_eq!(value["error"]["suggestions"][0]["score"], 0.8);
+    }
+
+    #[test]
+    fn test_selector_error_json() {
+        let error = BochiError::SelectorParse(Selector::parse("[text").unwrap_err());
+        let value = error.to_json("tap");
+        assert_eq!(value["error"]["kind"], "selector_parse");
+        assert_eq!(value["error"]["exit_code"], 3);
+        assert_eq!(value["error"]["selector"], "[text");
+        assert_eq!(value["error"]["position"], 5);
+        assert_eq!(value["error"]["expected"], "operator");
+        assert!(value["error"]["message"]
+            .as_str()
+            .unwrap()
+            .ends_with("\n  [text\n       ^"));
+    }
+}
@@ -0,0 +1,473 @@
+use crate::error::BochiError;
+use crate::report::element_to_json;
+use crate::selector::{AttrClause, PseudoClass, Selector};
+use crate::suggest::suggest;
+use crate::ui_element::{node_to_xml_string, parse_hierarchy, UiElement, UiTree};
+use roxmltree::Node;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Maximum number of matched and of near-miss elements listed by `explain`
+pub const EXPLAIN_LIMIT: usize = 5;
+
+/// One step of a selector evaluated against a node
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    /// The selector part, e.g. `[text="Submit"]` or `parent matches [class="List"]`
+    pub step: String,
+    pub passed: bool,
+    /// Why the step failed, e.g. `text is "Submt"`
+    pub detail: Option<String>,
+}
+
+impl Check {
+    fn new(step: String, passed: bool, detail: impl FnOnce() -> String) -> Check {
+        Check {
+            step,
+            passed,
+            detail: if passed { None } else { Some(detail()) },
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "step": self.step,
+            "passed": self.passed,
+            "detail": self.detail,
+        })
+    }
+}
+
+/// An element of the hierarchy with the result of every selector step
+#[derive(Debug)]
+pub struct Candidate {
+    pub element: UiElement,
+    pub checks: Vec<Check>,
+}
+
+impl Candidate {
+    pub fn matched(&self) -> bool {
+        self.checks.iter().all(|c| c.passed)
+    }
+
+    fn failed(&self) -> usize {
+        self.checks.iter().filter(|c| !c.passed).count()
+    }
+}
+
+/// Result of the `explain` command
+#[derive(Debug)]
+pub struct Explanation {
+    pub selector: String,
+    /// Number of elements matching the selector
+    pub matches: usize,
+    /// Matched elements followed by the closest near misses, each list capped at `EXPLAIN_LIMIT`
+    pub candidates: Vec<Candidate>,
+    /// The near misses were picked by the similarity of their text, content-desc or
+    /// resource-id, because no element passes any step of the selector
+    pub by_similarity: bool,
+    pub elapsed: Duration,
+}
+
+impl Explanation {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "command": "explain",
+            "success": true,
+            "selector": self.selector,
+            "matches": self.matches,
+            "candidates": self.candidates.iter().map(|c| json!({
+                "element": element_to_json(&c.element),
+                "matched": c.matched(),
+                "checks": c.checks.iter().map(Check::to_json).collect::<Vec<_>>(),
+            })).collect::<Vec<_>>(),
+            "by_similarity": self.by_similarity,
+            "elapsed_ms": self.elapsed.as_millis() as u64,
+            "polls": 1,
+        })
+    }
+
+    pub fn text_lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("Selector: {}", self.selector),
+            format!("{} element(s) match", self.matches),
+        ];
+        let near_misses = self.candidates.iter().filter(|c| !c.matched()).count();
+        if self.matches == 0 && (near_misses == 0 || self.by_similarity) {
+            lines.push("No element passes any step of the selector".to_string());
+        }
+        for (i, candidate) in self.candidates.iter().enumerate() {
+            if i == 0 && candidate.matched() {
+                lines.push(String::new());
+                lines.push("Matched:".to_string());
+            }
+            if !candidate.matched() && (i == 0 || self.candidates[i - 1].matched()) {
+                lines.push(String::new());
+                lines.push(if self.by_similarity {
+                    "Elements with similar text, content-desc or resource-id:".to_string()
+                } else {
+                    "Closest elements that do not match:".to_string()
+                });
+            }
+            lines.push(candidate.element.raw_xml.clone());
+            for check in &candidate.checks {
+                match &check.detail {
+                    Some(detail) => lines.push(format!("  FAIL {}: {}", check.step, detail)),
+                    None => lines.push(format!("  pass {}", check.step)),
+                }
+            }
+        }
+        lines
+    }
+}
+
+/// Evaluate the selector against every element of the hierarchy and keep the matches
+/// and the elements that fail the fewest steps
+pub fn explain(xml: &str, selector: &Selector, limit: usize) -> Result<Explanation, BochiError> {
+    let doc = parse_hierarchy(xml)?;
+    let tree = UiTree::from_document(&doc);
+    let mut matched = Vec::new();
+    let mut near_misses = Vec::new();
+
+    let subject = subject(selector);
+    for node in doc.descendants().filter(|n| n.is_element()) {
+        let Some(element) = tree.element(node).cloned() else {
+            continue;
+        };
+        let candidate = Candidate {
+            element,
+            checks: checks(selector, node),
+        };
+        if candidate.matched() {
+            matched.push(candidate);
+        } else if checks(subject, node).iter().any(|c| c.passed) {
+            // Only elements that look like the target itself are near misses,
+            // not every element that merely sits in the right place
+            near_misses.push(candidate);
+        }
+    }
+
+    let matches = matched.len();
+    // Stable sort: equally close elements stay in document order
+    near_misses.sort_by_key(|c| c.failed());
+
+    // Nothing resembles the target step by step, e.g. a typo in the only clause:
+    // fall back to the elements whose values are closest to the wanted ones
+    let by_similarity = matches == 0 && near_misses.is_empty();
+    if by_similarity {
+        let mut nodes: Vec<Node> = doc.descendants().filter(|n| n.is_element()).collect();
+        for suggestion in suggest(xml, selector, limit) {
+            let Some(i) = nodes
+                .iter()
+                .position(|n| node_to_xml_string(*n) == suggestion.xml)
+            else {
+                continue;
+            };
+            let node = nodes.remove(i);
+            if let Some(element) = tree.element(node).cloned() {
+                near_misses.push(Candidate {
+                    element,
+                    checks: checks(selector, node),
+                });
+            }
+        }
+    }
+    let mut candidates: Vec<Candidate> = matched.into_iter().take(limit).collect();
+    candidates.extend(near_misses.into_iter().take(limit));
+
+    Ok(Explanation {
+        selector: selector.to_string(),
+        matches,
+        candidates,
+        by_similarity,
+        elapsed: Duration::ZERO,
+    })
+}
+
+/// The compound selector that the matched elements must satisfy themselves,
+/// i.e. the rightmost part of a combinator chain
+fn subject(selector: &Selector) -> &Selector {
+    match selector {
+        Selector::Child { child, .. } => subject(child),
+        Selector::Descendant { descendant, .. } => subject(descendant),
+        Selector::AdjacentSibling { next, .. } | Selector::GeneralSibling { next, .. } => {
+            subject(next)
+        }
+        _ => selector,
+    }
+}
+
+/// Evaluate each step of the selector against the node.
+/// For combinators, the steps of the rightmost compound come first, followed by one
+/// step for the relation to the rest of the selector.
+fn checks(selector: &Selector, node: Node) -> Vec<Check> {
+    match selector {
+        Selector::And(clauses) => clauses.iter().map(|c| clause_check(c, node)).collect(),
+        Selector::Or(alternatives) => alternatives
+            .iter()
+            .map(|alternative| checks(alternative, node))
+            .min_by_key(|checks| {
+                let failed = checks.iter().filter(|c| !c.passed).count();
+                (failed, usize::MAX - checks.len())
+            })
+            .unwrap_or_default(),
+        Selector::Complex { attrs, pseudos } => {
+            let mut result: Vec<Check> = attrs.iter().map(|c| clause_check(c, node)).collect();
+            for pseudo in pseudos {
+                let step = pseudo.to_string();
+                let check = match pseudo {
+                    // The position among matches depends on the whole compound
+                    PseudoClass::NthMatch(n) => Check::new(step, selector.matches(node), || {
+                        format!("is not match #{} of the rest of the compound", n)
+                    }),
+                    _ => Check::new(step, pseudo.matches(node, &|_| true), || {
+                        pseudo_failure(pseudo, node)
+                    }),
+                };
+                result.push(check);
+            }
+            result
+        }
+        Selector::Child { parent, child } => {
+            let mut result = checks(child, node);
+            let parent_node = node.parent_element();
+            result.push(Check::new(
+                format!("parent matches {}", parent),
+                parent_node.is_some_and(|p| parent.matches(p)),
+                || match parent_node {
+                    Some(p) => format!("parent {}", failure_summary(parent, p)),
+                    None => "element has no parent".to_string(),
+                },
+            ));
+            result
+        }
+        Selector::Descendant {
+            ancestor,
+            descendant,
+        } => {
+            let mut result = checks(descendant, node);
+            result.push(Check::new(
+                format!("an ancestor matches {}", ancestor),
+                node.ancestors().skip(1).any(|a| ancestor.matches(a)),
+                || "no ancestor matches".to_string(),
+            ));
+            result
+        }
+        Selector::AdjacentSibling { prev, next } => {
+            let mut result = checks(next, node);
+            let sibling = node.prev_sibling_element();
+            result.push(Check::new(
+                format!("previous sibling matches {}", prev),
+                sibling.is_some_and(|s| prev.matches(s)),
+                || match sibling {
+                    Some(s) => format!("previous sibling {}", failure_summary(prev, s)),
+                    None => "element has no previous sibling".to_string(),
+                },
+            ));
+            result
+        }
+        Selector::GeneralSibling { prev, next } => {
+            let mut result = checks(next, node);
+            result.push(Check::new(
+                format!("an earlier sibling matches {}", prev),
+                node.prev_siblings()
+                    .skip(1)
+                    .any(|s| s.is_element() && prev.matches(s)),
+                || "no earlier sibling matches".to_string(),
+            ));
+            result
+        }
+    }
+}
+
+/// Short description of the steps a related node (parent, sibling) fails
+fn failure_summary(selector: &Selector, node: Node) -> String {
+    let failures: Vec<String> = checks(selector, node)
+        .into_iter()
+        .filter(|c| !c.passed)
+        .map(|c| match c.detail {
+            Some(detail) => format!("{} ({})", c.step, detail),
+            None => c.step,
+        })
+        .collect();
+    format!(
+        "<{}> fails {}",
+        node.attribute("class").unwrap_or(node.tag_name().name()),
+        failures.join(", ")
+    )
+}
+
+fn clause_check(clause: &AttrClause, node: Node) -> Check {
+    Check::new(clause.to_string(), clause.matches(node), || {
+        match clause.attribute_value(node) {
+            Some(value) => format!("{} is {:?}", clause.attr, value),
+            None => format!("{} is missing", clause.attr),
+        }
+    })
+}
+
+fn pseudo_failure(pseudo: &PseudoClass, node: Node) -> String {
+    let position = || {
+        let index = node.prev_siblings().filter(|n| n.is_element()).count();
+        let count = node
+            .parent()
+            .map_or(1, |p| p.children().filter(|n| n.is_element()).count());
+        format!("is child {} of {}", index, count)
+    };
+    match pseudo {
+        PseudoClass::Has(_) => "no descendant matches".to_string(),
+        PseudoClass::Not(_) => "matches the negated selector".to_string(),
+        PseudoClass::Is(inner) => failure_summary(inner, node),
+        PseudoClass::FirstChild
+        | PseudoClass::LastChild
+        | PseudoClass::OnlyChild
+        | PseudoClass::NthChild(_) => position(),
+        PseudoClass::NthMatch(n) => format!("is not match #{}", n),
+        PseudoClass::Layout(_, _) => "no matching element is in that position".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = r##"<?xml version="1.0" encoding="UTF-8"?><hierarchy rotation="0"><node class="Form" bounds="[0,0][1080,1920]"><node class="TextView" text="Email" bounds="[0,0][200,100]" /><node class="EditText" text="" bounds="[0,100][1080,200]" /><node class="Button" text="Submt" bounds="[0,200][200,300]" /><node class="Button" text="Cancel" bounds="[200,200][400,300]" /></node></hierarchy>"##;
+
+    fn explain_str(selector: &str) -> Explanation {
+        explain(XML, &Selector::parse(selector).unwrap(), EXPLAIN_LIMIT).unwrap()
+    }
+
+    #[test]
+    fn test_explain_reports_failing_clause() {
+        let explanation = explain_str("[class=Button][text=Submit]");
+        assert_eq!(explanation.matches, 0);
+        let closest = &explanation.candidates[0];
+        assert!(closest.element.raw_xml.contains("Submt"));
+        assert_eq!(
+            closest.checks,
+            vec![
+                Check {
+                    step: r#"[class="Button"]"#.to_string(),
+                    passed: true,
+                    detail: None,
+                },
+                Check {
+                    step: r#"[text="Submit"]"#.to_string(),
+                    passed: false,
+                    detail: Some(r#"text is "Submt""#.to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_explain_lists_matches_first() {
+        let explanation = explain_str("[class=Button]");
+        assert_eq!(explanation.matches, 2);
+        assert!(explanation.candidates.iter().all(|c| c.matched()));
+        let lines = explanation.text_lines();
+        assert_eq!(lines[1], "2 element(s) match");
+        assert!(lines.contains(&"Matched:".to_string()));
+    }
+
+    #[test]
+    fn test_explain_combinator_step() {
+        let explanation = explain_str("[text=Name] + [class=EditText]");
+        assert_eq!(explanation.matches, 0);
+        let closest = &explanation.candidates[0];
+        assert!(closest.element.raw_xml.contains("EditText"));
+        let failed: Vec<_> = closest.checks.iter().filter(|c| !c.passed).collect();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].step, r#"previous sibling matches [text="Name"]"#);
+        assert_eq!(
+            failed[0].detail.as_deref(),
+            Some(r#"previous sibling <TextView> fails [text="Name"] (text is "Email")"#)
+        );
+    }
+
+    #[test]
+    fn test_explain_has_and_not() {
+        let explanation = explain_str("[class=Form]:has([text=Submit]):not([class=Form])");
+        let form = &explanation.candidates[0];
+        assert!(form.element.raw_xml.starts_with("<node class=\"Form\""));
+        let details: Vec<_> = form
+            .checks
+            .iter()
+            .map(|c| (c.step.as_str(), c.detail.as_deref()))
+            .collect();
+        assert_eq!(
+            details,
+            vec![
+                (r#"[class="Form"]"#, None),
+                (r#":has([text="Submit"])"#, Some("no descendant matches")),
+                (
+                    r#":not([class="Form"])"#,
+                    Some("matches the negated selector")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_explain_missing_attribute() {
+        let explanation = explain_str("[class=Button][content-desc=Go]");
+        let closest = &explanation.candidates[0];
+        assert_eq!(
+            closest.checks[1].detail.as_deref(),
+            Some("content-desc is missing")
+        );
+    }
+
+    #[test]
+    fn test_explain_no_candidates() {
+        let explanation = explain_str("[class=Switch]");
+        assert_eq!(explanation.matches, 0);
+        assert!(explanation.candidates.is_empty());
+        assert!(explanation
+            .text_lines()
+            .contains(&"No element passes any step of the selector".to_string()));
+    }
+
+    #[test]
+    fn test_explain_typo_lists_similar_elements() {
+        let explanation = explain_str(r#"[text="Submit"]"#);
+        assert_eq!(explanation.matches, 0);
+        assert!(explanation.by_similarity);
+        assert_eq!(explanation.candidates.len(), 1);
+        let closest = &explanation.candidates[0];
+        assert!(closest.element.raw_xml.contains("Submt"));
+        assert_eq!(
+            closest.checks[0].detail.as_deref(),
+            Some(r#"text is "Submt""#)
+        );
+
+        let lines = explanation.text_lines();
+        assert!(lines.contains(&"No element passes any step of the selector".to_string()));
+        assert!(
+            lines.contains(&"Elements with similar text, content-desc or resource-id:".to_string())
+        );
+        assert_eq!(explanation.to_json()["by_similarity"], true);
+    }
+
+    #[test]
+    fn test_explain_json() {
+        let value = explain_str("[class=Button][text=Submit]").to_json();
+        assert_eq!(value["command"], "explain");
+        assert_eq!(value["matches"], 0);
+        assert_eq!(value["candidates"][0]["matched"], false);
+        assert_eq!(value["candidates"][0]["checks"][1]["passed"], false);
+        assert_eq!(
+            value["candidates"][0]["element"]["bounds"],
+            json!([0, 200, 200, 300])
+        );
+    }
+
+    #[test]
+    fn test_explain_near_misses_must_resemble_the_subject() {
+        // The TextView and EditText children of Form pass only the `parent matches` step
+        let explanation = explain_str("[class=Form] > [class=Button][text=Submit]");
+        assert_eq!(explanation.candidates.len(), 2);
+        assert!(explanation.candidates[0].element.raw_xml.contains("Submt"));
+        assert!(explanation.candidates[1].element.raw_xml.contains("Cancel"));
+    }
+}
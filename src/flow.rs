@@ -0,0 +1,1329 @@
+use crate::backend::DeviceBackend;
+use crate::device::{index_note, wait_for_element};
+use crate::error::BochiError;
+use crate::report::CommandReport;
+use crate::script::{
+    argument_start, parse_quoted, parse_step, run_step, SharedState, Step, StepCommand,
+    STEP_COMMANDS,
+};
+use crate::selector::{quote, Selector};
+use crate::ui_element::find_elements;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use serde_norway::{Mapping, Value};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Step commands only available in flows, on top of the script commands
+const FLOW_COMMANDS: [&str; 5] = [
+    "launchApp",
+    "stopApp",
+    "assertVisible",
+    "assertNotVisible",
+    "assertText",
+];
+
+/// Expected text of an `assertText` step
+#[derive(Debug, Clone)]
+pub enum TextMatch {
+    Equals(String),
+    /// The whole text matches the regular expression
+    Matches(Regex),
+}
+
+impl TextMatch {
+    pub fn is_match(&self, text: &str) -> bool {
+        match self {
+            TextMatch::Equals(expected) => text == expected,
+            TextMatch::Matches(re) => re.is_match(text),
+        }
+    }
+}
+
+impl fmt::Display for TextMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextMatch::Equals(expected) => write!(f, "{}", quote(expected)),
+            TextMatch::Matches(re) => {
+                // Show the pattern as written, without the anchors added by the parser
+                let pattern = re.as_str();
+                let pattern = pattern
+                    .strip_prefix("^(?:")
+                    .and_then(|p| p.strip_suffix(")$"))
+                    .unwrap_or(pattern);
+                write!(f, "--matches {}", quote(pattern))
+            }
+        }
+    }
+}
+
+/// One step of a flow
+#[derive(Debug, Clone)]
+pub enum FlowStep {
+    /// A device command, as in scripts
+    Step(Step),
+    /// Start the launcher activity of the app, stopping it (and clearing its data) first
+    LaunchApp {
+        app_id: String,
+        clear_state: bool,
+        stop_app: bool,
+    },
+    StopApp(String),
+    /// Wait until the selector matches
+    AssertVisible {
+        selector: Selector,
+        index: Option<usize>,
+        timeout: Option<u64>,
+    },
+    /// Wait until the selector matches nothing
+    AssertNotVisible {
+        selector: Selector,
+        timeout: Option<u64>,
+    },
+    /// Wait until the matched element has the expected text
+    AssertText {
+        selector: Selector,
+        index: Option<usize>,
+        expected: TextMatch,
+        timeout: Option<u64>,
+    },
+}
+
+impl FlowStep {
+    /// Name of the step command
+    pub fn name(&self) -> &'static str {
+        match self {
+            FlowStep::Step(step) => step.command.name(),
+            FlowStep::LaunchApp { .. } => "launchApp",
+            FlowStep::StopApp(_) => "stopApp",
+            FlowStep::AssertVisible { .. } => "assertVisible",
+            FlowStep::AssertNotVisible { .. } => "assertNotVisible",
+            FlowStep::AssertText { .. } => "assertText",
+        }
+    }
+}
+
+/// One-line description in script-like syntax, used in progress output and failures
+impl fmt::Display for FlowStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (selector, index, timeout) = match self {
+            FlowStep::Step(step) => return write!(f, "{}", step),
+            FlowStep::LaunchApp {
+                app_id,
+                clear_state,
+                stop_app,
+            } => {
+                write!(f, "launchApp {}", app_id)?;
+                if *clear_state {
+                    write!(f, " --clear-state")?;
+                }
+                if !*stop_app {
+                    write!(f, " --no-stop")?;
+                }
+                return Ok(());
+            }
+            FlowStep::StopApp(app_id) => return write!(f, "stopApp {}", app_id),
+            FlowStep::AssertVisible {
+                selector,
+                index,
+                timeout,
+            } => (selector, index, timeout),
+            FlowStep::AssertNotVisible { selector, timeout } => (selector, &None, timeout),
+            FlowStep::AssertText {
+                selector,
+                index,
+                timeout,
+                ..
+            } => (selector, index, timeout),
+        };
+        write!(f, "{} {}", self.name(), selector)?;
+        if let FlowStep::AssertText { expected, .. } = self {
+            write!(f, " {}", expected)?;
+        }
+        if let Some(index) = index {
+            write!(f, " --index {}", index)?;
+        }
+        if let Some(timeout) = timeout {
+            write!(f, " --timeout {}", timeout)?;
+        }
+        Ok(())
+    }
+}
+
+/// What to save when a flow fails
+#[derive(Debug, Clone, PartialEq)]
+pub enum FailureAction {
+    /// PNG of the screen, to the given path or `<flow file>-failure.png` in the artifacts directory
+    Screenshot(Option<PathBuf>),
+    /// UI hierarchy XML, to the given path or `<flow file>-failure.xml` in the artifacts directory
+    Dump(Option<PathBuf>),
+}
+
+/// A test flow read from YAML
+#[derive(Debug, Clone)]
+pub struct Flow {
+    pub name: String,
+    /// The file the flow was read from
+    pub file: String,
+    /// Default timeout of the steps in seconds
+    pub timeout: Option<u64>,
+    pub steps: Vec<FlowStep>,
+    pub on_failure: Vec<FailureAction>,
+}
+
+/// Parse a flow. The YAML is either a mapping with the configuration and a `steps` list,
+/// or, like Maestro, a configuration document and a `---` separated list of steps:
+///
+/// ```yaml
+/// appId: com.example
+/// name: Login
+/// onFailure: [screenshot, dump]
+/// ---
+/// - launchApp
+/// - inputText:
+///     selector: "[resource-id=com.example:id/user]"
+///     text: alice
+/// - tap: "[text=Login]"
+/// - assertVisible: "[text=Home]"
+/// - assertNotVisible: "[class$=ProgressBar]"
+/// - assertText: { selector: "[resource-id=com.example:id/title]", text: "Hi alice" }
+/// ```
+///
+/// A step given as a plain string uses the script syntax, e.g. `- tap [text=Login] --index 1`.
+/// `file` names the flow when it has no `name`.
+pub fn parse_flow(yaml: &str, file: &str) -> Result<Flow, BochiError> {
+    let mut documents = Vec::new();
+    for document in serde_norway::Deserializer::from_str(yaml) {
+        let value = Value::deserialize(document)
+            .map_err(|e| BochiError::InvalidArgument(format!("Invalid flow YAML: {}", e)))?;
+        if !value.is_null() {
+            documents.push(value);
+        }
+    }
+
+    let (config, steps) = match documents.as_slice() {
+        [Value::Sequence(steps)] => (None, steps),
+        [Value::Mapping(config)] => match config.get("steps") {
+            Some(Value::Sequence(steps)) => (Some(config), steps),
+            _ => return Err(invalid("a flow needs a `steps` list")),
+        },
+        [Value::Mapping(config), Value::Sequence(steps)] if config.get("steps").is_none() => {
+            (Some(config), steps)
+        }
+        _ => {
+            return Err(invalid(
+                "a flow is a list of steps, a mapping with `steps`, or a configuration and a list of steps separated by ---",
+            ))
+        }
+    };
+
+    let mut flow = Flow {
+        name: Path::new(file)
+            .file_stem()
+            .map_or(file.to_string(), |stem| stem.to_string_lossy().to_string()),
+        file: file.to_string(),
+        timeout: None,
+        steps: Vec::new(),
+        on_failure: Vec::new(),
+    };
+    let mut app_id = None;
+    if let Some(config) = config {
+        check_keys(
+            "flow configuration",
+            config,
+            &["name", "appId", "timeout", "onFailure", "steps"],
+        )?;
+        if let Some(name) = string_field(config, "name")? {
+            flow.name = name.to_string();
+        }
+        app_id = string_field(config, "appId")?
+            .map(check_package_name)
+            .transpose()?;
+        flow.timeout = number_field(config, "timeout")?;
+        if let Some(actions) = config.get("onFailure") {
+            flow.on_failure = parse_failure_actions(actions)?;
+        }
+    }
+
+    for (i, value) in steps.iter().enumerate() {
+        let step = parse_flow_step(value, app_id).map_err(|e| match e {
+            BochiError::InvalidArgument(msg) => {
+                BochiError::InvalidArgument(format!("step {}: {}", i + 1, msg))
+            }
+            e => e,
+        })?;
+        flow.steps.push(step);
+    }
+    Ok(flow)
+}
+
+/// Read and parse a flow file
+pub fn read_flow_file(path: &str) -> Result<Flow, BochiError> {
+    let yaml = fs::read_to_string(path)
+        .map_err(|e| BochiError::Other(format!("Failed to read flow file {}: {}", path, e)))?;
+    parse_flow(&yaml, path).map_err(|e| match e {
+        BochiError::InvalidArgument(msg) => {
+            BochiError::InvalidArgument(format!("{}: {}", path, msg))
+        }
+        e => e,
+    })
+}
+
+fn invalid(message: &str) -> BochiError {
+    BochiError::InvalidArgument(message.to_string())
+}
+
+fn parse_failure_actions(value: &Value) -> Result<Vec<FailureAction>, BochiError> {
+    let Value::Sequence(actions) = value else {
+        return Err(invalid(
+            "onFailure is a list of `screenshot` and `dump`, optionally with a path",
+        ));
+    };
+    actions
+        .iter()
+        .map(|action| {
+            let (name, path) = match action {
+                Value::String(name) => (name.as_str(), None),
+                Value::Mapping(map) if map.len() == 1 => {
+                    let (name, path) = map.iter().next().unwrap();
+                    (
+                        name.as_str().unwrap_or_default(),
+                        path.as_str().map(PathBuf::from),
+                    )
+                }
+                _ => ("", None),
+            };
+            match name {
+                "screenshot" => Ok(FailureAction::Screenshot(path)),
+                "dump" => Ok(FailureAction::Dump(path)),
+                _ => Err(BochiError::InvalidArgument(format!(
+                    "unknown onFailure action {} (expected screenshot or dump)",
+                    serde_norway::to_string(action).unwrap_or_default().trim()
+                ))),
+            }
+        })
+        .collect()
+}
+
+/// Parse a step: a string in script syntax, or a mapping from the command to a
+/// selector string or to its options
+fn parse_flow_step(value: &Value, app_id: Option<&str>) -> Result<FlowStep, BochiError> {
+    let app_id_or = |given: Option<&str>| match given.or(app_id) {
+        Some(app_id) => check_package_name(app_id).map(str::to_string),
+        None => Err(invalid(
+            "no app to launch or stop: give one or set appId in the flow configuration",
+        )),
+    };
+
+    let line_options;
+    let (name, options) =
+        match value {
+            Value::String(line) => {
+                let line = line.trim();
+                let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+                if !FLOW_COMMANDS.contains(&name) {
+                    return Ok(FlowStep::Step(parse_step(line)?));
+                }
+                line_options = Value::Mapping(parse_line_options(name, rest)?);
+                (name, &line_options)
+            }
+            Value::Mapping(map) if map.len() == 1 => {
+                let (name, options) = map.iter().next().unwrap();
+                let name = name
+                    .as_str()
+                    .ok_or_else(|| invalid("step name must be a string"))?;
+                (name, options)
+            }
+            _ => return Err(invalid(
+                "a step is a string like `tap [text=OK]` or a mapping like `tap: \"[text=OK]\"`",
+            )),
+        };
+
+    // `tap: "[text=OK]"` is short for `tap: { selector: "[text=OK]" }`
+    let mut shorthand = Mapping::new();
+    let options = match options {
+        Value::Mapping(map) => map,
+        Value::String(selector) if !matches!(name, "launchApp" | "stopApp") => {
+            shorthand.insert("selector".into(), selector.as_str().into());
+            &shorthand
+        }
+        Value::String(app) => {
+            shorthand.insert("appId".into(), app.as_str().into());
+            &shorthand
+        }
+        Value::Null => &shorthand,
+        _ => {
+            return Err(BochiError::InvalidArgument(format!(
+                "{} takes a selector string or a mapping of options",
+                name
+            )))
+        }
+    };
+
+    let selector = || -> Result<Selector, BochiError> {
+        let selector = string_field(options, "selector")?
+            .ok_or_else(|| BochiError::InvalidArgument(format!("{} needs a selector", name)))?;
+        Ok(Selector::parse(selector)?)
+    };
+    let index = || number_field(options, "index").map(|n| n.map(|n| n as usize));
+    let timeout = || number_field(options, "timeout");
+
+    match name {
+        "launchApp" => {
+            check_keys(name, options, &["appId", "clearState", "stopApp"])?;
+            Ok(FlowStep::LaunchApp {
+                app_id: app_id_or(string_field(options, "appId")?)?,
+                clear_state: bool_field(options, "clearState")?.unwrap_or(false),
+                stop_app: bool_field(options, "stopApp")?.unwrap_or(true),
+            })
+        }
+        "stopApp" => {
+            check_keys(name, options, &["appId"])?;
+            Ok(FlowStep::StopApp(app_id_or(string_field(
+                options, "appId",
+            )?)?))
+        }
+        "assertVisible" => {
+            check_keys(name, options, &["selector", "index", "timeout"])?;
+            Ok(FlowStep::AssertVisible {
+                selector: selector()?,
+                index: index()?,
+                timeout: timeout()?,
+            })
+        }
+        "assertNotVisible" => {
+            check_keys(name, options, &["selector", "timeout"])?;
+            Ok(FlowStep::AssertNotVisible {
+                selector: selector()?,
+                timeout: timeout()?,
+            })
+        }
+        "assertText" => {
+            check_keys(
+                name,
+                options,
+                &["selector", "text", "matches", "index", "timeout"],
+            )?;
+            let expected = match (
+                string_field(options, "text")?,
+                string_field(options, "matches")?,
+            ) {
+                (Some(text), None) => TextMatch::Equals(text.to_string()),
+                (None, Some(pattern)) => {
+                    TextMatch::Matches(Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| {
+                        BochiError::InvalidArgument(format!("invalid regex in matches: {}", e))
+                    })?)
+                }
+                _ => return Err(invalid("assertText needs either text or matches")),
+            };
+            Ok(FlowStep::AssertText {
+                selector: selector()?,
+                index: index()?,
+                expected,
+                timeout: timeout()?,
+            })
+        }
+        _ => {
+            if !STEP_COMMANDS.contains(&name) {
+                return Err(BochiError::InvalidArgument(format!(
+                    "unknown step command `{}` (expected one of {}, {})",
+                    name,
+                    STEP_COMMANDS.join(", "),
+                    FLOW_COMMANDS.join(", ")
+                )));
+            }
+            let allowed: &[&str] = match name {
+                "waitFor" => &["selector", "index", "timeout", "printDescendants"],
+                "inputText" => &["selector", "text", "index", "timeout"],
+                "scrollUp" | "scrollDown" => &["selector", "scrollTarget", "index", "timeout"],
+                _ => &["selector", "index", "timeout"],
+            };
+            check_keys(name, options, allowed)?;
+            let command = match name {
+                "waitFor" => StepCommand::WaitFor,
+                "tap" => StepCommand::Tap,
+                "longTap" => StepCommand::LongTap,
+                "doubleTap" => StepCommand::DoubleTap,
+                "inputText" => StepCommand::InputText(
+                    string_field(options, "text")?
+                        .ok_or_else(|| invalid("inputText needs a text"))?
+                        .to_string(),
+                ),
+                _ => {
+                    let target = string_field(options, "scrollTarget")?.ok_or_else(|| {
+                        BochiError::InvalidArgument(format!("{} needs a scrollTarget", name))
+                    })?;
+                    let target = Selector::parse(target)?;
+                    if name == "scrollUp" {
+                        StepCommand::ScrollUp(target)
+                    } else {
+                        StepCommand::ScrollDown(target)
+                    }
+                }
+            };
+            Ok(FlowStep::Step(Step {
+                index: index()?,
+                timeout: timeout()?,
+                print_descendants: bool_field(options, "printDescendants")?.unwrap_or(false),
+                ..Step::new(command, selector()?)
+            }))
+        }
+    }
+}
+
+/// Check that an app id is an Android package name, as it is passed to `am` and `pm`
+fn check_package_name(app_id: &str) -> Result<&str, BochiError> {
+    static PACKAGE_RE: OnceLock<Regex> = OnceLock::new();
+    let package = PACKAGE_RE
+        .get_or_init(|| Regex::new(r"^[A-Za-z][A-Za-z0-9_]*(\.[A-Za-z][A-Za-z0-9_]*)+$").unwrap());
+    if package.is_match(app_id) {
+        Ok(app_id)
+    } else {
+        Err(BochiError::InvalidArgument(format!(
+            "invalid appId {}: expected a package name like com.example.app",
+            quote(app_id)
+        )))
+    }
+}
+
+/// Options of a flow-only step written as a line, in the syntax steps are printed in,
+/// e.g. `launchApp com.example --clear-state` or `assertText [text^=Hi] "Hi alice" --timeout 5`
+fn parse_line_options(name: &str, rest: &str) -> Result<Mapping, BochiError> {
+    let mut options = Mapping::new();
+    let unexpected =
+        |word: &str| BochiError::InvalidArgument(format!("unexpected `{}` in {} step", word, name));
+    if matches!(name, "launchApp" | "stopApp") {
+        for word in rest.split_whitespace() {
+            match word {
+                "--clear-state" if name == "launchApp" => {
+                    options.insert("clearState".into(), true.into());
+                }
+                "--no-stop" if name == "launchApp" => {
+                    options.insert("stopApp".into(), false.into());
+                }
+                _ if !word.starts_with("--") && !options.contains_key("appId") => {
+                    options.insert("appId".into(), word.into());
+                }
+                _ => return Err(unexpected(word)),
+            }
+        }
+        return Ok(options);
+    }
+
+    let (selector, mut rest) = rest.split_at(argument_start(rest));
+    if !selector.trim().is_empty() {
+        options.insert("selector".into(), selector.trim().into());
+    }
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let (key, value, after) = if rest.starts_with('"') {
+            let (text, after) = parse_quoted(rest)?;
+            ("text", Value::from(text), after)
+        } else {
+            let (word, after) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            match word {
+                "--matches" => {
+                    let after = after.trim_start();
+                    if !after.starts_with('"') {
+                        return Err(invalid("--matches needs a quoted regex"));
+                    }
+                    let (pattern, after) = parse_quoted(after)?;
+                    ("matches", Value::from(pattern), after)
+                }
+                "--index" | "--timeout" => {
+                    let after = after.trim_start();
+                    let (number, after) =
+                        after.split_once(char::is_whitespace).unwrap_or((after, ""));
+                    let number = number.parse::<u64>().map_err(|_| {
+                        BochiError::InvalidArgument(format!(
+                            "{} needs a number, got `{}`",
+                            word, number
+                        ))
+                    })?;
+                    (&word[2..], Value::from(number), after)
+                }
+                _ => return Err(unexpected(word)),
+            }
+        };
+        if options.insert(key.into(), value).is_some() {
+            return Err(BochiError::InvalidArgument(format!(
+                "{} is given twice in {} step",
+                key, name
+            )));
+        }
+        rest = after;
+    }
+    Ok(options)
+}
+
+/// Reject option names the step does not know, which are most likely typos
+fn check_keys(name: &str, options: &Mapping, allowed: &[&str]) -> Result<(), BochiError> {
+    for key in options.keys() {
+        let key = key.as_str().unwrap_or_default();
+        if !allowed.contains(&key) {
+            return Err(BochiError::InvalidArgument(format!(
+                "unknown option `{}` for {} (expected {})",
+                key,
+                name,
+                allowed.join(", ")
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn string_field<'a>(options: &'a Mapping, key: &str) -> Result<Option<&'a str>, BochiError> {
+    match options.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value)),
+        Some(_) => Err(BochiError::InvalidArgument(format!(
+            "{} must be a string (quote selectors and numbers)",
+            key
+        ))),
+    }
+}
+
+fn number_field(options: &Mapping, key: &str) -> Result<Option<u64>, BochiError> {
+    match options.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value.as_u64().map(Some).ok_or_else(|| {
+            BochiError::InvalidArgument(format!("{} must be a non-negative integer", key))
+        }),
+    }
+}
+
+fn bool_field(options: &Mapping, key: &str) -> Result<Option<bool>, BochiError> {
+    match options.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value
+            .as_bool()
+            .map(Some)
+            .ok_or_else(|| BochiError::InvalidArgument(format!("{} must be true or false", key))),
+    }
+}
+
+/// A step that finished, with its description for logs and reports
+#[derive(Debug)]
+pub struct StepResult {
+    pub description: String,
+    pub report: CommandReport,
+}
+
+/// The step a flow stopped at
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowFailure {
+    /// 1-based position of the step in the flow
+    pub step: usize,
+    pub description: String,
+    pub error: BochiError,
+}
+
+impl fmt::Display for FlowFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "step {} ({}): {}",
+            self.step, self.description, self.error
+        )
+    }
+}
+
+/// A file saved after a failure, or the reason it could not be saved
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artifact {
+    pub path: PathBuf,
+    pub error: Option<String>,
+}
+
+/// Outcome of running a flow
+#[derive(Debug)]
+pub struct FlowResult {
+    pub name: String,
+    pub file: String,
+    pub steps: Vec<StepResult>,
+    pub failure: Option<FlowFailure>,
+    pub artifacts: Vec<Artifact>,
+    pub elapsed: Duration,
+}
+
+impl FlowResult {
+    pub fn to_json(&self) -> JsonValue {
+        let mut result = json!({
+            "name": self.name,
+            "file": self.file,
+            "success": self.failure.is_none(),
+            "steps": self.steps.iter().map(|step| {
+                let mut report = step.report.to_json();
+                report["step"] = json!(step.description);
+                report
+            }).collect::<Vec<_>>(),
+            "elapsed_ms": self.elapsed.as_millis() as u64,
+        });
+        if let Some(failure) = &self.failure {
+            let mut error = failure.error.to_json("test")["error"].take();
+            error["step"] = json!(failure.step);
+            error["description"] = json!(failure.description);
+            result["error"] = error;
+            result["artifacts"] = self
+                .artifacts
+                .iter()
+                .map(|artifact| {
+                    json!({
+                        "path": artifact.path.to_string_lossy(),
+                        "error": artifact.error,
+                    })
+                })
+                .collect();
+        }
+        result
+    }
+}
+
+/// Run the steps of a flow until one fails. On failure the `onFailure` artifacts are
+/// saved, by default into `artifacts_dir`. `on_step` is called as each step finishes.
+pub fn run_flow(
+    device: &dyn DeviceBackend,
+    flow: &Flow,
+    default_timeout: u64,
+    artifacts_dir: &Path,
+    mut on_step: impl FnMut(usize, &StepResult),
+) -> FlowResult {
+    let shared = SharedState::new(device);
+    let timeout = flow.timeout.unwrap_or(default_timeout);
+    let start = device.now();
+    let mut steps = Vec::new();
+    let mut failure = None;
+    for (i, step) in flow.steps.iter().enumerate() {
+        shared.next_step();
+        let step_start = device.now();
+        match run_flow_step(&shared, step, timeout) {
+            Ok(mut report) => {
+                report.elapsed = device.now() - step_start;
+                let result = StepResult {
+                    description: step.to_string(),
+                    report,
+                };
+                on_step(i + 1, &result);
+                steps.push(result);
+            }
+            Err(error) => {
+                failure = Some(FlowFailure {
+                    step: i + 1,
+                    description: step.to_string(),
+                    error,
+                });
+                break;
+            }
+        }
+    }
+
+    // Artifacts come from the device itself so that the dump is fresh
+    let artifacts = match failure {
+        Some(_) => save_failure_artifacts(device, flow, artifacts_dir),
+        None => Vec::new(),
+    };
+    FlowResult {
+        name: flow.name.clone(),
+        file: flow.file.clone(),
+        steps,
+        failure,
+        artifacts,
+        elapsed: device.now() - start,
+    }
+}
+
+fn run_flow_step(
+    device: &dyn DeviceBackend,
+    step: &FlowStep,
+    default_timeout: u64,
+) -> Result<CommandReport, BochiError> {
+    let report = |elements, polls| CommandReport {
+        command: step.name(),
+        elements,
+        action: None,
+        dry_run: false,
+        elapsed: Duration::ZERO,
+        polls,
+    };
+
+    match step {
+        FlowStep::Step(step) => run_step(device, step, default_timeout),
+        FlowStep::LaunchApp {
+            app_id,
+            clear_state,
+            stop_app,
+        } => {
+            if *stop_app {
+                device.shell(&["am", "force-stop", app_id])?;
+            }
+            if *clear_state {
+                device.shell(&["pm", "clear", app_id])?;
+            }
+            let output = device.shell(&[
+                "monkey",
+                "-p",
+                app_id,
+                "-c",
+                "android.intent.category.LAUNCHER",
+                "1",
+            ])?;
+            // monkey reports a missing package on stdout with a zero exit status on some versions
+            if output.contains("No activities found") {
+                return Err(BochiError::Other(format!(
+                    "Failed to launch {}: {}",
+                    app_id,
+                    output.trim()
+                )));
+            }
+            Ok(report(Vec::new(), 0))
+        }
+        FlowStep::StopApp(app_id) => {
+            device.shell(&["am", "force-stop", app_id])?;
+            Ok(report(Vec::new(), 0))
+        }
+        FlowStep::AssertVisible {
+            selector,
+            index,
+            timeout,
+        } => {
+            let (element, polls) = wait_for_element(
+                device,
+                selector,
+                timeout.unwrap_or(default_timeout),
+                index.unwrap_or(0),
+            )
+            .map_err(|e| match e {
+                BochiError::ElementTimeout(..) => {
+                    BochiError::AssertionFailed(format!("assertVisible failed: {}", e))
+                }
+                e => e,
+            })?;
+            Ok(report(vec![element], polls))
+        }
+        FlowStep::AssertNotVisible { selector, timeout } => {
+            let timeout = Duration::from_secs(timeout.unwrap_or(default_timeout));
+            let start = device.now();
+            let mut polls = 0;
+            loop {
+                let xml = device.ui_hierarchy()?;
+                polls += 1;
+                let matched = find_elements(&xml, selector)?;
+                if matched.is_empty() {
+                    return Ok(report(Vec::new(), polls));
+                }
+                if device.now() - start > timeout {
+                    return Err(BochiError::AssertionFailed(format!(
+                        "assertNotVisible failed: {} element(s) still match selector: {}\n{}",
+                        matched.len(),
+                        selector,
+                        matched[0].raw_xml
+                    )));
+                }
+                device.sleep(Duration::from_millis(500));
+            }
+        }
+        FlowStep::AssertText {
+            selector,
+            index,
+            expected,
+            timeout,
+        } => {
+            let timeout = Duration::from_secs(timeout.unwrap_or(default_timeout));
+            let index = index.unwrap_or(0);
+            let start = device.now();
+            let mut polls = 0;
+            loop {
+                let xml = device.ui_hierarchy()?;
+                polls += 1;
+                let mut matched = find_elements(&xml, selector)?;
+                if matched
+                    .get(index)
+                    .is_some_and(|e| expected.is_match(&e.text))
+                {
+                    return Ok(report(vec![matched.swap_remove(index)], polls));
+                }
+                if device.now() - start > timeout {
+                    let message = match matched.get(index) {
+                        Some(element) => format!(
+                            "assertText failed: expected text {} but the element matching {} has text {}",
+                            expected,
+                            selector,
+                            quote(&element.text)
+                        ),
+                        None => format!(
+                            "assertText failed: no element matches selector: {}{}",
+                            selector,
+                            index_note(index, matched.len())
+                        ),
+                    };
+                    return Err(BochiError::AssertionFailed(message));
+                }
+                device.sleep(Duration::from_millis(500));
+            }
+        }
+    }
+}
+
+fn save_failure_artifacts(device: &dyn DeviceBackend, flow: &Flow, dir: &Path) -> Vec<Artifact> {
+    // Named after the flow file, which tells apart flows with the same name
+    let file = Path::new(&flow.file).with_extension("");
+    let slug = file
+        .to_string_lossy()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join("-");
+    flow.on_failure
+        .iter()
+        .map(|action| {
+            let (path, content) = match action {
+                FailureAction::Screenshot(path) => (
+                    path.clone()
+                        .unwrap_or_else(|| dir.join(format!("{}-failure.png", slug))),
+                    device.screenshot(),
+                ),
+                FailureAction::Dump(path) => (
+                    path.clone()
+                        .unwrap_or_else(|| dir.join(format!("{}-failure.xml", slug))),
+                    device.ui_hierarchy().map(String::into_bytes),
+                ),
+            };
+            let error = match content {
+                Ok(content) => fs::write(&path, content).err().map(|e| e.to_string()),
+                Err(e) => Some(e.to_string()),
+            };
+            Artifact { path, error }
+        })
+        .collect()
+}
+
+/// JUnit XML report with one test case per flow. Assertion failures and elements that
+/// never appeared are `<failure>`s; device and adb problems are `<error>`s.
+pub fn junit_report(results: &[FlowResult]) -> String {
+    let is_failure = |error: &BochiError| {
+        matches!(
+            error,
+            BochiError::AssertionFailed(_)
+                | BochiError::ElementTimeout(..)
+                | BochiError::ElementNotFound(_)
+        )
+    };
+    let failures = results
+        .iter()
+        .filter(|r| r.failure.as_ref().is_some_and(|f| is_failure(&f.error)))
+        .count();
+    let errors = results.iter().filter(|r| r.failure.is_some()).count() - failures;
+    let time: f64 = results.iter().map(|r| r.elapsed.as_secs_f64()).sum();
+
+    let counts = format!(
+        r#"tests="{}" failures="{}" errors="{}" time="{:.3}""#,
+        results.len(),
+        failures,
+        errors,
+        time
+    );
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!("<testsuites name=\"bochi\" {}>\n", counts));
+    xml.push_str(&format!("  <testsuite name=\"bochi\" {}>\n", counts));
+    for result in results {
+        xml.push_str(&format!(
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\">\n",
+            escape_xml(&result.name),
+            escape_xml(&result.file),
+            result.elapsed.as_secs_f64()
+        ));
+        if let Some(failure) = &result.failure {
+            let mut details = failure.to_string();
+            for artifact in &result.artifacts {
+                match &artifact.error {
+                    None => details.push_str(&format!("\nSaved {}", artifact.path.display())),
+                    Some(e) => details.push_str(&format!(
+                        "\nFailed to save {}: {}",
+                        artifact.path.display(),
+                        e
+                    )),
+                }
+            }
+            let tag = if is_failure(&failure.error) {
+                "failure"
+            } else {
+                "error"
+            };
+            xml.push_str(&format!(
+                "      <{} type=\"{}\" message=\"{}\">{}</{}>\n",
+                tag,
+                failure.error.kind(),
+                escape_xml(&format!("step {}: {}", failure.step, failure.description)),
+                escape_xml(&details),
+                tag
+            ));
+        }
+        let log: Vec<String> = result
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| {
+                format!(
+                    "[{}] {} ({} ms)",
+                    i + 1,
+                    step.description,
+                    step.report.elapsed.as_millis()
+                )
+            })
+            .collect();
+        if !log.is_empty() {
+            xml.push_str(&format!(
+                "      <system-out>{}</system-out>\n",
+                escape_xml(&log.join("\n"))
+            ));
+        }
+        xml.push_str("    </testcase>\n");
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{DeviceEvent, FakeDevice};
+
+    const LOGIN: &str = r##"<?xml version="1.0" encoding="UTF-8"?><hierarchy rotation="0"><node class="android.widget.FrameLayout" bounds="[0,0][1080,1920]"><node class="android.widget.EditText" resource-id="com.example:id/user" bounds="[0,100][1080,200]" /><node class="android.widget.Button" text="Login" bounds="[0,300][200,400]" /><node class="android.widget.ProgressBar" bounds="[0,500][100,600]" /></node></hierarchy>"##;
+    const HOME: &str = r##"<?xml version="1.0" encoding="UTF-8"?><hierarchy rotation="0"><node class="android.widget.TextView" resource-id="com.example:id/title" text="Hi alice" bounds="[0,0][1080,100]" /></hierarchy>"##;
+
+    const FLOW: &str = r#"
+appId: com.example
+name: Login flow
+onFailure: [screenshot, dump]
+---
+- launchApp:
+    clearState: true
+- inputText:
+    selector: "[resource-id=com.example:id/user]"
+    text: alice
+- tap [text=Login]
+- assertNotVisible: "[class$=ProgressBar]"
+- assertVisible: "[text^=Hi]"
+- assertText: { selector: "[resource-id=com.example:id/title]", matches: "Hi \\w+" }
+"#;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bochi-flow-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_parse_flow() {
+        let flow = parse_flow(FLOW, "flows/login.yaml").unwrap();
+        assert_eq!(flow.name, "Login flow");
+        assert_eq!(flow.file, "flows/login.yaml");
+        assert_eq!(
+            flow.on_failure,
+            vec![FailureAction::Screenshot(None), FailureAction::Dump(None)]
+        );
+        let steps: Vec<String> = flow.steps.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            steps,
+            vec![
+                "launchApp com.example --clear-state",
+                r#"inputText [resource-id="com.example:id/user"] "alice""#,
+                r#"tap [text="Login"]"#,
+                r#"assertNotVisible [class$="ProgressBar"]"#,
+                r#"assertVisible [text^="Hi"]"#,
+                r#"assertText [resource-id="com.example:id/title"] --matches "Hi \w+""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_flow_layouts() {
+        let flow = parse_flow("- launchApp com.other\n- waitFor [text=OK]\n", "smoke.yml").unwrap();
+        assert_eq!(flow.name, "smoke");
+        assert_eq!(flow.steps.len(), 2);
+
+        let flow = parse_flow(
+            "timeout: 5\nsteps:\n  - stopApp: com.example\n  - assertVisible: { selector: \"[text=OK]\", index: 1 }\n",
+            "x.yaml",
+        )
+        .unwrap();
+        assert_eq!(flow.timeout, Some(5));
+        assert_eq!(flow.steps[0].to_string(), "stopApp com.example");
+        assert_eq!(
+            flow.steps[1].to_string(),
+            r#"assertVisible [text="OK"] --index 1"#
+        );
+    }
+
+    #[test]
+    fn test_parse_flow_documents() {
+        // Separator lines with a comment, and `---` inside block and quoted scalars
+        let flow = parse_flow(
+            "appId: com.example\n--- # steps\n- inputText:\n    selector: \"[text=Note]\"\n    text: |\n      above\n      ---\n      below\n- inputText: { selector: \"[text=Note]\", text: \"a\n  ---\n  b\" }\n",
+            "notes.yaml",
+        )
+        .unwrap();
+        assert_eq!(flow.steps.len(), 2);
+        let texts: Vec<&str> = flow
+            .steps
+            .iter()
+            .map(|step| match step {
+                FlowStep::Step(Step {
+                    command: StepCommand::InputText(text),
+                    ..
+                }) => text.as_str(),
+                step => panic!("unexpected step {}", step),
+            })
+            .collect();
+        assert_eq!(texts, vec!["above\n---\nbelow\n", "a --- b"]);
+    }
+
+    #[test]
+    fn test_printed_steps_parse_back() {
+        let yaml = r#"appId: com.example
+---
+- launchApp: { clearState: true, stopApp: false }
+- stopApp
+- assertVisible: { selector: "[text^=Hi]", index: 1, timeout: 5 }
+- assertNotVisible: { selector: "[class$=ProgressBar]", timeout: 20 }
+- assertText: { selector: "[text^=Hi]", text: "Hi \"alice\"", index: 2 }
+- assertText: { selector: "[text^=Hi]", matches: "Hi \\w+", timeout: 3 }
+- tap: { selector: "[text=OK]", index: 1 }
+"#;
+        let flow = parse_flow(yaml, "f.yaml").unwrap();
+        for step in &flow.steps {
+            let line = step.to_string();
+            let reparsed = parse_flow(&format!("- '{}'\n", line.replace('\'', "''")), "f.yaml")
+                .unwrap_or_else(|e| panic!("`{}` does not parse back: {}", line, e));
+            assert_eq!(reparsed.steps[0].to_string(), line);
+        }
+        assert_eq!(
+            flow.steps[0].to_string(),
+            "launchApp com.example --clear-state --no-stop"
+        );
+    }
+
+    #[test]
+    fn test_parse_flow_errors() {
+        let error = |yaml: &str| parse_flow(yaml, "f.yaml").unwrap_err().to_string();
+        assert!(error("- launchApp\n").contains("step 1: no app to launch"));
+        assert!(error("- launchApp com.example;reboot\n")
+            .contains(r#"step 1: invalid appId "com.example;reboot""#));
+        assert!(error("- launchApp com.example && reboot\n").contains("unexpected `&&`"));
+        assert!(
+            error("- stopApp com.example --clear-state\n").contains("unexpected `--clear-state`")
+        );
+        assert!(
+            error("- assertVisible [text=OK] --scroll-target [text=A]\n")
+                .contains("unexpected `--scroll-target`")
+        );
+        assert!(error("- assertText [text=OK] --matches hi\n").contains("needs a quoted regex"));
+        assert!(error("- assertText [text=OK] \"a\" \"b\"\n").contains("text is given twice"));
+        assert!(error("- assertNotVisible [text=OK] --index 1\n")
+            .contains("unknown option `index` for assertNotVisible"));
+        assert!(error("- stopApp: { appId: \"com.example'\" }\n").contains("step 1: invalid appId"));
+        assert!(error("- launchApp: example\n").contains("invalid appId"));
+        assert!(
+            error("appId: com.example && reboot\n---\n- waitFor [text=OK]\n")
+                .contains("invalid appId")
+        );
+        assert!(error("- tap: { selector: \"[text=OK]\", txt: hi }\n")
+            .contains("step 1: unknown option `txt` for tap"));
+        assert!(error("- assertText: \"[text=OK]\"\n").contains("assertText needs either text"));
+        assert!(error("- swipe: \"[text=OK]\"\n").contains("unknown step command `swipe`"));
+        assert!(error("- scrollDown: \"[scrollable=true]\"\n").contains("needs a scrollTarget"));
+        assert!(error("- inputText: \"[text=OK]\"\n").contains("inputText needs a text"));
+        assert!(error("name: x\n").contains("needs a `steps` list"));
+        assert!(error("onFailure: [video]\nsteps: []\n").contains("unknown onFailure action video"));
+        assert!(matches!(
+            parse_flow("- tap: \"[text=OK\"\n", "f.yaml"),
+            Err(BochiError::SelectorParse(_))
+        ));
+    }
+
+    #[test]
+    fn test_run_flow() {
+        let flow = parse_flow(FLOW, "login.yaml").unwrap();
+        let device = FakeDevice::new([LOGIN, LOGIN, HOME]);
+        let mut seen = Vec::new();
+        let result = run_flow(&device, &flow, 5, Path::new("."), |n, _| seen.push(n));
+        assert_eq!(result.failure, None);
+        assert_eq!(seen, vec![1, 2, 3, 4, 5, 6]);
+        assert!(result.artifacts.is_empty());
+        assert_eq!(result.steps[5].report.elements[0].text, "Hi alice");
+
+        let events = device.events();
+        assert_eq!(
+            &events[..3],
+            &[
+                DeviceEvent::Shell("am force-stop com.example".to_string()),
+                DeviceEvent::Shell("pm clear com.example".to_string()),
+                DeviceEvent::Shell(
+                    "monkey -p com.example -c android.intent.category.LAUNCHER 1".to_string()
+                ),
+            ]
+        );
+        assert!(!events.contains(&DeviceEvent::Screenshot));
+    }
+
+    #[test]
+    fn test_assertion_failures() {
+        let flow = parse_flow(
+            "- assertNotVisible: { selector: \"[text=Login]\", timeout: 2 }\n",
+            "f.yaml",
+        )
+        .unwrap();
+        let device = FakeDevice::new([LOGIN]);
+        let result = run_flow(&device, &flow, 5, Path::new("."), |_, _| {});
+        let failure = result.failure.unwrap();
+        assert_eq!(failure.step, 1);
+        assert!(matches!(failure.error, BochiError::AssertionFailed(_)));
+        assert!(failure
+            .error
+            .to_string()
+            .contains("1 element(s) still match"));
+        assert!(device.elapsed() >= Duration::from_secs(2));
+
+        let flow = parse_flow(
+            "- assertText: { selector: \"[resource-id=com.example:id/title]\", text: Hi bob, timeout: 1 }\n",
+            "f.yaml",
+        )
+        .unwrap();
+        let result = run_flow(
+            &FakeDevice::new([HOME]),
+            &flow,
+            5,
+            Path::new("."),
+            |_, _| {},
+        );
+        assert!(result
+            .failure
+            .unwrap()
+            .error
+            .to_string()
+            .contains(r#"expected text "Hi bob" but the element matching [resource-id="com.example:id/title"] has text "Hi alice""#));
+
+        let flow = parse_flow("- assertVisible [text=Home]\n", "f.yaml").unwrap();
+        let result = run_flow(
+            &FakeDevice::new([LOGIN]),
+            &flow,
+            0,
+            Path::new("."),
+            |_, _| {},
+        );
+        let error = result.failure.unwrap().error;
+        assert_eq!(error.exit_code(), 12);
+        assert!(error.to_string().starts_with("assertVisible failed: "));
+    }
+
+    #[test]
+    fn test_failure_artifacts() {
+        let dir = temp_dir("artifacts");
+        let flow = parse_flow(
+            "name: Check Home\nonFailure: [screenshot, dump]\nsteps:\n  - tap [text=Login]\n  - waitFor: { selector: \"[text=Home]\", timeout: 0 }\n  - tap [text=Home]\n",
+            "flows/Home.yaml",
+        )
+        .unwrap();
+        let device = FakeDevice::new([LOGIN]);
+        let result = run_flow(&device, &flow, 5, &dir, |_, _| {});
+        assert_eq!(result.steps.len(), 1);
+        assert_eq!(result.failure.as_ref().unwrap().step, 2);
+        assert_eq!(
+            result.artifacts,
+            vec![
+                Artifact {
+                    path: dir.join("flows-home-failure.png"),
+                    error: None
+                },
+                Artifact {
+                    path: dir.join("flows-home-failure.xml"),
+                    error: None
+                },
+            ]
+        );
+        assert!(fs::read(dir.join("flows-home-failure.png"))
+            .unwrap()
+            .starts_with(b"\x89PNG"));
+        assert_eq!(
+            fs::read_to_string(dir.join("flows-home-failure.xml")).unwrap(),
+            LOGIN
+        );
+
+        // Another flow with the same name does not overwrite them
+        let other = Flow {
+            file: "smoke/home.yml".to_string(),
+            ..flow
+        };
+        let result = run_flow(&FakeDevice::new([LOGIN]), &other, 5, &dir, |_, _| {});
+        assert_eq!(result.artifacts[0].path, dir.join("smoke-home-failure.png"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_junit_report() {
+        let passing = parse_flow(
+            "name: Smoke\nsteps: [\"waitFor [text=Login]\"]\n",
+            "smoke.yaml",
+        )
+        .unwrap();
+        let failing = parse_flow(
+            "name: <Home> & co\nsteps: [\"assertVisible [text=Home]\"]\n",
+            "home.yaml",
+        )
+        .unwrap();
+        let results: Vec<FlowResult> = [passing, failing]
+            .iter()
+            .map(|flow| {
+                run_flow(
+                    &FakeDevice::new([LOGIN]),
+                    flow,
+                    0,
+                    Path::new("."),
+                    |_, _| {},
+                )
+            })
+            .collect();
+        let xml = junit_report(&results);
+
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+        let suite = doc
+            .descendants()
+            .find(|n| n.has_tag_name("testsuite"))
+            .unwrap();
+        assert_eq!(suite.attribute("tests"), Some("2"));
+        assert_eq!(suite.attribute("failures"), Some("1"));
+        assert_eq!(suite.attribute("errors"), Some("0"));
+        let cases: Vec<_> = doc
+            .descendants()
+            .filter(|n| n.has_tag_name("testcase"))
+            .collect();
+        assert_eq!(cases[0].attribute("name"), Some("Smoke"));
+        assert_eq!(cases[0].attribute("classname"), Some("smoke.yaml"));
+        assert!(!cases[0].children().any(|n| n.has_tag_name("failure")));
+        assert_eq!(cases[1].attribute("name"), Some("<Home> & co"));
+        let failure = cases[1]
+            .children()
+            .find(|n| n.has_tag_name("failure"))
+            .unwrap();
+        assert_eq!(failure.attribute("type"), Some("assertion_failed"));
+        assert_eq!(
+            failure.attribute("message"),
+            Some(r#"step 1: assertVisible [text="Home"]"#)
+        );
+    }
+}
@@ -0,0 +1,53 @@
+//! Control Android devices through ADB with CSS-like selectors on the UI hierarchy.
+//!
+//! The `bochi` binary is a thin command line interface over this library. Use the
+//! library directly to embed the selector engine or the device actions in a Rust
+//! test harness, or run the same logic against the scripted `FakeDevice` in unit tests:
+//!
+//! ```no_run
+//! use bochi::{find_elements, tap_element, wait_for_element, AdbDevice, DeviceBackend, Selector};
+//!
+//! # fn main() -> Result<(), bochi::BochiError> {
+//! let device = AdbDevice::new(None);
+//! let selector = Selector::parse(r#"[resource-id="com.example:id/login"]"#)?;
+//!
+//! // Poll the connected device until the element appears (30 s timeout), then tap it
+//! let (button, _polls) = wait_for_element(&device, &selector, 30, 0)?;
+//! tap_element(&device, &button)?;
+//!
+//! // Or evaluate a selector against a hierarchy fetched once
+//! let xml = device.ui_hierarchy()?;
+//! let rows = find_elements(&xml, &Selector::parse("[class$=RecyclerView] > [clickable=true]")?)?;
+//! println!("{} rows", rows.len());
+//! # Ok(())
+//! # }
+//! ```
+
+mod adb_client;
+mod adb_utils;
+pub mod backend;
+pub mod device;
+pub mod dump;
+pub mod error;
+pub mod explain;
+pub mod flow;
+pub mod locate;
+pub mod report;
+pub mod script;
+pub mod selector;
+pub mod session;
+pub mod suggest;
+pub mod ui_element;
+
+pub use backend::{AdbDevice, DeviceBackend, DeviceEvent, FakeDevice};
+pub use device::{
+    double_tap_element, input_text_element, long_tap_element, scroll_until_visible, swipe,
+    tap_element, wait_for_element, wait_for_elements, ScreenPoint,
+};
+pub use error::BochiError;
+pub use selector::{Selector, SelectorError};
+pub use session::ShellSession;
+pub use ui_element::{
+    find_elements, find_elements_with_descendants, get_ui_hierarchy, parse_hierarchy, UiElement,
+    UiTree,
+};
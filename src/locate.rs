@@ -0,0 +1,401 @@
+use crate::error::BochiError;
+use crate::report::element_to_json;
+use crate::selector::{quote, Selector};
+use crate::ui_element::{parse_bounds, parse_hierarchy, UiElement, UiTree};
+use roxmltree::Node;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Maximum number of alternative selectors listed per element
+pub const ALTERNATIVE_LIMIT: usize = 3;
+
+/// Attributes identifying an element on their own, most robust first
+const IDENTIFYING_ATTRIBUTES: [&str; 3] = ["resource-id", "content-desc", "text"];
+
+/// Generated selectors for one element
+#[derive(Debug)]
+pub struct Location {
+    pub element: UiElement,
+    /// The preferred selector matching only this element
+    pub selector: String,
+    /// Other selectors matching only this element, in order of preference
+    pub alternatives: Vec<String>,
+}
+
+impl Location {
+    fn to_json(&self) -> Value {
+        json!({
+            "selector": self.selector,
+            "alternatives": self.alternatives,
+            "element": element_to_json(&self.element),
+        })
+    }
+}
+
+/// Result of the `locate` command
+#[derive(Debug)]
+pub struct LocateReport {
+    pub locations: Vec<Location>,
+    pub elapsed: Duration,
+}
+
+impl LocateReport {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "command": "locate",
+            "success": true,
+            "elements": self.locations.iter().map(Location::to_json).collect::<Vec<_>>(),
+            "elapsed_ms": self.elapsed.as_millis() as u64,
+            "polls": 1,
+        })
+    }
+
+    /// The selector of each element, followed by its XML and the alternatives
+    pub fn text_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (i, location) in self.locations.iter().enumerate() {
+            if i > 0 {
+                lines.push(String::new());
+            }
+            lines.push(location.selector.clone());
+            lines.push(format!("  element: {}", location.element.raw_xml));
+            for alternative in &location.alternatives {
+                lines.push(format!("  alternative: {}", alternative));
+            }
+        }
+        lines
+    }
+}
+
+/// Parse the `x,y` value of `--at`
+pub fn parse_point(s: &str) -> Result<(i32, i32), String> {
+    let invalid = || format!("expected x,y coordinates such as 540,1200, got {:?}", s);
+    let (x, y) = s.split_once(',').ok_or_else(invalid)?;
+    let x = x.trim().parse().map_err(|_| invalid())?;
+    let y = y.trim().parse().map_err(|_| invalid())?;
+    Ok((x, y))
+}
+
+/// Generate selectors for the innermost element whose bounds contain the point
+pub fn locate_at(xml: &str, x: i32, y: i32) -> Result<LocateReport, BochiError> {
+    let doc = parse_hierarchy(xml)?;
+    let containing: Vec<(Node, i32)> = doc
+        .descendants()
+        .filter(|n| n.is_element())
+        .filter_map(|n| {
+            let (x1, y1, x2, y2) = parse_bounds(n.attribute("bounds")?)?;
+            let contains = x1 <= x && x < x2 && y1 <= y && y < y2;
+            contains.then_some((n, (x2 - x1) * (y2 - y1)))
+        })
+        .collect();
+    // Smallest area wins; on ties the later (deeper) element is drawn on top
+    let node = containing
+        .into_iter()
+        .rev()
+        .min_by_key(|(_, area)| *area)
+        .map(|(node, _)| node)
+        .ok_or_else(|| BochiError::ElementNotFound(format!("No element at ({}, {})", x, y)))?;
+
+    Ok(LocateReport {
+        locations: location(&UiTree::from_document(&doc), node)
+            .into_iter()
+            .collect(),
+        elapsed: Duration::ZERO,
+    })
+}
+
+/// Generate selectors for every element matching a (typically broad) selector
+pub fn locate_matching(xml: &str, selector: &Selector) -> Result<LocateReport, BochiError> {
+    let doc = parse_hierarchy(xml)?;
+    let tree = UiTree::from_document(&doc);
+    let locations: Vec<Location> = doc
+        .descendants()
+        .filter(|n| n.is_element() && selector.matches(*n))
+        .filter_map(|n| location(&tree, n))
+        .collect();
+    if locations.is_empty() {
+        return Err(BochiError::ElementNotFound(format!(
+            "No element in the UI hierarchy matches selector: {}",
+            selector
+        )));
+    }
+
+    Ok(LocateReport {
+        locations,
+        elapsed: Duration::ZERO,
+    })
+}
+
+fn location(tree: &UiTree, node: Node) -> Option<Location> {
+    let element = tree.element(node)?.clone();
+    let mut selectors = unique_selectors(node);
+    let selector = selectors.remove(0);
+    selectors.truncate(ALTERNATIVE_LIMIT);
+    Some(Location {
+        element,
+        selector,
+        alternatives: selectors,
+    })
+}
+
+/// All generated selectors matching only the node, most preferred first.
+/// Never empty: the last resort is the structural path from the top of the hierarchy.
+fn unique_selectors(node: Node) -> Vec<String> {
+    let own = identifying_clauses(node);
+    let class = node
+        .attribute("class")
+        .filter(|c| !c.is_empty())
+        .map(|c| format!("[class={}]", quote(c)));
+
+    // Tiers in order of preference. Single attributes keep the attribute order,
+    // the other tiers prefer shorter selectors.
+    let mut tiers: Vec<Vec<String>> = own.iter().map(|c| vec![c.clone()]).collect();
+
+    let mut compounds = Vec::new();
+    for (i, clause) in own.iter().enumerate() {
+        if let Some(class) = &class {
+            compounds.push(format!("{}{}", class, clause));
+        }
+        for other in &own[i + 1..] {
+            compounds.push(format!("{}{}", clause, other));
+        }
+    }
+    tiers.push(compounds);
+
+    // Relative to the closest uniquely identified ancestor or the preceding sibling,
+    // e.g. the input field right after its label
+    let subjects: Vec<&String> = own.iter().chain(class.iter()).collect();
+    let mut relative = Vec::new();
+    if let Some(label) = node.prev_sibling_element().and_then(unique_identifier) {
+        relative.extend(subjects.iter().map(|s| format!("{} + {}", label, s)));
+    }
+    if let Some(anchor) = node.ancestors().skip(1).find_map(unique_identifier) {
+        relative.extend(subjects.iter().map(|s| format!("{} {}", anchor, s)));
+    }
+    tiers.push(relative);
+
+    // The n-th of the elements with the same text or description, e.g. one of several
+    // identical "Add to cart" buttons, which survives layout changes better than a path
+    let mut positional = Vec::new();
+    for clause in own.iter().filter(|c| !c.starts_with("[resource-id=")) {
+        if let Some(position) = match_position(clause, node) {
+            positional.push(format!("{}:nth-match({})", clause, position + 1));
+        }
+    }
+    tiers.push(positional);
+
+    let mut selectors: Vec<String> = Vec::new();
+    for mut tier in tiers {
+        tier.sort_by_key(|s| s.len());
+        for selector in tier {
+            if !selectors.contains(&selector) && is_unique(&selector, node) {
+                selectors.push(selector);
+            }
+        }
+    }
+    selectors.push(structural_path(node));
+    selectors
+}
+
+/// `[attr="value"]` clauses for the node's non-empty identifying attributes
+fn identifying_clauses(node: Node) -> Vec<String> {
+    IDENTIFYING_ATTRIBUTES
+        .iter()
+        .filter_map(|attr| {
+            let value = node.attribute(*attr).filter(|v| !v.trim().is_empty())?;
+            Some(format!("[{}={}]", attr, quote(value)))
+        })
+        .collect()
+}
+
+/// The most preferred single-attribute selector matching only the node
+fn unique_identifier(node: Node) -> Option<String> {
+    identifying_clauses(node)
+        .into_iter()
+        .find(|clause| is_unique(clause, node))
+}
+
+/// Chain of `[class=...]:nth-child(n)` steps from the closest uniquely identified ancestor,
+/// or from the top of the hierarchy, down to the node. Selects the node by its
+/// position among the matches if the path alone is ambiguous.
+fn structural_path(node: Node) -> String {
+    let mut steps = Vec::new();
+    let mut current = node;
+    loop {
+        let position = current.prev_siblings().filter(|n| n.is_element()).count();
+        let class = current
+            .attribute("class")
+            .filter(|c| !c.is_empty())
+            .map(|c| format!("[class={}]", quote(c)))
+            .unwrap_or_default();
+        steps.push(format!("{}:nth-child({})", class, position));
+
+        let Some(parent) = current.parent_element() else {
+            break;
+        };
+        // The root element is the <hierarchy> wrapper, not a UI element
+        if parent.parent_element().is_none() {
+            break;
+        }
+        if let Some(anchor) = unique_identifier(parent) {
+            steps.push(anchor);
+            break;
+        }
+        current = parent;
+    }
+    steps.reverse();
+    let path = steps.join(" > ");
+    if is_unique(&path, node) {
+        return path;
+    }
+
+    let position = match_position(&path, node).unwrap_or(0);
+    format!(":is({}):nth-match({})", path, position + 1)
+}
+
+/// 0-based position of the node among the elements matching the selector, in document order
+fn match_position(selector: &str, node: Node) -> Option<usize> {
+    let selector = Selector::parse(selector).ok()?;
+    node.document()
+        .descendants()
+        .filter(|n| n.is_element() && selector.matches(*n))
+        .position(|n| n == node)
+}
+
+/// Check that the selector matches the node and nothing else in its hierarchy
+fn is_unique(selector: &str, node: Node) -> bool {
+    let Ok(selector) = Selector::parse(selector) else {
+        return false;
+    };
+    let mut matches = node
+        .document()
+        .descendants()
+        .filter(|n| n.is_element() && selector.matches(*n));
+    matches.next() == Some(node) && matches.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui_element::find_elements;
+
+    const XML: &str = r##"<?xml version="1.0" encoding="UTF-8"?><hierarchy rotation="0"><node class="android.widget.FrameLayout" bounds="[0,0][1080,1920]"><node class="android.widget.LinearLayout" resource-id="com.example:id/form" bounds="[0,0][1080,1000]"><node class="android.widget.TextView" text="Email" bounds="[0,0][1080,100]" /><node class="android.widget.EditText" text="" bounds="[0,100][1080,200]" /><node class="android.widget.TextView" text="Password" bounds="[0,200][1080,300]" /><node class="android.widget.EditText" text="" bounds="[0,300][1080,400]" /><node class="android.widget.Button" text="Submit" resource-id="com.example:id/submit" bounds="[0,400][540,500]" /><node class="android.widget.Button" text="Submit" content-desc="Submit later" bounds="[540,400][1080,500]" /></node><node class="android.widget.LinearLayout" bounds="[0,1000][1080,1920]"><node class="android.view.View" bounds="[0,1000][540,1920]" /><node class="android.view.View" bounds="[540,1000][1080,1920]" /></node></node></hierarchy>"##;
+
+    fn selector_at(x: i32, y: i32) -> String {
+        locate_at(XML, x, y).unwrap().locations[0].selector.clone()
+    }
+
+    /// The generated selector must select exactly the located element
+    fn assert_selects_only(selector: &str, bounds: (i32, i32, i32, i32)) {
+        let elements = find_elements(XML, &Selector::parse(selector).unwrap()).unwrap();
+        assert_eq!(elements.len(), 1, "{} is not unique", selector);
+        assert_eq!(
+            elements[0].bounds, bounds,
+            "{} selects another element",
+            selector
+        );
+    }
+
+    #[test]
+    fn test_parse_point() {
+        assert_eq!(parse_point("540,1200"), Ok((540, 1200)));
+        assert_eq!(parse_point(" 1, 2 "), Ok((1, 2)));
+        assert!(parse_point("540").is_err());
+        assert!(parse_point("a,b").is_err());
+    }
+
+    #[test]
+    fn test_prefers_resource_id() {
+        assert_eq!(
+            selector_at(100, 450),
+            r#"[resource-id="com.example:id/submit"]"#
+        );
+        let location = &locate_at(XML, 100, 450).unwrap().locations[0];
+        // Both buttons have the same class and text
+        assert!(!location
+            .alternatives
+            .contains(&r#"[class="android.widget.Button"][text="Submit"]"#.to_string()));
+    }
+
+    #[test]
+    fn test_prefers_content_desc_over_ambiguous_text() {
+        assert_eq!(selector_at(600, 450), r#"[content-desc="Submit later"]"#);
+    }
+
+    #[test]
+    fn test_unique_text() {
+        assert_eq!(selector_at(10, 10), r#"[text="Email"]"#);
+    }
+
+    #[test]
+    fn test_input_after_label() {
+        let selector = selector_at(10, 350);
+        assert_eq!(
+            selector,
+            r#"[text="Password"] + [class="android.widget.EditText"]"#
+        );
+        assert_selects_only(&selector, (0, 300, 1080, 400));
+    }
+
+    #[test]
+    fn test_structural_path() {
+        let selector = selector_at(600, 1500);
+        assert_eq!(
+            selector,
+            r#"[class="android.widget.FrameLayout"]:nth-child(1) > [class="android.widget.LinearLayout"]:nth-child(2) > [class="android.view.View"]:nth-child(2)"#
+        );
+        assert_selects_only(&selector, (540, 1000, 1080, 1920));
+    }
+
+    #[test]
+    fn test_no_element_at_point() {
+        assert!(matches!(
+            locate_at(XML, 2000, 10),
+            Err(BochiError::ElementNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_locate_matching_generates_unique_selectors() {
+        let selector = Selector::parse("[class=android.widget.EditText]").unwrap();
+        let report = locate_matching(XML, &selector).unwrap();
+        assert_eq!(report.locations.len(), 2);
+        for location in &report.locations {
+            assert_selects_only(&location.selector, location.element.bounds);
+            for alternative in &location.alternatives {
+                assert_selects_only(alternative, location.element.bounds);
+            }
+        }
+    }
+
+    #[test]
+    fn test_repeated_text_uses_nth_match() {
+        let xml = r##"<hierarchy rotation="0"><node class="android.widget.ListView" resource-id="com.example:id/products" bounds="[0,0][1080,1600]"><node class="android.widget.Button" text="Add to cart" bounds="[0,0][1080,400]" /><node class="android.widget.Button" text="Add to cart" bounds="[0,400][1080,800]" /><node class="android.widget.Button" text="Add to cart" bounds="[0,800][1080,1200]" /><node class="android.widget.Button" text="Add to cart" bounds="[0,1200][1080,1600]" /></node></hierarchy>"##;
+        let report = locate_at(xml, 540, 1000).unwrap();
+        let location = &report.locations[0];
+        assert_eq!(location.selector, r#"[text="Add to cart"]:nth-match(3)"#);
+        // The structural path is still offered as an alternative
+        assert!(location
+            .alternatives
+            .iter()
+            .any(|s| s.contains(":nth-child(")));
+        let matched = find_elements(xml, &Selector::parse(&location.selector).unwrap()).unwrap();
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].bounds, (0, 800, 1080, 1200));
+    }
+
+    #[test]
+    fn test_ambiguous_path_uses_nth_match() {
+        let xml = r##"<hierarchy><node class="A" bounds="[0,0][10,10]"><node class="A" bounds="[0,0][10,10]"><node class="A" bounds="[0,0][5,5]" /></node></node></hierarchy>"##;
+        let doc = parse_hierarchy(xml).unwrap();
+        let middle = doc.descendants().filter(|n| n.is_element()).nth(2).unwrap();
+        let selectors = unique_selectors(middle);
+        assert_eq!(
+            selectors,
+            vec![r#":is([class="A"]:nth-child(1) > [class="A"]:nth-child(1)):nth-match(1)"#]
+        );
+        let matched = find_elements(xml, &Selector::parse(&selectors[0]).unwrap()).unwrap();
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].bounds, (0, 0, 10, 10));
+    }
+}
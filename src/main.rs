@@ -1,14 +1,20 @@
-mod adb_utils;
-mod selector;
-mod ui_element;
-
-use adb_utils::{format_adb_error, get_adb_command};
+use bochi::dump::{format_hierarchy, hierarchy_to_json, DumpFormat};
+use bochi::explain::{explain, EXPLAIN_LIMIT};
+use bochi::flow::{junit_report, read_flow_file, run_flow};
+use bochi::locate::{locate_at, locate_matching, parse_point};
+use bochi::report::OutputFormat;
+use bochi::script::{
+    dry_run_step, parse_script, read_script_file, run_script, run_step, ScriptError, Step,
+    StepCommand,
+};
+use bochi::ui_element::read_ui_hierarchy_file;
+use bochi::{AdbDevice, BochiError, DeviceBackend, Selector, ShellSession};
 use clap::Parser;
-use selector::Selector;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
 use std::process::exit;
-use std::thread;
-use std::time::{Duration, Instant};
-use ui_element::{find_elements, find_elements_with_descendants, get_ui_hierarchy, is_element_visible, UiElement};
+use std::time::Instant;
 
 #[derive(clap::ValueEnum, Clone, Debug)]
 enum BochiCommand {
@@ -33,11 +39,29 @@ enum BochiCommand {
     /// Scroll down until the target element is visible
     #[value(name = "scrollDown")]
     ScrollDown,
+    /// Print the full UI hierarchy (no selector needed)
+    #[value(name = "dump")]
+    Dump,
+    /// Show which steps of the selector each matching or nearly matching element passes
+    #[value(name = "explain")]
+    Explain,
+    /// Generate a unique selector for the element at --at x,y or for each element matching -e
+    #[value(name = "locate")]
+    Locate,
+    /// Run the steps of a --script file one after another on one device connection
+    #[value(name = "run")]
+    Run,
+    /// Run the YAML test flows given with --flow and report each as passed or failed
+    #[value(name = "test")]
+    Test,
 }
 
 #[derive(Parser)]
 #[command(name = "bochi")]
 #[command(about = "A CLI tool for AI agents to control Android devices via ADB")]
+#[command(
+    override_usage = "bochi [OPTIONS] --selector <SELECTOR> --command <COMMAND>\n       bochi [OPTIONS] --command dump [--format <FORMAT>]\n       bochi [OPTIONS] --command locate --at <X,Y>\n       bochi [OPTIONS] --command run --script <PATH>\n       bochi [OPTIONS] --command test --flow <PATH>... [--junit <PATH>]"
+)]
 struct Cli {
     #[arg(short, long, help_heading = "Common Parameters", display_order = 1)]
     serial: Option<String>,
@@ -55,12 +79,21 @@ Supports CSS-like syntax:
  - `sel1,sel2` - OR of multiple selectors
  - `:has(cond)` - select nodes with a descendant matching cond
  - `:not(cond)` - select nodes that do NOT match cond
+ - `:is(sel1,sel2)` / `:where(...)` - OR inside a compound or after a combinator
  - `ancestor > child` - child combinator (direct children only)
- - `ancestor descendant` - descendant combinator (any depth)"#,
+ - `ancestor descendant` - descendant combinator (any depth)
+ - `prev + next` / `prev ~ next` - next sibling right after / anywhere after prev
+ - `[attr~="regex"]` - attribute matches a regular expression
+ - `[attr="value" i]` - case-insensitive match (`n` normalizes whitespace)
+ - `[attr]` / `[attr!=value]` - attribute is non-empty / differs from value
+ - `[attr>=3]` - numeric comparison (`>`, `>=`, `<`, `<=`), also on `width`/`height`
+ - `:first-child` / `:last-child` / `:only-child` / `:nth-child(An+B)` - position among siblings
+ - `:nth-match(n)` - the n-th (1-based) element in document order matching the rest
+ - `:right-of(sel)` / `:left-of` / `:above` / `:below` / `:near(sel, px)` / `:inside` - layout relative to sel"#,
         help_heading = "Common Parameters",
         display_order = 2
     )]
-    selector: String,
+    selector: Option<String>,
 
     #[arg(
         short = 'c',
@@ -106,385 +139,503 @@ Example: --scroll-target '[text="Submit Button"]'
         display_order = 21
     )]
     scroll_target: Option<String>,
-}
 
-fn tap_element(serial: Option<&str>, element: &UiElement) -> Result<(), String> {
-    let (x1, y1, x2, y2) = element.bounds;
-    let center_x = (x1 + x2) / 2;
-    let center_y = (y1 + y2) / 2;
-
-    let output = get_adb_command(serial)
-        .map_err(|e| format_adb_error(&e))?
-        .args([
-            "shell",
-            "input",
-            "tap",
-            &center_x.to_string(),
-            &center_y.to_string(),
-        ])
-        .output()
-        .map_err(|e| format_adb_error(&e))?;
-
-    if !output.status.success() {
-        return Err(format!(
-            "Tap command failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ));
-    }
+    /// Evaluate against a saved uiautomator XML dump instead of the device
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Evaluate against a saved uiautomator XML dump (use - for stdin)",
+        long_help = r##"Evaluate the selector against a previously captured uiautomator XML dump
+instead of fetching the hierarchy from the device. Use `-` to read the dump from stdin.
+
+No device is needed in this mode. `waitFor` prints the matched elements, and all other
+commands are dry-run: they report the element and coordinates they would act on
+without sending any input.
+"##,
+        help_heading = "Common Parameters",
+        display_order = 5
+    )]
+    xml_file: Option<String>,
 
-    Ok(())
+    /// Output format for the dump command
+    #[arg(
+        long,
+        value_enum,
+        default_value = "xml",
+        help_heading = "Command-Specific Parameters",
+        display_order = 22
+    )]
+    format: DumpFormat,
+
+    /// Output mode for command results
+    #[arg(
+        short = 'o',
+        long,
+        value_enum,
+        default_value = "text",
+        help = "Output mode: human-readable text or one JSON result object",
+        long_help = r##"Output mode for command results.
+
+`text` keeps the human-readable output. `json` prints a single JSON object with the
+matched elements (parsed attributes and bounds), the action performed and its
+coordinates, the elapsed time in milliseconds and the number of UI hierarchy polls.
+"##,
+        help_heading = "Common Parameters",
+        display_order = 6
+    )]
+    output: OutputFormat,
+
+    /// Which of the matched elements to use (0-based, document order)
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Act on the N-th matched element (0-based) instead of the first",
+        long_help = r##"Act on the N-th element matched by -e/--selector (0-based, in document order)
+instead of the first one. Actions wait until at least N+1 elements match; `waitFor`
+prints only that element, and scroll commands swipe on that scroll container.
+Equivalent to `:is(<selector>):nth-match(N+1)`. A plain `:nth-match()` counts only
+the matches of the compound it is attached to, ignoring combinators around it.
+"##,
+        help_heading = "Common Parameters",
+        display_order = 7
+    )]
+    index: Option<usize>,
+
+    /// Screen coordinates of the element for the locate command
+    #[arg(
+        long,
+        value_name = "X,Y",
+        value_parser = parse_point,
+        help = "Screen coordinates of the element to generate a selector for (locate command)",
+        long_help = r##"Screen coordinates of the element to generate a selector for, e.g. --at 540,1200.
+
+The locate command picks the innermost element whose bounds contain the point and
+prints the shortest selector that matches only that element, preferring resource-id,
+then content-desc, then text, then the position relative to a label or container,
+then the n-th element with the same text or content-desc.
+Use -e/--selector instead to generate selectors for every element it matches.
+"##,
+        help_heading = "Command-Specific Parameters",
+        display_order = 23
+    )]
+    at: Option<(i32, i32)>,
+
+    /// Step file for the run command
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "File with one step per line for the run command (use - for stdin)",
+        long_help = r##"File with the steps for the run command, one per line (use - for stdin).
+
+Each step is a command, a selector and its arguments, e.g.
+
+    waitFor [text=Login]
+    inputText [resource-id=com.example:id/user] "alice"
+    tap [text=Login]
+    scrollDown [scrollable=true] --scroll-target [text="Item 30"] --timeout 10
+
+Steps take --index, --timeout, --scroll-target and --print-descendants like the
+command line; lines starting with # are comments. The steps share one device
+connection, the screen size and the last fetched hierarchy, and the run stops at
+the first failing step.
+"##,
+        help_heading = "Command-Specific Parameters",
+        display_order = 24
+    )]
+    script: Option<String>,
+
+    /// Flow files for the test command
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "YAML test flow for the test command (repeat for several flows)",
+        long_help = r##"YAML test flow for the test command; repeat to run several flows in order.
+
+A flow is an optional configuration and a list of steps, e.g.
+
+    appId: com.example
+    name: Login
+    onFailure: [screenshot, dump]
+    ---
+    - launchApp
+    - inputText: { selector: "[resource-id=com.example:id/user]", text: alice }
+    - tap: "[text=Login]"
+    - assertVisible: "[text=Home]"
+    - assertNotVisible: "[class$=ProgressBar]"
+    - assertText: { selector: "[resource-id=com.example:id/title]", text: "Hi alice" }
+
+Steps can also be written in the --script syntax, e.g. - tap [text=OK] --index 1.
+A failing flow saves its onFailure screenshot and dump and the next flow runs.
+"##,
+        help_heading = "Command-Specific Parameters",
+        display_order = 25
+    )]
+    flow: Vec<String>,
+
+    /// JUnit report path for the test command
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Write a JUnit XML report of the test command to this file",
+        help_heading = "Command-Specific Parameters",
+        display_order = 26
+    )]
+    junit: Option<String>,
+
+    /// Directory for the failure screenshots and dumps of the test command
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = ".",
+        help = "Directory for the onFailure screenshots and dumps of the test command",
+        help_heading = "Command-Specific Parameters",
+        display_order = 27
+    )]
+    artifacts_dir: String,
 }
 
-fn long_tap_element(
-    serial: Option<&str>,
-    element: &UiElement,
-    duration_ms: u64,
-) -> Result<(), String> {
-    let (x1, y1, x2, y2) = element.bounds;
-    let center_x = (x1 + x2) / 2;
-    let center_y = (y1 + y2) / 2;
-
-    // Use swipe with same start and end position to simulate a long press
-    let output = get_adb_command(serial)
-        .map_err(|e| format_adb_error(&e))?
-        .args([
-            "shell",
-            "input",
-            "swipe",
-            &center_x.to_string(),
-            &center_y.to_string(),
-            &center_x.to_string(),
-            &center_y.to_string(),
-            &duration_ms.to_string(),
-        ])
-        .output()
-        .map_err(|e| format_adb_error(&e))?;
-
-    if !output.status.success() {
-        return Err(format!(
-            "Long tap command failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ));
+/// Name of the command as accepted by `-c`
+fn command_name(command: &BochiCommand) -> &'static str {
+    match command {
+        BochiCommand::WaitFor => "waitFor",
+        BochiCommand::Tap => "tap",
+        BochiCommand::InputText => "inputText",
+        BochiCommand::LongTap => "longTap",
+        BochiCommand::DoubleTap => "doubleTap",
+        BochiCommand::ScrollUp => "scrollUp",
+        BochiCommand::ScrollDown => "scrollDown",
+        BochiCommand::Dump => "dump",
+        BochiCommand::Explain => "explain",
+        BochiCommand::Locate => "locate",
+        BochiCommand::Run => "run",
+        BochiCommand::Test => "test",
     }
-
-    Ok(())
 }
 
-fn double_tap_element(serial: Option<&str>, element: &UiElement) -> Result<(), String> {
-    // First tap
-    tap_element(serial, element)?;
+fn required_text(cli: &Cli) -> Result<&str, BochiError> {
+    cli.text.as_deref().ok_or_else(|| {
+        BochiError::InvalidArgument(
+            "--text parameter is required for inputText command".to_string(),
+        )
+    })
+}
 
-    // Small delay between taps (typical double tap timing)
-    thread::sleep(Duration::from_millis(100));
+fn required_scroll_target(cli: &Cli) -> Result<Selector, BochiError> {
+    match &cli.scroll_target {
+        Some(target_str) => Selector::parse(target_str).map_err(BochiError::SelectorParse),
+        None => Err(BochiError::InvalidArgument(format!(
+            "--scroll-target parameter is required for {} command",
+            command_name(&cli.command)
+        ))),
+    }
+}
 
-    // Second tap
-    tap_element(serial, element)
+/// The step a device command given on the command line describes
+fn cli_step(cli: &Cli, selector: Selector) -> Result<Step, BochiError> {
+    let command = match cli.command {
+        BochiCommand::WaitFor => StepCommand::WaitFor,
+        BochiCommand::Tap => StepCommand::Tap,
+        BochiCommand::InputText => StepCommand::InputText(required_text(cli)?.to_string()),
+        BochiCommand::LongTap => StepCommand::LongTap,
+        BochiCommand::DoubleTap => StepCommand::DoubleTap,
+        BochiCommand::ScrollUp => StepCommand::ScrollUp(required_scroll_target(cli)?),
+        BochiCommand::ScrollDown => StepCommand::ScrollDown(required_scroll_target(cli)?),
+        BochiCommand::Dump
+        | BochiCommand::Explain
+        | BochiCommand::Locate
+        | BochiCommand::Run
+        | BochiCommand::Test => {
+            unreachable!("{} is handled separately", command_name(&cli.command))
+        }
+    };
+    Ok(Step {
+        index: cli.index,
+        print_descendants: cli.print_descendants,
+        ..Step::new(command, selector)
+    })
 }
 
-/// Get the screen dimensions (width, height)
-fn get_screen_dimensions(serial: Option<&str>) -> Result<(i32, i32), String> {
-    let output = get_adb_command(serial)
-        .map_err(|e| format_adb_error(&e))?
-        .args(["shell", "wm", "size"])
-        .output()
-        .map_err(|e| format_adb_error(&e))?;
-
-    if !output.status.success() {
-        return Err(format!(
-            "Failed to get screen size: {}",
-            String::from_utf8_lossy(&output.stderr)
+/// Run the steps of `--script` on one device connection, printing each step as it finishes
+fn run_script_file(cli: &Cli) -> Result<(), BochiError> {
+    let start = Instant::now();
+    let path = cli.script.as_deref().ok_or_else(|| {
+        BochiError::InvalidArgument("--script parameter is required for run command".to_string())
+    })?;
+    if cli.xml_file.is_some() {
+        return Err(BochiError::InvalidArgument(
+            "--xml-file is not supported by the run command".to_string(),
         ));
     }
-
-    let output_str = String::from_utf8_lossy(&output.stdout);
-    // Parse "Physical size: 1080x1920" or "Override size: 1080x1920"
-    for line in output_str.lines() {
-        if let Some(idx) = line.find("size: ") {
-            let size_part = &line[idx + 6..];
-            let parts: Vec<&str> = size_part.split('x').collect();
-            if parts.len() == 2 {
-                if let (Ok(width), Ok(height)) = (parts[0].trim().parse(), parts[1].trim().parse()) {
-                    return Ok((width, height));
-                }
+    let script = read_script_file(path)?;
+    let steps = parse_script(&script).unwrap_or_else(|e| exit_with_script_error(cli, e, &[]));
+
+    let device = ShellSession::open(cli.serial.as_deref())?;
+    let mut completed = Vec::new();
+    let result = run_script(&device, &steps, cli.timeout, |step, report| {
+        if cli.output == OutputFormat::Text {
+            println!(
+                "[{}/{}] {} ({} ms)",
+                completed.len() + 1,
+                steps.len(),
+                step,
+                report.elapsed.as_millis()
+            );
+            for line in report.text_lines() {
+                println!("{}", line);
             }
         }
+        completed.push(report.to_json());
+    });
+    if let Err(e) = result {
+        exit_with_script_error(cli, e, &completed);
     }
 
-    Err(format!("Could not parse screen size from: {}", output_str))
+    if cli.output == OutputFormat::Json {
+        let report = json!({
+            "command": command_name(&cli.command),
+            "success": true,
+            "steps": completed,
+            "elapsed_ms": start.elapsed().as_millis() as u64,
+        });
+        println!("{}", report);
+    }
+    Ok(())
 }
 
-/// Perform a swipe gesture
-fn perform_swipe(
-    serial: Option<&str>,
-    x1: i32,
-    y1: i32,
-    x2: i32,
-    y2: i32,
-    duration_ms: u64,
-) -> Result<(), String> {
-    let output = get_adb_command(serial)
-        .map_err(|e| format_adb_error(&e))?
-        .args([
-            "shell",
-            "input",
-            "swipe",
-            &x1.to_string(),
-            &y1.to_string(),
-            &x2.to_string(),
-            &y2.to_string(),
-            &duration_ms.to_string(),
-        ])
-        .output()
-        .map_err(|e| format_adb_error(&e))?;
-
-    if !output.status.success() {
-        return Err(format!(
-            "Swipe command failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ));
+/// Print the error of a failed script step and exit with its exit code. In JSON mode the
+/// error body also names the failing line and lists the steps completed before it.
+fn exit_with_script_error(cli: &Cli, e: ScriptError, completed: &[Value]) -> ! {
+    match cli.output {
+        OutputFormat::Text => eprintln!("Error: {}", e),
+        OutputFormat::Json => {
+            let mut report = e.error.to_json(command_name(&cli.command));
+            report["error"]["line"] = json!(e.line);
+            report["steps"] = json!(completed);
+            println!("{}", report);
+        }
     }
-
-    Ok(())
+    exit(e.error.exit_code())
 }
 
-/// Scroll gradually until the target element is visible
-fn scroll_until_visible(
-    serial: Option<&str>,
-    scroll_selector: &Selector,
-    target_selector: &Selector,
-    timeout_secs: u64,
-    scroll_up: bool,
-) -> Result<(), String> {
+/// Run the `--flow` files one after another on one device connection. A failing flow
+/// does not stop the others; the exit code is the one of the first failure.
+fn run_flow_files(cli: &Cli) -> Result<(), BochiError> {
     let start = Instant::now();
-    let timeout = Duration::from_secs(timeout_secs);
-
-    // Get screen dimensions
-    let (screen_width, screen_height) = get_screen_dimensions(serial)?;
-
-    // Calculate swipe parameters
-    // Swipe from 70% to 30% of screen height (or reverse for scroll up)
-    let start_y = if scroll_up {
-        screen_height * 3 / 10 // Start from 30% from top
-    } else {
-        screen_height * 7 / 10 // Start from 70% from top
-    };
-    let end_y = if scroll_up {
-        screen_height * 7 / 10 // End at 70% from top (swiping down)
-    } else {
-        screen_height * 3 / 10 // End at 30% from top (swiping up)
-    };
-    let _center_x = screen_width / 2;
-
-    // Swipe duration in ms - moderate speed for smooth scrolling
-    let swipe_duration = 300;
+    if cli.flow.is_empty() {
+        return Err(BochiError::InvalidArgument(
+            "--flow parameter is required for test command".to_string(),
+        ));
+    }
+    if cli.xml_file.is_some() {
+        return Err(BochiError::InvalidArgument(
+            "--xml-file is not supported by the test command".to_string(),
+        ));
+    }
+    // Check every flow before touching the device
+    let flows = cli
+        .flow
+        .iter()
+        .map(|path| read_flow_file(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let artifacts_dir = Path::new(&cli.artifacts_dir);
+    if flows.iter().any(|flow| !flow.on_failure.is_empty()) {
+        fs::create_dir_all(artifacts_dir).map_err(|e| {
+            BochiError::Other(format!(
+                "Failed to create artifacts directory {}: {}",
+                cli.artifacts_dir, e
+            ))
+        })?;
+    }
 
-    loop {
-        if start.elapsed() > timeout {
-            return Err(format!(
-                "Timeout waiting for target element to become visible: {:?}",
-                target_selector
-            ));
+    let device = ShellSession::open(cli.serial.as_deref())?;
+    let mut results = Vec::new();
+    for flow in &flows {
+        if cli.output == OutputFormat::Text {
+            println!("Flow: {} ({})", flow.name, flow.file);
         }
-
-        // Get current UI hierarchy
-        let xml = get_ui_hierarchy(serial)?;
-
-        // First, check if target is already visible
-        let target_elements = find_elements(&xml, target_selector)?;
-        if let Some(target) = target_elements.first() {
-            if is_element_visible(target, screen_width, screen_height) {
-                return Ok(());
+        let result = run_flow(&device, flow, cli.timeout, artifacts_dir, |n, step| {
+            if cli.output == OutputFormat::Text {
+                println!(
+                    "  [{}/{}] {} ({} ms)",
+                    n,
+                    flow.steps.len(),
+                    step.description,
+                    step.report.elapsed.as_millis()
+                );
+            }
+        });
+        if cli.output == OutputFormat::Text {
+            match &result.failure {
+                None => println!("  PASSED ({} ms)", result.elapsed.as_millis()),
+                Some(failure) => {
+                    println!("  FAILED at {}", failure);
+                    for artifact in &result.artifacts {
+                        match &artifact.error {
+                            None => println!("  Saved {}", artifact.path.display()),
+                            Some(e) => {
+                                println!("  Failed to save {}: {}", artifact.path.display(), e)
+                            }
+                        }
+                    }
+                }
             }
         }
+        results.push(result);
+    }
 
-        // Find scrollable element (the element we swipe on)
-        let scroll_elements = find_elements(&xml, scroll_selector)?;
-        if scroll_elements.is_empty() {
-            return Err(format!(
-                "Scroll element not found with selector: {:?}",
-                scroll_selector
-            ));
+    if let Some(path) = &cli.junit {
+        fs::write(path, junit_report(&results)).map_err(|e| {
+            BochiError::Other(format!("Failed to write JUnit report {}: {}", path, e))
+        })?;
+    }
+    let passed = results.iter().filter(|r| r.failure.is_none()).count();
+    match cli.output {
+        OutputFormat::Text => println!("{} of {} flows passed", passed, results.len()),
+        OutputFormat::Json => {
+            let report = json!({
+                "command": command_name(&cli.command),
+                "success": passed == results.len(),
+                "flows": results.iter().map(|r| r.to_json()).collect::<Vec<_>>(),
+                "elapsed_ms": start.elapsed().as_millis() as u64,
+            });
+            println!("{}", report);
         }
-
-        // Perform swipe on the first scrollable element's center area
-        let scroll_element = &scroll_elements[0];
-        let (ex1, ey1, ex2, ey2) = scroll_element.bounds;
-        let swipe_x = (ex1 + ex2) / 2;
-
-        // Calculate swipe coordinates relative to the scrollable element
-        let actual_start_y = if scroll_up {
-            ey1 + screen_height / 5 // Start lower within the element
-        } else {
-            ey2 - screen_height / 5 // Start higher within the element
-        };
-        let actual_end_y = if scroll_up {
-            (ey2 - screen_height / 5).min(start_y + (end_y - start_y).abs())
-        } else {
-            (ey1 + screen_height / 5).max(start_y - (end_y - start_y).abs())
-        };
-
-        // Clamp coordinates to be within screen bounds
-        let actual_start_y = actual_start_y.max(0).min(screen_height);
-        let actual_end_y = actual_end_y.max(0).min(screen_height);
-
-        perform_swipe(
-            serial,
-            swipe_x,
-            actual_start_y,
-            swipe_x,
-            actual_end_y,
-            swipe_duration,
-        )?;
-
-        // Small delay between swipes to let UI settle
-        thread::sleep(Duration::from_millis(500));
     }
+    if let Some(failure) = results.iter().find_map(|r| r.failure.as_ref()) {
+        exit(failure.error.exit_code());
+    }
+    Ok(())
 }
 
-fn input_text_element(serial: Option<&str>, element: &UiElement, text: &str) -> Result<(), String> {
-    // First tap to focus on the element
-    tap_element(serial, element)?;
-
-    // Small delay to ensure the element is focused
-    thread::sleep(Duration::from_millis(100));
-
-    // Then type the text
-    let output = get_adb_command(serial)
-        .map_err(|e| format_adb_error(&e))?
-        .args(["shell", "input", "text", text])
-        .output()
-        .map_err(|e| format_adb_error(&e))?;
-
-    if !output.status.success() {
-        return Err(format!(
-            "Input text command failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ));
+fn dump_hierarchy(cli: &Cli) -> Result<(), BochiError> {
+    let start = Instant::now();
+    let xml = match &cli.xml_file {
+        Some(path) => read_ui_hierarchy_file(path)?,
+        None => AdbDevice::new(cli.serial.as_deref()).ui_hierarchy()?,
+    };
+    match cli.output {
+        OutputFormat::Text => println!("{}", format_hierarchy(&xml, &cli.format)?),
+        OutputFormat::Json => {
+            let report = json!({
+                "command": command_name(&cli.command),
+                "success": true,
+                "hierarchy": hierarchy_to_json(&xml)?,
+                "elapsed_ms": start.elapsed().as_millis() as u64,
+                "polls": 1,
+            });
+            println!("{}", report);
+        }
     }
-
     Ok(())
 }
 
-fn wait_for_element(
-    serial: Option<&str>,
-    selector: &Selector,
-    timeout_secs: u64,
-) -> Result<UiElement, String> {
-    wait_for_elements(serial, selector, timeout_secs, false)
-        .map(|elements| elements.into_iter().next().unwrap())
+/// Fetch the hierarchy once and show which selector steps the closest elements pass or fail
+fn explain_selector(cli: &Cli, selector: &Selector) -> Result<(), BochiError> {
+    let start = Instant::now();
+    let xml = match &cli.xml_file {
+        Some(path) => read_ui_hierarchy_file(path)?,
+        None => AdbDevice::new(cli.serial.as_deref()).ui_hierarchy()?,
+    };
+    let mut explanation = explain(&xml, selector, EXPLAIN_LIMIT)?;
+    explanation.elapsed = start.elapsed();
+    match cli.output {
+        OutputFormat::Text => {
+            for line in explanation.text_lines() {
+                println!("{}", line);
+            }
+        }
+        OutputFormat::Json => println!("{}", explanation.to_json()),
+    }
+    Ok(())
 }
 
-fn wait_for_elements(
-    serial: Option<&str>,
-    selector: &Selector,
-    timeout_secs: u64,
-    with_descendants: bool,
-) -> Result<Vec<UiElement>, String> {
+/// Fetch the hierarchy once and generate selectors for the element at `--at`
+/// or for the elements matching `--selector`
+fn locate_elements(cli: &Cli) -> Result<(), BochiError> {
     let start = Instant::now();
-    let timeout = Duration::from_secs(timeout_secs);
-
-    loop {
-        if start.elapsed() > timeout {
-            return Err(format!(
-                "Timeout waiting for element with selector: {:?}",
-                selector
-            ));
-        }
+    let selector = cli
+        .selector
+        .as_deref()
+        .map(Selector::parse)
+        .transpose()
+        .map_err(BochiError::SelectorParse)?;
+    if cli.at.is_some() == selector.is_some() {
+        return Err(BochiError::InvalidArgument(
+            "locate command needs either --at or --selector (but not both)".to_string(),
+        ));
+    }
 
-        let xml = get_ui_hierarchy(serial)?;
-        let elements = if with_descendants {
-            find_elements_with_descendants(&xml, selector)?
-        } else {
-            find_elements(&xml, selector)?
-        };
-        if !elements.is_empty() {
-            return Ok(elements);
+    let xml = match &cli.xml_file {
+        Some(path) => read_ui_hierarchy_file(path)?,
+        None => AdbDevice::new(cli.serial.as_deref()).ui_hierarchy()?,
+    };
+    let mut report = match (cli.at, &selector) {
+        (Some((x, y)), _) => locate_at(&xml, x, y)?,
+        (None, Some(selector)) => locate_matching(&xml, selector)?,
+        (None, None) => unreachable!("--at or --selector is checked above"),
+    };
+    report.elapsed = start.elapsed();
+    match cli.output {
+        OutputFormat::Text => {
+            for line in report.text_lines() {
+                println!("{}", line);
+            }
         }
-        thread::sleep(Duration::from_millis(500));
+        OutputFormat::Json => println!("{}", report.to_json()),
     }
+    Ok(())
 }
 
-fn main() {
-    let cli = Cli::parse();
+fn run(cli: &Cli) -> Result<(), BochiError> {
+    match cli.command {
+        BochiCommand::Dump => return dump_hierarchy(cli),
+        BochiCommand::Locate => return locate_elements(cli),
+        BochiCommand::Run => return run_script_file(cli),
+        BochiCommand::Test => return run_flow_files(cli),
+        _ => {}
+    }
 
-    let selector = match Selector::parse(&cli.selector) {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            exit(1);
+    let selector = match &cli.selector {
+        Some(selector_str) => Selector::parse(selector_str).map_err(BochiError::SelectorParse)?,
+        None => {
+            return Err(BochiError::InvalidArgument(format!(
+                "--selector parameter is required for {} command",
+                command_name(&cli.command)
+            )))
         }
     };
 
-    let result = match cli.command {
-        BochiCommand::WaitFor => wait_for_elements(
-            cli.serial.as_deref(),
-            &selector,
+    if matches!(cli.command, BochiCommand::Explain) {
+        return explain_selector(cli, &selector);
+    }
+
+    let start = Instant::now();
+    let step = cli_step(cli, selector)?;
+    let mut report = match &cli.xml_file {
+        Some(path) => dry_run_step(&step, &read_ui_hierarchy_file(path)?)?,
+        // One shell for all the dumps and input of the command
+        None => run_step(
+            &ShellSession::open(cli.serial.as_deref())?,
+            &step,
             cli.timeout,
-            cli.print_descendants,
-        )
-        .map(|elements| {
-            for element in elements {
-                println!("{}", element.raw_xml);
-            }
-        }),
-        BochiCommand::Tap => {
-            match wait_for_element(cli.serial.as_deref(), &selector, cli.timeout) {
-                Ok(element) => tap_element(cli.serial.as_deref(), &element),
-                Err(e) => Err(e),
-            }
-        }
-        BochiCommand::InputText => match cli.text {
-            Some(text) => match wait_for_element(cli.serial.as_deref(), &selector, cli.timeout) {
-                Ok(element) => input_text_element(cli.serial.as_deref(), &element, &text),
-                Err(e) => Err(e),
-            },
-            None => Err("--text parameter is required for inputText command".to_string()),
-        },
-        BochiCommand::LongTap => {
-            match wait_for_element(cli.serial.as_deref(), &selector, cli.timeout) {
-                Ok(element) => long_tap_element(cli.serial.as_deref(), &element, 1000),
-                Err(e) => Err(e),
-            }
-        }
-        BochiCommand::DoubleTap => {
-            match wait_for_element(cli.serial.as_deref(), &selector, cli.timeout) {
-                Ok(element) => double_tap_element(cli.serial.as_deref(), &element),
-                Err(e) => Err(e),
-            }
-        }
-        BochiCommand::ScrollUp => match cli.scroll_target {
-            Some(target_str) => match Selector::parse(&target_str) {
-                Ok(target_selector) => scroll_until_visible(
-                    cli.serial.as_deref(),
-                    &selector,
-                    &target_selector,
-                    cli.timeout,
-                    true, // scroll_up = true
-                ),
-                Err(e) => Err(format!("Failed to parse scroll target selector: {}", e)),
-            },
-            None => Err("--scroll-target parameter is required for scrollUp command".to_string()),
-        },
-        BochiCommand::ScrollDown => match cli.scroll_target {
-            Some(target_str) => match Selector::parse(&target_str) {
-                Ok(target_selector) => scroll_until_visible(
-                    cli.serial.as_deref(),
-                    &selector,
-                    &target_selector,
-                    cli.timeout,
-                    false, // scroll_up = false
-                ),
-                Err(e) => Err(format!("Failed to parse scroll target selector: {}", e)),
-            },
-            None => Err("--scroll-target parameter is required for scrollDown command".to_string()),
-        },
+        )?,
     };
+    report.elapsed = start.elapsed();
+    report.print(&cli.output);
+    Ok(())
+}
 
-    match result {
+fn main() {
+    let cli = Cli::parse();
+
+    match run(&cli) {
         Ok(()) => exit(0),
         Err(e) => {
-            eprintln!("Error: {}", e);
-            exit(1);
+            match cli.output {
+                OutputFormat::Text => eprintln!("Error: {}", e),
+                OutputFormat::Json => println!("{}", e.to_json(command_name(&cli.command))),
+            }
+            exit(e.exit_code());
         }
     }
 }
@@ -0,0 +1,259 @@
+use crate::ui_element::UiElement;
+use serde_json::{json, Map, Value};
+use std::time::Duration;
+
+/// Output mode for command results
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// Human-readable output
+    #[value(name = "text")]
+    Text,
+    /// One JSON result object per invocation
+    #[value(name = "json")]
+    Json,
+}
+
+/// Input action performed (or, in dry-run mode, planned) by a command
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Tap {
+        x: i32,
+        y: i32,
+    },
+    LongTap {
+        x: i32,
+        y: i32,
+        duration_ms: u64,
+    },
+    DoubleTap {
+        x: i32,
+        y: i32,
+    },
+    InputText {
+        x: i32,
+        y: i32,
+        text: String,
+    },
+    /// Swipes performed (or, in dry-run mode, planned) before the target became visible
+    Scroll {
+        up: bool,
+        swipes: usize,
+    },
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match self {
+            Action::Tap { .. } => "tap",
+            Action::LongTap { .. } => "longTap",
+            Action::DoubleTap { .. } => "doubleTap",
+            Action::InputText { .. } => "inputText",
+            Action::Scroll { up: true, .. } => "scrollUp",
+            Action::Scroll { up: false, .. } => "scrollDown",
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut object = json!({ "type": self.name() });
+        match self {
+            Action::Tap { x, y } | Action::DoubleTap { x, y } => {
+                object["x"] = json!(x);
+                object["y"] = json!(y);
+            }
+            Action::LongTap { x, y, duration_ms } => {
+                object["x"] = json!(x);
+                object["y"] = json!(y);
+                object["duration_ms"] = json!(duration_ms);
+            }
+            Action::InputText { x, y, text } => {
+                object["x"] = json!(x);
+                object["y"] = json!(y);
+                object["text"] = json!(text);
+            }
+            Action::Scroll { swipes, .. } => {
+                object["swipes"] = json!(swipes);
+            }
+        }
+        object
+    }
+
+    /// Sentence describing what a dry run would have done to the given element
+    fn describe_planned(&self, element: &UiElement) -> String {
+        match self {
+            Action::Tap { x, y } => format!("Would tap at ({}, {})", x, y),
+            Action::LongTap { x, y, .. } => format!("Would long tap at ({}, {})", x, y),
+            Action::DoubleTap { x, y } => format!("Would double tap at ({}, {})", x, y),
+            Action::InputText { x, y, text } => {
+                format!("Would tap at ({}, {}) and input text: {}", x, y, text)
+            }
+            Action::Scroll { swipes: 0, .. } => {
+                format!("Target element found: {}", element.raw_xml)
+            }
+            Action::Scroll { up, .. } => {
+                let (x, y) = element.center();
+                format!(
+                    "Target element not found, would scroll {} on element at ({}, {})",
+                    if *up { "up" } else { "down" },
+                    x,
+                    y
+                )
+            }
+        }
+    }
+}
+
+/// Result of a single command, printed either as text or as a JSON object
+#[derive(Debug)]
+pub struct CommandReport {
+    /// Command name as given to `-c`
+    pub command: &'static str,
+    /// Elements matched by the selector. For scroll commands this is the target element,
+    /// or the scroll container when a dry run finds no target.
+    pub elements: Vec<UiElement>,
+    pub action: Option<Action>,
+    /// True if the hierarchy came from `--xml-file` and no input was sent
+    pub dry_run: bool,
+    pub elapsed: Duration,
+    /// Number of UI hierarchy fetches
+    pub polls: usize,
+}
+
+impl CommandReport {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "command": self.command,
+            "success": true,
+            "dry_run": self.dry_run,
+            "elements": self.elements.iter().map(element_to_json).collect::<Vec<_>>(),
+            "action": self.action.as_ref().map(Action::to_json),
+            "elapsed_ms": self.elapsed.as_millis() as u64,
+            "polls": self.polls,
+        })
+    }
+
+    /// Lines printed in text mode: matched elements for `waitFor`,
+    /// the planned action for dry runs and nothing for performed actions
+    pub fn text_lines(&self) -> Vec<String> {
+        match (&self.action, self.dry_run) {
+            (None, _) => self.elements.iter().map(|e| e.raw_xml.clone()).collect(),
+            (Some(action), true) => self
+                .elements
+                .first()
+                .map(|element| action.describe_planned(element))
+                .into_iter()
+                .collect(),
+            (Some(_), false) => Vec::new(),
+        }
+    }
+
+    pub fn print(&self, format: &OutputFormat) {
+        match format {
+            OutputFormat::Text => {
+                for line in self.text_lines() {
+                    println!("{}", line);
+                }
+            }
+            OutputFormat::Json => println!("{}", self.to_json()),
+        }
+    }
+}
+
+pub fn element_to_json(element: &UiElement) -> Value {
+    let (x1, y1, x2, y2) = element.bounds;
+    let attributes: Map<String, Value> = element
+        .attributes
+        .iter()
+        .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+        .collect();
+    json!({
+        "bounds": [x1, y1, x2, y2],
+        "attributes": attributes,
+        "xml": element.raw_xml,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element() -> UiElement {
+        UiElement {
+            bounds: (0, 10, 100, 50),
+            raw_xml: r##"<node text="OK" bounds="[0,10][100,50]" />"##.to_string(),
+            attributes: vec![
+                ("text".to_string(), "OK".to_string()),
+                ("bounds".to_string(), "[0,10][100,50]".to_string()),
+            ],
+            text: "OK".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn report(action: Option<Action>, dry_run: bool) -> CommandReport {
+        CommandReport {
+            command: "tap",
+            elements: vec![element()],
+            action,
+            dry_run,
+            elapsed: Duration::from_millis(1500),
+            polls: 3,
+        }
+    }
+
+    #[test]
+    fn test_report_json() {
+        let value = report(Some(Action::Tap { x: 50, y: 30 }), false).to_json();
+        assert_eq!(value["command"], "tap");
+        assert_eq!(value["success"], true);
+        assert_eq!(value["dry_run"], false);
+        assert_eq!(value["elapsed_ms"], 1500);
+        assert_eq!(value["polls"], 3);
+        assert_eq!(value["action"], json!({ "type": "tap", "x": 50, "y": 30 }));
+        assert_eq!(value["elements"][0]["bounds"], json!([0, 10, 100, 50]));
+        assert_eq!(value["elements"][0]["attributes"]["text"], "OK");
+    }
+
+    #[test]
+    fn test_report_json_without_action() {
+        let value = report(None, false).to_json();
+        assert_eq!(value["action"], Value::Null);
+    }
+
+    #[test]
+    fn test_report_text_lines() {
+        assert_eq!(report(None, false).text_lines(), vec![element().raw_xml]);
+        assert!(report(Some(Action::Tap { x: 50, y: 30 }), false)
+            .text_lines()
+            .is_empty());
+        assert_eq!(
+            report(Some(Action::Tap { x: 50, y: 30 }), true).text_lines(),
+            vec!["Would tap at (50, 30)".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_report_text_lines_scroll_dry_run() {
+        let not_found = report(
+            Some(Action::Scroll {
+                up: false,
+                swipes: 1,
+            }),
+            true,
+        );
+        assert_eq!(
+            not_found.text_lines(),
+            vec!["Target element not found, would scroll down on element at (50, 30)".to_string()]
+        );
+        let found = report(
+            Some(Action::Scroll {
+                up: false,
+                swipes: 0,
+            }),
+            true,
+        );
+        assert_eq!(
+            found.text_lines(),
+            vec![format!("Target element found: {}", element().raw_xml)]
+        );
+    }
+}
@@ -0,0 +1,829 @@
+use crate::backend::DeviceBackend;
+use crate::device::{
+    double_tap_element, index_note, input_text_element, long_tap_element, scroll_until_visible,
+    tap_element, wait_for_element, wait_for_elements, LONG_TAP_DURATION_MS,
+};
+use crate::error::BochiError;
+use crate::report::{Action, CommandReport};
+use crate::selector::{quote, Selector};
+use crate::ui_element::{find_elements, find_elements_with_descendants, UiElement};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+/// Commands that can be used as script steps
+pub(crate) const STEP_COMMANDS: [&str; 7] = [
+    "waitFor",
+    "tap",
+    "inputText",
+    "longTap",
+    "doubleTap",
+    "scrollUp",
+    "scrollDown",
+];
+
+/// What a step does with the element its selector matches
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepCommand {
+    WaitFor,
+    Tap,
+    InputText(String),
+    LongTap,
+    DoubleTap,
+    /// Scroll up on the matched container until the target selector is visible
+    ScrollUp(Selector),
+    ScrollDown(Selector),
+}
+
+impl StepCommand {
+    /// Name of the command as accepted by `-c` and in scripts
+    pub fn name(&self) -> &'static str {
+        match self {
+            StepCommand::WaitFor => "waitFor",
+            StepCommand::Tap => "tap",
+            StepCommand::InputText(_) => "inputText",
+            StepCommand::LongTap => "longTap",
+            StepCommand::DoubleTap => "doubleTap",
+            StepCommand::ScrollUp(_) => "scrollUp",
+            StepCommand::ScrollDown(_) => "scrollDown",
+        }
+    }
+}
+
+/// One device command: a line of a script, or the command given on the command line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub command: StepCommand,
+    pub selector: Selector,
+    /// Which of the matched elements to use (0-based); all of them for `waitFor` when None
+    pub index: Option<usize>,
+    /// Timeout in seconds, overriding the default of the run
+    pub timeout: Option<u64>,
+    /// Report `waitFor` matches with their descendants
+    pub print_descendants: bool,
+    /// 1-based line in the script, 0 for a step from the command line
+    pub line: usize,
+}
+
+impl Step {
+    pub fn new(command: StepCommand, selector: Selector) -> Step {
+        Step {
+            command,
+            selector,
+            index: None,
+            timeout: None,
+            print_descendants: false,
+            line: 0,
+        }
+    }
+
+    /// The input action the step performs on the given element
+    pub fn planned_action(&self, element: &UiElement) -> Option<Action> {
+        let (x, y) = element.center();
+        match &self.command {
+            StepCommand::Tap => Some(Action::Tap { x, y }),
+            StepCommand::InputText(text) => Some(Action::InputText {
+                x,
+                y,
+                text: text.clone(),
+            }),
+            StepCommand::LongTap => Some(Action::LongTap {
+                x,
+                y,
+                duration_ms: LONG_TAP_DURATION_MS,
+            }),
+            StepCommand::DoubleTap => Some(Action::DoubleTap { x, y }),
+            _ => None,
+        }
+    }
+}
+
+/// The step in script syntax, which parses back to the same step
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.command.name(), self.selector)?;
+        match &self.command {
+            StepCommand::InputText(text) => write!(f, " {}", quote(text))?,
+            StepCommand::ScrollUp(target) | StepCommand::ScrollDown(target) => {
+                write!(f, " --scroll-target {}", target)?
+            }
+            _ => {}
+        }
+        if let Some(index) = self.index {
+            write!(f, " --index {}", index)?;
+        }
+        if let Some(timeout) = self.timeout {
+            write!(f, " --timeout {}", timeout)?;
+        }
+        if self.print_descendants {
+            write!(f, " --print-descendants")?;
+        }
+        Ok(())
+    }
+}
+
+/// A script step that could not be parsed or failed to run
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+    /// 1-based line of the step in the script
+    pub line: usize,
+    pub error: BochiError,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// Parse a script: one step per line, written like
+///
+/// ```text
+/// # Log in
+/// waitFor [text=Login]
+/// inputText [resource-id=com.example:id/user] "alice"
+/// tap [text=Login]
+/// scrollDown [scrollable=true] --scroll-target [text="Item 30"]
+/// tap [class$=Button] --index 1 --timeout 10
+/// ```
+///
+/// Empty lines and lines starting with `#` are skipped.
+pub fn parse_script(script: &str) -> Result<Vec<Step>, ScriptError> {
+    let mut steps = Vec::new();
+    for (number, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut step = parse_step(line).map_err(|error| ScriptError {
+            line: number + 1,
+            error,
+        })?;
+        step.line = number + 1;
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+/// Read a script from a file, or from stdin when `path` is `-`
+pub fn read_script_file(path: &str) -> Result<String, BochiError> {
+    if path == "-" {
+        let mut script = String::new();
+        io::stdin()
+            .read_to_string(&mut script)
+            .map_err(|e| BochiError::Other(format!("Failed to read script from stdin: {}", e)))?;
+        Ok(script)
+    } else {
+        fs::read_to_string(path)
+            .map_err(|e| BochiError::Other(format!("Failed to read script file {}: {}", path, e)))
+    }
+}
+
+/// Parse one script line into a step
+pub fn parse_step(line: &str) -> Result<Step, BochiError> {
+    let line = line.trim();
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    if !STEP_COMMANDS.contains(&name) {
+        return Err(BochiError::InvalidArgument(format!(
+            "unknown step command `{}` (expected one of {})",
+            name,
+            STEP_COMMANDS.join(", ")
+        )));
+    }
+    let (selector_text, mut rest) = rest.split_at(argument_start(rest));
+    let selector_text = selector_text.trim();
+    if selector_text.is_empty() {
+        return Err(BochiError::InvalidArgument(format!(
+            "{} step needs a selector",
+            name
+        )));
+    }
+    let selector = Selector::parse(selector_text)?;
+
+    let mut text = None;
+    let mut scroll_target = None;
+    let mut index = None;
+    let mut timeout = None;
+    let mut print_descendants = false;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        if rest.starts_with('"') {
+            let (value, after) = parse_quoted(rest)?;
+            if text.replace(value).is_some() {
+                return Err(BochiError::InvalidArgument(
+                    "only one quoted text is allowed in a step".to_string(),
+                ));
+            }
+            rest = after;
+            continue;
+        }
+        let Some(option) = rest.strip_prefix("--") else {
+            let word = rest.split_whitespace().next().unwrap_or(rest);
+            return Err(BochiError::InvalidArgument(format!(
+                "unexpected `{}`: step options start with --",
+                word
+            )));
+        };
+        let (option, after) = option
+            .split_once(char::is_whitespace)
+            .unwrap_or((option, ""));
+        rest = after;
+        match option {
+            "print-descendants" => print_descendants = true,
+            "scroll-target" => {
+                let (target, after) = rest.split_at(argument_start(rest));
+                scroll_target = Some(Selector::parse(target.trim())?);
+                rest = after;
+            }
+            "index" | "timeout" => {
+                let after = rest.trim_start();
+                let (value, after) = after
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((after, ""));
+                let number = value.parse::<u64>().map_err(|_| {
+                    BochiError::InvalidArgument(format!(
+                        "--{} needs a number, got `{}`",
+                        option, value
+                    ))
+                })?;
+                if option == "index" {
+                    index = Some(number as usize);
+                } else {
+                    timeout = Some(number);
+                }
+                rest = after;
+            }
+            _ => {
+                return Err(BochiError::InvalidArgument(format!(
+                    "unknown step option `--{}` (expected --index, --timeout, --scroll-target or --print-descendants)",
+                    option
+                )))
+            }
+        }
+    }
+
+    let command = match (name, text, scroll_target) {
+        ("inputText", Some(text), None) => StepCommand::InputText(text),
+        ("inputText", None, _) => {
+            return Err(BochiError::InvalidArgument(
+                "inputText step needs the text to type in quotes, e.g. inputText [resource-id=user] \"alice\"".to_string(),
+            ))
+        }
+        ("scrollUp", None, Some(target)) => StepCommand::ScrollUp(target),
+        ("scrollDown", None, Some(target)) => StepCommand::ScrollDown(target),
+        ("scrollUp" | "scrollDown", _, None) => {
+            return Err(BochiError::InvalidArgument(format!(
+                "{} step needs --scroll-target",
+                name
+            )))
+        }
+        ("waitFor", None, None) => StepCommand::WaitFor,
+        ("tap", None, None) => StepCommand::Tap,
+        ("longTap", None, None) => StepCommand::LongTap,
+        ("doubleTap", None, None) => StepCommand::DoubleTap,
+        ("waitFor" | "tap" | "longTap" | "doubleTap" | "scrollUp" | "scrollDown", Some(_), _) => {
+            return Err(BochiError::InvalidArgument(format!(
+                "{} step does not take a text",
+                name
+            )))
+        }
+        ("waitFor" | "tap" | "inputText" | "longTap" | "doubleTap", _, Some(_)) => {
+            return Err(BochiError::InvalidArgument(format!(
+                "{} step does not take --scroll-target",
+                name
+            )))
+        }
+        _ => unreachable!("step command names are checked above"),
+    };
+    if print_descendants && command != StepCommand::WaitFor {
+        return Err(BochiError::InvalidArgument(
+            "--print-descendants only applies to waitFor steps".to_string(),
+        ));
+    }
+
+    Ok(Step {
+        command,
+        selector,
+        index,
+        timeout,
+        print_descendants,
+        line: 0,
+    })
+}
+
+/// Byte offset of the first argument after a selector: a `--option` or a quoted text
+/// starting a word outside of brackets, parentheses and quoted values
+pub(crate) fn argument_start(s: &str) -> usize {
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut word_start = true;
+    for (i, c) in s.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        if depth == 0 && word_start && (c == '"' || s[i..].starts_with("--")) {
+            return i;
+        }
+        match c {
+            '[' | '(' => depth += 1,
+            ']' | ')' => depth = depth.saturating_sub(1),
+            '"' | '\'' => quote = Some(c),
+            _ => {}
+        }
+        word_start = c.is_whitespace();
+    }
+    s.len()
+}
+
+/// Parse the double-quoted string at the start of `s`; `\"` and `\\` are escapes
+/// like in selector values. Returns the value and the rest after the closing quote.
+pub(crate) fn parse_quoted(s: &str) -> Result<(String, &str), BochiError> {
+    let mut value = String::new();
+    let mut chars = s.char_indices().skip(1).peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some((_, '"' | '\'' | '\\'))) => {
+                value.push(chars.next().unwrap().1)
+            }
+            '"' => {
+                let rest = &s[i + 1..];
+                if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+                    return Err(BochiError::InvalidArgument(format!(
+                        "unexpected `{}` after quoted text",
+                        rest.split_whitespace().next().unwrap_or(rest)
+                    )));
+                }
+                return Ok((value, rest));
+            }
+            c => value.push(c),
+        }
+    }
+    Err(BochiError::InvalidArgument(format!(
+        "unterminated quoted text: {}",
+        s
+    )))
+}
+
+/// Run a step against the device and report the elements and action.
+/// `elapsed` in the report is left at zero for the caller to fill in.
+pub fn run_step(
+    device: &dyn DeviceBackend,
+    step: &Step,
+    default_timeout: u64,
+) -> Result<CommandReport, BochiError> {
+    let timeout = step.timeout.unwrap_or(default_timeout);
+    let selector = &step.selector;
+
+    let (elements, action, polls) = match &step.command {
+        StepCommand::WaitFor => {
+            let min_count = step.index.map_or(1, |index| index + 1);
+            let (elements, polls) =
+                wait_for_elements(device, selector, timeout, step.print_descendants, min_count)?;
+            (select_index(elements, step.index), None, polls)
+        }
+        StepCommand::ScrollUp(target) | StepCommand::ScrollDown(target) => {
+            let up = matches!(step.command, StepCommand::ScrollUp(_));
+            let (target, swipes) = scroll_until_visible(
+                device,
+                selector,
+                target,
+                timeout,
+                up,
+                step.index.unwrap_or(0),
+            )?;
+            (
+                vec![target],
+                Some(Action::Scroll { up, swipes }),
+                swipes + 1,
+            )
+        }
+        command => {
+            let (element, polls) =
+                wait_for_element(device, selector, timeout, step.index.unwrap_or(0))?;
+            match command {
+                StepCommand::Tap => tap_element(device, &element)?,
+                StepCommand::InputText(text) => input_text_element(device, &element, text)?,
+                StepCommand::LongTap => long_tap_element(device, &element, LONG_TAP_DURATION_MS)?,
+                _ => double_tap_element(device, &element)?,
+            }
+            let action = step.planned_action(&element);
+            (vec![element], action, polls)
+        }
+    };
+
+    Ok(CommandReport {
+        command: step.command.name(),
+        elements,
+        action,
+        dry_run: false,
+        elapsed: Duration::ZERO,
+        polls,
+    })
+}
+
+/// Evaluate a step against a saved UI hierarchy without touching the device.
+/// Actions are not performed; the report describes the action that would be taken.
+pub fn dry_run_step(step: &Step, xml: &str) -> Result<CommandReport, BochiError> {
+    let with_descendants = step.command == StepCommand::WaitFor && step.print_descendants;
+    let matched = if with_descendants {
+        find_elements_with_descendants(xml, &step.selector)?
+    } else {
+        find_elements(xml, &step.selector)?
+    };
+    let index = step.index.unwrap_or(0);
+    if matched.len() <= index {
+        return Err(BochiError::ElementNotFound(format!(
+            "No element in the UI hierarchy matches selector: {}{}",
+            step.selector,
+            index_note(index, matched.len())
+        )));
+    }
+    let mut elements = select_index(matched, step.index);
+
+    let action = match &step.command {
+        StepCommand::ScrollUp(target_selector) | StepCommand::ScrollDown(target_selector) => {
+            let targets = find_elements(xml, target_selector)?;
+            let swipes = if targets.is_empty() {
+                1
+            } else {
+                elements = targets;
+                0
+            };
+            Some(Action::Scroll {
+                up: matches!(step.command, StepCommand::ScrollUp(_)),
+                swipes,
+            })
+        }
+        _ => step.planned_action(&elements[0]),
+    };
+    if step.command != StepCommand::WaitFor {
+        elements.truncate(1);
+    }
+
+    Ok(CommandReport {
+        command: step.command.name(),
+        elements,
+        action,
+        dry_run: true,
+        elapsed: Duration::ZERO,
+        polls: 1,
+    })
+}
+
+/// Keep only the element chosen by `--index`, or all of them when no index is given
+fn select_index(elements: Vec<UiElement>, index: Option<usize>) -> Vec<UiElement> {
+    match index {
+        Some(index) => elements.into_iter().skip(index).take(1).collect(),
+        None => elements,
+    }
+}
+
+/// Run the steps in order, stopping at the first failure.
+/// `on_step` is called with each step and its report as soon as the step is done.
+///
+/// The steps share the screen size, looked up once, and the hierarchy: a step
+/// starts from the hierarchy the previous one last fetched when no input was sent since.
+pub fn run_script(
+    device: &dyn DeviceBackend,
+    steps: &[Step],
+    default_timeout: u64,
+    mut on_step: impl FnMut(&Step, &CommandReport),
+) -> Result<Vec<CommandReport>, ScriptError> {
+    let device = SharedState::new(device);
+    let mut reports = Vec::new();
+    for step in steps {
+        device.next_step();
+        let start = device.now();
+        let mut report = run_step(&device, step, default_timeout).map_err(|error| ScriptError {
+            line: step.line,
+            error,
+        })?;
+        report.elapsed = device.now() - start;
+        on_step(step, &report);
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
+/// Device wrapper keeping what consecutive steps can share
+pub(crate) struct SharedState<'a> {
+    device: &'a dyn DeviceBackend,
+    screen_size: Cell<Option<(i32, i32)>>,
+    /// The last fetched hierarchy, dropped when input is sent
+    hierarchy: RefCell<Option<String>>,
+    /// Serve `hierarchy` on the next fetch instead of dumping again
+    reuse_hierarchy: Cell<bool>,
+}
+
+impl<'a> SharedState<'a> {
+    pub(crate) fn new(device: &'a dyn DeviceBackend) -> Self {
+        SharedState {
+            device,
+            screen_size: Cell::new(None),
+            hierarchy: RefCell::new(None),
+            reuse_hierarchy: Cell::new(false),
+        }
+    }
+
+    /// Let the next step start from the last fetched hierarchy
+    pub(crate) fn next_step(&self) {
+        self.reuse_hierarchy.set(true);
+    }
+
+    fn input_sent(&self) {
+        self.hierarchy.replace(None);
+    }
+}
+
+impl DeviceBackend for SharedState<'_> {
+    fn ui_hierarchy(&self) -> Result<String, BochiError> {
+        if self.reuse_hierarchy.replace(false) {
+            if let Some(xml) = self.hierarchy.borrow().as_ref() {
+                return Ok(xml.clone());
+            }
+        }
+        let xml = self.device.ui_hierarchy()?;
+        self.hierarchy.replace(Some(xml.clone()));
+        Ok(xml)
+    }
+
+    fn tap(&self, x: i32, y: i32) -> Result<(), BochiError> {
+        self.input_sent();
+        self.device.tap(x, y)
+    }
+
+    fn swipe(
+        &self,
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+        duration_ms: u64,
+    ) -> Result<(), BochiError> {
+        self.input_sent();
+        self.device.swipe(x1, y1, x2, y2, duration_ms)
+    }
+
+    fn input_text(&self, text: &str) -> Result<(), BochiError> {
+        self.input_sent();
+        self.device.input_text(text)
+    }
+
+    fn screen_size(&self) -> Result<(i32, i32), BochiError> {
+        if let Some(size) = self.screen_size.get() {
+            return Ok(size);
+        }
+        let size = self.device.screen_size()?;
+        self.screen_size.set(Some(size));
+        Ok(size)
+    }
+
+    fn shell(&self, args: &[&str]) -> Result<String, BochiError> {
+        self.input_sent();
+        self.device.shell(args)
+    }
+
+    fn screenshot(&self) -> Result<Vec<u8>, BochiError> {
+        self.device.screenshot()
+    }
+
+    fn now(&self) -> Instant {
+        self.device.now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.device.sleep(duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{DeviceEvent, FakeDevice};
+
+    const LOGIN: &str = r##"<?xml version="1.0" encoding="UTF-8"?><hierarchy rotation="0"><node class="android.widget.FrameLayout" bounds="[0,0][1080,1920]"><node class="android.widget.EditText" resource-id="com.example:id/user" bounds="[0,100][1080,200]" /><node class="android.widget.Button" text="Login" bounds="[0,300][200,400]" /></node></hierarchy>"##;
+    const HOME: &str = r##"<?xml version="1.0" encoding="UTF-8"?><hierarchy rotation="0"><node class="android.widget.TextView" text="Home" bounds="[0,0][1080,100]" /></hierarchy>"##;
+
+    fn selector(s: &str) -> Selector {
+        Selector::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_steps() {
+        assert_eq!(
+            parse_step("tap [text=Login]").unwrap(),
+            Step::new(StepCommand::Tap, selector("[text=Login]"))
+        );
+        assert_eq!(
+            parse_step(r#"inputText [resource-id=user] "alice""#).unwrap(),
+            Step::new(
+                StepCommand::InputText("alice".to_string()),
+                selector("[resource-id=user]")
+            )
+        );
+        let step = parse_step(
+            r#"scrollDown [class$=List] [scrollable=true] --scroll-target [text="Item 30"] --index 1 --timeout 5"#,
+        )
+        .unwrap();
+        assert_eq!(
+            step.command,
+            StepCommand::ScrollDown(selector(r#"[text="Item 30"]"#))
+        );
+        assert_eq!(step.selector, selector("[class$=List] [scrollable=true]"));
+        assert_eq!(step.index, Some(1));
+        assert_eq!(step.timeout, Some(5));
+
+        let step = parse_step("waitFor [class=List]:has([text]) --print-descendants").unwrap();
+        assert!(step.print_descendants);
+    }
+
+    #[test]
+    fn test_parse_quoted_text() {
+        let step = parse_step(r#"inputText [text="a -- b"] "say \"hi\" -- \\o/""#).unwrap();
+        assert_eq!(step.selector, selector(r#"[text="a -- b"]"#));
+        assert_eq!(
+            step.command,
+            StepCommand::InputText(r#"say "hi" -- \o/"#.to_string())
+        );
+    }
+
+    #[test]
+    fn test_step_display_round_trip() {
+        for line in [
+            "tap [text=Login]",
+            r#"inputText [resource-id=user] "a \"quoted\" \\""#,
+            r#"scrollUp [scrollable=true] --scroll-target [text="Top"] --index 2 --timeout 3"#,
+            "waitFor [class=List] > [text] --print-descendants",
+        ] {
+            let step = parse_step(line).unwrap();
+            assert_eq!(parse_step(&step.to_string()).unwrap(), step, "{}", line);
+        }
+        assert_eq!(
+            parse_step("tap [text=Login]").unwrap().to_string(),
+            r#"tap [text="Login"]"#
+        );
+    }
+
+    #[test]
+    fn test_parse_step_errors() {
+        let error = |line: &str| parse_step(line).unwrap_err().to_string();
+        assert!(error("click [text=Login]").starts_with("unknown step command `click`"));
+        assert_eq!(error("tap"), "tap step needs a selector");
+        assert!(error("inputText [text=User]").starts_with("inputText step needs the text"));
+        assert_eq!(
+            error("scrollDown [scrollable=true]"),
+            "scrollDown step needs --scroll-target"
+        );
+        assert_eq!(
+            error(r#"tap [text=a] "b""#),
+            "tap step does not take a text"
+        );
+        assert_eq!(
+            error("tap [text=a] --index x"),
+            "--index needs a number, got `x`"
+        );
+        assert!(error("tap [text=a] --force").starts_with("unknown step option `--force`"));
+        assert_eq!(
+            error("tap [text=a] --index 1 timeout 5"),
+            "unexpected `timeout`: step options start with --"
+        );
+        assert!(matches!(
+            parse_step(r#"inputText [text=a] "b" index 1"#).unwrap_err(),
+            BochiError::InvalidArgument(_)
+        ));
+        assert!(error(r#"inputText [text=a] "b"#).starts_with("unterminated quoted text"));
+        assert!(matches!(
+            parse_step("tap [text=a").unwrap_err(),
+            BochiError::SelectorParse(_)
+        ));
+    }
+
+    #[test]
+    fn test_parse_script_lines() {
+        let script = "# Log in\n\nwaitFor [text=Login]\n  tap [text=Login]\n";
+        let steps = parse_script(script).unwrap();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].line, 3);
+        assert_eq!(steps[1].line, 4);
+
+        let error = parse_script("waitFor [text=Login]\ntap [text=\n").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(error.to_string().starts_with("line 2: Invalid selector"));
+    }
+
+    #[test]
+    fn test_run_script() {
+        let device = FakeDevice::new([LOGIN, LOGIN, HOME]);
+        let steps = parse_script(
+            "waitFor [text=Login]\ninputText [resource-id=com.example:id/user] \"alice\"\ntap [text=Login]\nwaitFor [text=Home]",
+        )
+        .unwrap();
+        let mut seen = Vec::new();
+        let reports = run_script(&device, &steps, 10, |step, _| seen.push(step.line)).unwrap();
+        assert_eq!(seen, vec![1, 2, 3, 4]);
+        assert_eq!(reports[3].elements[0].text, "Home");
+        assert_eq!(
+            device.events(),
+            vec![
+                DeviceEvent::Tap(540, 150),
+                DeviceEvent::Text("alice".to_string()),
+                DeviceEvent::Tap(100, 350),
+            ]
+        );
+        // inputText reuses the hierarchy waitFor fetched; tap fetches again after the input
+        assert_eq!(device.fetches(), 3);
+        assert_eq!(reports[1].polls, 1);
+    }
+
+    #[test]
+    fn test_run_script_stops_at_failure() {
+        let device = FakeDevice::new([LOGIN]);
+        let steps = parse_script("tap [text=Login]\ntap [text=Home] --timeout 1\ntap [text=Login]")
+            .unwrap();
+        let error = run_script(&device, &steps, 10, |_, _| {}).unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(matches!(error.error, BochiError::ElementTimeout(..)));
+        assert_eq!(device.events().len(), 1);
+    }
+
+    #[test]
+    fn test_dry_run_tap_coordinates() {
+        let report = dry_run_step(&parse_step("tap [text=Login]").unwrap(), LOGIN).unwrap();
+        assert!(report.dry_run);
+        assert_eq!(report.polls, 1);
+        assert_eq!(report.elements.len(), 1);
+        assert_eq!(report.action, Some(Action::Tap { x: 100, y: 350 }));
+
+        let step = parse_step(r#"inputText [class^=android.widget] "alice" --index 1"#).unwrap();
+        let report = dry_run_step(&step, LOGIN).unwrap();
+        assert_eq!(
+            report.action,
+            Some(Action::InputText {
+                x: 540,
+                y: 150,
+                text: "alice".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_dry_run_index_out_of_range() {
+        let step = parse_step("tap [class^=android.widget] --index 5").unwrap();
+        let error = dry_run_step(&step, LOGIN).unwrap_err();
+        assert_eq!(
+            error,
+            BochiError::ElementNotFound(
+                r#"No element in the UI hierarchy matches selector: [class^="android.widget"] (--index 5 needs 6 matches, found 3)"#
+                    .to_string()
+            )
+        );
+        assert!(matches!(
+            dry_run_step(&parse_step("tap [text=Home]").unwrap(), LOGIN),
+            Err(BochiError::ElementNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_dry_run_scroll() {
+        // The target is already in the dump: no swipes, and the target is reported
+        let step =
+            parse_step("scrollDown [class$=FrameLayout] --scroll-target [text=Login]").unwrap();
+        let report = dry_run_step(&step, LOGIN).unwrap();
+        assert_eq!(
+            report.action,
+            Some(Action::Scroll {
+                up: false,
+                swipes: 0
+            })
+        );
+        assert_eq!(report.elements[0].text, "Login");
+
+        // Not in the dump: one swipe on the container is planned
+        let step = parse_step("scrollUp [class$=FrameLayout] --scroll-target [text=Home]").unwrap();
+        let report = dry_run_step(&step, LOGIN).unwrap();
+        assert_eq!(
+            report.action,
+            Some(Action::Scroll {
+                up: true,
+                swipes: 1
+            })
+        );
+        assert_eq!(report.elements.len(), 1);
+        assert_eq!(report.elements[0].class, "android.widget.FrameLayout");
+    }
+}
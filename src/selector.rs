@@ -1,3 +1,9 @@
+use crate::ui_element::parse_bounds;
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+use std::fmt;
+use std::mem;
+
 /// CSS-like selector system for UI elements
 ///
 /// Syntax:
@@ -6,8 +12,22 @@
 /// - `sel1,sel2` - OR of multiple selectors
 /// - `:has(cond)` - select nodes with a descendant matching cond
 /// - `:not(cond)` - select nodes that do NOT match cond
+/// - `:is(sel1, sel2)` / `:where(sel1, sel2)` - OR of selectors inside a compound selector
 /// - `ancestor > child` - child combinator (direct children only)
 /// - `ancestor descendant` - descendant combinator (any depth)
+/// - `prev + next` - adjacent sibling combinator (next is the element right after prev)
+/// - `prev ~ next` - general sibling combinator (next comes anywhere after prev)
+/// - `[attr~="regex"]` - attribute matches a regular expression
+/// - `[attr="value" i]` - case-insensitive comparison
+/// - `[attr="value" n]` - whitespace-normalized comparison (runs of whitespace collapse to one space)
+/// - `[attr]` - attribute present and non-empty
+/// - `[attr!=value]` - attribute missing or different from value
+/// - `[attr>=3]` - numeric comparison (`>`, `>=`, `<`, `<=`), also on `width` and `height`
+/// - `:first-child`, `:last-child`, `:only-child`, `:nth-child(An+B)` - position among element siblings
+/// - `:nth-match(n)` - the n-th element (in document order) matching the rest of the compound selector
+/// - `:right-of(sel)`, `:left-of(sel)`, `:above(sel)`, `:below(sel)` - on that side of an element matching sel
+/// - `:near(sel)` / `:near(sel, px)` - within px (default 50) pixels of an element matching sel
+/// - `:inside(sel)` - bounds lie within the bounds of an element matching sel
 ///
 /// Examples:
 /// - `[text="Submit"]` - element with text="Submit"
@@ -19,21 +39,25 @@
 /// - `[text*=Confirm]:not([clickable=false])` - element with text containing "Confirm" AND not clickable=false
 /// - `[class=Column] > [clickable=true]` - clickable elements that are direct children of Column
 /// - `[class=List] [text=Item]` - elements with text="Item" anywhere inside a List
+/// - `[text=Email] + [class=EditText]` - the EditText right after the "Email" label
+/// - `[text~="^Item \d+$"]` - element whose text is "Item " followed by digits
+/// - `[text="submit" i]` - element with text "Submit", "SUBMIT", ...
+/// - `[content-desc][index>=3]` - element with a content description at index 3 or later
+/// - `[class=List] > :nth-child(odd)` - odd children of a List
+/// - `[text="Add to cart"]:nth-match(3)` - the third "Add to cart" element on screen
+/// - `[class$=Switch]:right-of([text="Wi-Fi"])` - the switch on the same row as "Wi-Fi"
+/// - `[class$=Toolbar] > :is([text=Save],[content-desc=Save])` - Save item of a toolbar, by text or description
 #[derive(Debug, Clone, PartialEq)]
 pub enum Selector {
     /// AND of multiple attribute clauses
     And(Vec<AttrClause>),
     /// OR of multiple selectors
     Or(Vec<Selector>),
-    /// :has() pseudo-class - matches if node has a descendant matching the inner selector
-    Has(Box<Selector>),
-    /// :not() pseudo-class - matches if node does NOT match the inner selector
-    Not(Box<Selector>),
-    /// Combination of attribute clauses AND :has() AND/OR :not()
+    /// Attribute clauses AND any number of pseudo-classes (:has(), :not(), positional, layout).
+    /// The attribute clauses may be empty, e.g. for `:has([text=A])`.
     Complex {
         attrs: Vec<AttrClause>,
-        has: Option<Box<Selector>>,
-        not: Option<Box<Selector>>,
+        pseudos: Vec<PseudoClass>,
     },
     /// Child combinator - matches if node matches child selector and its parent matches parent selector
     Child {
@@ -45,6 +69,189 @@ pub enum Selector {
         ancestor: Box<Selector>,
         descendant: Box<Selector>,
     },
+    /// Adjacent sibling combinator - matches if node matches next selector and the element sibling right before it matches prev selector
+    AdjacentSibling {
+        prev: Box<Selector>,
+        next: Box<Selector>,
+    },
+    /// General sibling combinator - matches if node matches next selector and any earlier element sibling matches prev selector
+    GeneralSibling {
+        prev: Box<Selector>,
+        next: Box<Selector>,
+    },
+}
+
+/// Pseudo-classes written as suffixes of a compound selector, e.g. `[text=Add]:first-child`
+#[derive(Debug, Clone, PartialEq)]
+pub enum PseudoClass {
+    /// :has() - has a descendant matching the inner selector
+    Has(Box<Selector>),
+    /// :not() - does NOT match the inner selector
+    Not(Box<Selector>),
+    /// :is() / :where() - matches the inner selector, usually an OR list
+    Is(Box<Selector>),
+    /// :first-child - first element among its siblings
+    FirstChild,
+    /// :last-child - last element among its siblings
+    LastChild,
+    /// :only-child - the only element among its siblings
+    OnlyChild,
+    /// :nth-child(An+B) - 1-based position among element siblings
+    NthChild(NthExpr),
+    /// :nth-match(n) - the n-th (1-based) element in document order matching the rest of the compound.
+    /// Parent and sibling steps of a complex selector are not part of the count.
+    NthMatch(usize),
+    /// :right-of(), :below(), :near(), ... - compares bounds with any element matching the inner selector
+    Layout(LayoutRelation, Box<Selector>),
+}
+
+/// Relation between the bounds of a candidate and an anchor element used by layout pseudo-classes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LayoutRelation {
+    /// :right-of() - starts at or after the anchor's right edge and overlaps it vertically
+    RightOf,
+    /// :left-of() - ends at or before the anchor's left edge and overlaps it vertically
+    LeftOf,
+    /// :above() - ends at or before the anchor's top edge and overlaps it horizontally
+    Above,
+    /// :below() - starts at or after the anchor's bottom edge and overlaps it horizontally
+    Below,
+    /// :near() - no more than the given number of pixels away from the anchor on both axes
+    Near(i32),
+    /// :inside() - fully contained in the anchor's bounds
+    Inside,
+}
+
+/// Default distance in pixels of :near()
+const NEAR_DEFAULT_DISTANCE: i32 = 50;
+
+type Bounds = (i32, i32, i32, i32);
+
+impl LayoutRelation {
+    const NAMES: [(&'static str, LayoutRelation); 6] = [
+        (":right-of(", LayoutRelation::RightOf),
+        (":left-of(", LayoutRelation::LeftOf),
+        (":above(", LayoutRelation::Above),
+        (":below(", LayoutRelation::Below),
+        (":near(", LayoutRelation::Near(NEAR_DEFAULT_DISTANCE)),
+        (":inside(", LayoutRelation::Inside),
+    ];
+
+    /// Check the candidate bounds `c` against the anchor bounds `a`
+    fn holds(&self, c: Bounds, a: Bounds) -> bool {
+        let overlaps_vertically = c.1 < a.3 && a.1 < c.3;
+        let overlaps_horizontally = c.0 < a.2 && a.0 < c.2;
+        match self {
+            LayoutRelation::RightOf => c.0 >= a.2 && overlaps_vertically,
+            LayoutRelation::LeftOf => c.2 <= a.0 && overlaps_vertically,
+            LayoutRelation::Above => c.3 <= a.1 && overlaps_horizontally,
+            LayoutRelation::Below => c.1 >= a.3 && overlaps_horizontally,
+            LayoutRelation::Near(distance) => {
+                let dx = (a.0 - c.2).max(c.0 - a.2).max(0);
+                let dy = (a.1 - c.3).max(c.1 - a.3).max(0);
+                dx <= *distance && dy <= *distance
+            }
+            LayoutRelation::Inside => a.0 <= c.0 && a.1 <= c.1 && c.2 <= a.2 && c.3 <= a.3,
+        }
+    }
+}
+
+fn node_bounds(node: roxmltree::Node) -> Option<Bounds> {
+    parse_bounds(node.attribute("bounds")?)
+}
+
+/// CSS `An+B` expression: matches positions `A*k + B` for some integer k >= 0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NthExpr {
+    pub a: i32,
+    pub b: i32,
+}
+
+impl NthExpr {
+    /// Parse `odd`, `even`, `3`, `2n+1`, `-n+3`, `n` and similar forms
+    fn parse(s: &str) -> Result<NthExpr, String> {
+        let expr: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+        let expr = expr.to_lowercase();
+        match expr.as_str() {
+            "odd" => return Ok(NthExpr { a: 2, b: 1 }),
+            "even" => return Ok(NthExpr { a: 2, b: 0 }),
+            _ => {}
+        }
+
+        let invalid = || format!("Invalid :nth-child() argument '{}'", s);
+        match expr.find('n') {
+            Some(idx) => {
+                let a = match &expr[..idx] {
+                    "" | "+" => 1,
+                    "-" => -1,
+                    a => a.parse().map_err(|_| invalid())?,
+                };
+                let b = match &expr[idx + 1..] {
+                    "" => 0,
+                    b if b.starts_with('+') || b.starts_with('-') => {
+                        b.parse().map_err(|_| invalid())?
+                    }
+                    _ => return Err(invalid()),
+                };
+                Ok(NthExpr { a, b })
+            }
+            None => Ok(NthExpr {
+                a: 0,
+                b: expr.parse().map_err(|_| invalid())?,
+            }),
+        }
+    }
+
+    /// Check whether the 1-based position is selected by this expression
+    fn matches(&self, position: i32) -> bool {
+        if self.a == 0 {
+            return position == self.b;
+        }
+        let diff = position - self.b;
+        diff % self.a == 0 && diff / self.a >= 0
+    }
+}
+
+impl PseudoClass {
+    /// Check the pseudo-class against a node. `base` evaluates the rest of the compound
+    /// selector, which `:nth-match()` needs to count earlier matches.
+    pub fn matches(&self, node: roxmltree::Node, base: &dyn Fn(roxmltree::Node) -> bool) -> bool {
+        match self {
+            PseudoClass::Has(inner) => has_descendant_matching(node, inner),
+            PseudoClass::Not(inner) => !inner.matches(node),
+            PseudoClass::Is(inner) => inner.matches(node),
+            PseudoClass::FirstChild => node.prev_sibling_element().is_none(),
+            PseudoClass::LastChild => node.next_sibling_element().is_none(),
+            PseudoClass::OnlyChild => {
+                node.prev_sibling_element().is_none() && node.next_sibling_element().is_none()
+            }
+            PseudoClass::NthChild(expr) => {
+                let position = node
+                    .prev_siblings()
+                    .filter(|sibling| sibling.is_element())
+                    .count();
+                expr.matches(position as i32)
+            }
+            PseudoClass::NthMatch(n) => node
+                .document()
+                .root()
+                .descendants()
+                .filter(|candidate| candidate.is_element() && base(*candidate))
+                .nth(n - 1)
+                .is_some_and(|nth| nth == node),
+            PseudoClass::Layout(relation, anchor) => {
+                let Some(bounds) = node_bounds(node) else {
+                    return false;
+                };
+                node.document()
+                    .root()
+                    .descendants()
+                    .filter(|other| *other != node && anchor.matches(*other))
+                    .filter_map(node_bounds)
+                    .any(|anchor_bounds| relation.holds(bounds, anchor_bounds))
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -52,22 +259,66 @@ pub struct AttrClause {
     pub attr: String,
     pub op: AttrOp,
     pub value: String,
+    pub flags: AttrFlags,
+    /// Pattern compiled at parse time for the `~=` operator
+    regex: Option<AttrRegex>,
+}
+
+/// Modifiers written after the value of an attribute clause, e.g. `[text="ok" i n]`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AttrFlags {
+    /// `i` - compare case-insensitively
+    pub case_insensitive: bool,
+    /// `n` - trim and collapse runs of whitespace (including newlines) into a single space
+    pub normalize_whitespace: bool,
+}
+
+impl AttrFlags {
+    /// Apply the normalizations enabled by the flags to a value
+    fn apply<'v>(&self, value: &'v str) -> Cow<'v, str> {
+        let mut value = Cow::Borrowed(value);
+        if self.normalize_whitespace {
+            value = Cow::Owned(collapse_whitespace(&value));
+        }
+        if self.case_insensitive {
+            value = Cow::Owned(value.to_lowercase());
+        }
+        value
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum AttrOp {
-    Equals,     // =
-    StartsWith, // ^=
-    EndsWith,   // $=
-    Contains,   // *=
+    Equals,         // =
+    StartsWith,     // ^=
+    EndsWith,       // $=
+    Contains,       // *=
+    Matches,        // ~=
+    NotEquals,      // !=
+    Exists,         // [attr] - present and non-empty
+    GreaterThan,    // >
+    GreaterOrEqual, // >=
+    LessThan,       // <
+    LessOrEqual,    // <=
+}
+
+/// Compiled regular expression of a `~=` clause.
+/// Two patterns compare equal if their source strings are equal.
+#[derive(Debug, Clone)]
+struct AttrRegex(Regex);
+
+impl PartialEq for AttrRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
 }
 
 impl Selector {
     /// Parse a selector string into a Selector AST
     /// Supports CSS-like syntax [attr=value]
-    pub fn parse(s: &str) -> Result<Selector, String> {
-        let trimmed = s.trim();
-        let mut parser = SelectorParser::new(trimmed);
+    /// Errors point at a byte offset of `s`
+    pub fn parse(s: &str) -> Result<Selector, SelectorError> {
+        let mut parser = SelectorParser::new(s);
         parser.parse()
     }
 
@@ -79,19 +330,20 @@ impl Selector {
         match self {
             Selector::And(clauses) => clauses.iter().all(|c| c.matches(node)),
             Selector::Or(selectors) => selectors.iter().any(|s| s.matches(node)),
-            Selector::Has(inner) => has_descendant_matching(node, inner),
-            Selector::Not(inner) => !inner.matches(node),
-            Selector::Complex { attrs, has, not } => {
-                let attrs_match = attrs.iter().all(|c| c.matches(node));
-                let has_match = match has {
-                    Some(inner) => has_descendant_matching(node, inner),
-                    None => true,
+            Selector::Complex { attrs, pseudos } => {
+                // Everything except :nth-match(), which counts the nodes matching the rest
+                let base = |node: roxmltree::Node| {
+                    attrs.iter().all(|c| c.matches(node))
+                        && pseudos
+                            .iter()
+                            .filter(|p| !matches!(p, PseudoClass::NthMatch(_)))
+                            .all(|p| p.matches(node, &|_| true))
                 };
-                let not_match = match not {
-                    Some(inner) => !inner.matches(node),
-                    None => true,
-                };
-                attrs_match && has_match && not_match
+                base(node)
+                    && pseudos
+                        .iter()
+                        .filter(|p| matches!(p, PseudoClass::NthMatch(_)))
+                        .all(|p| p.matches(node, &base))
             }
             Selector::Child { parent, child } => {
                 // First check if current node matches child selector
@@ -119,12 +371,29 @@ impl Selector {
                 // Then check if any ancestor matches ancestor selector
                 has_ancestor_matching(node, ancestor)
             }
+            Selector::AdjacentSibling { prev, next } => {
+                next.matches(node)
+                    && node
+                        .prev_sibling_element()
+                        .is_some_and(|sibling| prev.matches(sibling))
+            }
+            Selector::GeneralSibling { prev, next } => {
+                next.matches(node)
+                    && node
+                        .prev_siblings()
+                        .skip(1)
+                        .filter(|n| n.is_element())
+                        .any(|sibling| prev.matches(sibling))
+            }
         }
     }
 }
 
 impl AttrClause {
-    fn matches(&self, node: roxmltree::Node) -> bool {
+    /// Look up the value of the clause's attribute, resolving aliases and derived properties.
+    /// `width`, `height`, `left`, `top`, `right` and `bottom` are computed from `bounds`
+    /// unless the node has an attribute of that name.
+    pub fn attribute_value<'a>(&self, node: roxmltree::Node<'a, '_>) -> Option<Cow<'a, str>> {
         let attr_value = match self.attr.as_str() {
             "text" => node.attribute("text"),
             "contentDescription" | "content-description" | "content_desc" => {
@@ -146,16 +415,206 @@ impl AttrClause {
             "bounds" => node.attribute("bounds"),
             attr => node.attribute(attr),
         };
+        if let Some(val) = attr_value {
+            return Some(Cow::Borrowed(val));
+        }
+
+        let (x1, y1, x2, y2) = parse_bounds(node.attribute("bounds")?)?;
+        let derived = match self.attr.as_str() {
+            "width" => x2 - x1,
+            "height" => y2 - y1,
+            "left" => x1,
+            "top" => y1,
+            "right" => x2,
+            "bottom" => y2,
+            _ => return None,
+        };
+        Some(Cow::Owned(derived.to_string()))
+    }
 
-        match attr_value {
-            Some(val) => match self.op {
-                AttrOp::Equals => val == self.value,
-                AttrOp::StartsWith => val.starts_with(&self.value),
-                AttrOp::EndsWith => val.ends_with(&self.value),
-                AttrOp::Contains => val.contains(&self.value),
-            },
-            None => false,
+    pub fn matches(&self, node: roxmltree::Node) -> bool {
+        let val = match (self.attribute_value(node), &self.op) {
+            (Some(val), _) => val,
+            // A missing attribute is never equal to the value
+            (None, AttrOp::NotEquals) => return true,
+            (None, _) => return false,
+        };
+
+        match self.op {
+            AttrOp::Matches => {
+                // Case-insensitivity is compiled into the regex, so only normalize whitespace
+                let val = if self.flags.normalize_whitespace {
+                    Cow::Owned(collapse_whitespace(&val))
+                } else {
+                    val
+                };
+                self.regex
+                    .as_ref()
+                    .is_some_and(|regex| regex.0.is_match(&val))
+            }
+            AttrOp::GreaterThan
+            | AttrOp::GreaterOrEqual
+            | AttrOp::LessThan
+            | AttrOp::LessOrEqual => {
+                let (actual, expected) =
+                    match (val.trim().parse::<f64>(), self.value.trim().parse::<f64>()) {
+                        (Ok(actual), Ok(expected)) => (actual, expected),
+                        _ => return false,
+                    };
+                match self.op {
+                    AttrOp::GreaterThan => actual > expected,
+                    AttrOp::GreaterOrEqual => actual >= expected,
+                    AttrOp::LessThan => actual < expected,
+                    _ => actual <= expected,
+                }
+            }
+            _ => {
+                let val = self.flags.apply(&val);
+                let expected = self.flags.apply(&self.value);
+                match self.op {
+                    AttrOp::Equals => val == expected,
+                    AttrOp::NotEquals => val != expected,
+                    AttrOp::StartsWith => val.starts_with(expected.as_ref()),
+                    AttrOp::EndsWith => val.ends_with(expected.as_ref()),
+                    AttrOp::Contains => val.contains(expected.as_ref()),
+                    _ => !val.is_empty(), // Exists
+                }
+            }
+        }
+    }
+}
+
+/// Trim and collapse runs of whitespace into a single space
+fn collapse_whitespace(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Canonical selector text: values are always double-quoted, `:where()` is written as
+/// `:is()` and `:near()` omits the default distance. Parsing the text gives back an
+/// equal selector.
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Selector::And(clauses) => clauses.iter().try_for_each(|c| write!(f, "{}", c)),
+            Selector::Or(selectors) => {
+                for (i, selector) in selectors.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", selector)?;
+                }
+                Ok(())
+            }
+            Selector::Complex { attrs, pseudos } => {
+                attrs.iter().try_for_each(|c| write!(f, "{}", c))?;
+                pseudos.iter().try_for_each(|p| write!(f, "{}", p))
+            }
+            Selector::Child { parent, child } => {
+                write!(f, "{} > {}", Operand(parent), Operand(child))
+            }
+            Selector::Descendant {
+                ancestor,
+                descendant,
+            } => write!(f, "{} {}", Operand(ancestor), Operand(descendant)),
+            Selector::AdjacentSibling { prev, next } => {
+                write!(f, "{} + {}", Operand(prev), Operand(next))
+            }
+            Selector::GeneralSibling { prev, next } => {
+                write!(f, "{} ~ {}", Operand(prev), Operand(next))
+            }
+        }
+    }
+}
+
+/// Operand of a combinator. An OR list cannot appear there in selector text,
+/// so one built by hand is written as `:is()`.
+struct Operand<'s>(&'s Selector);
+
+impl fmt::Display for Operand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Selector::Or(_) => write!(f, ":is({})", self.0),
+            selector => write!(f, "{}", selector),
+        }
+    }
+}
+
+impl fmt::Display for PseudoClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PseudoClass::Has(inner) => write!(f, ":has({})", inner),
+            PseudoClass::Not(inner) => write!(f, ":not({})", inner),
+            PseudoClass::Is(inner) => write!(f, ":is({})", inner),
+            PseudoClass::FirstChild => write!(f, ":first-child"),
+            PseudoClass::LastChild => write!(f, ":last-child"),
+            PseudoClass::OnlyChild => write!(f, ":only-child"),
+            PseudoClass::NthChild(expr) => write!(f, ":nth-child({})", expr),
+            PseudoClass::NthMatch(n) => write!(f, ":nth-match({})", n),
+            PseudoClass::Layout(relation, anchor) => {
+                let (name, _) = LayoutRelation::NAMES
+                    .iter()
+                    .find(|(_, r)| mem::discriminant(r) == mem::discriminant(relation))
+                    .expect("every layout relation has a name");
+                match relation {
+                    LayoutRelation::Near(distance) if *distance != NEAR_DEFAULT_DISTANCE => {
+                        write!(f, "{}{}, {})", name, anchor, distance)
+                    }
+                    _ => write!(f, "{}{})", name, anchor),
+                }
+            }
+        }
+    }
+}
+
+/// `An+B` in its shortest form, e.g. `3`, `n`, `-n+3` or `2n+1` (for `odd`)
+impl fmt::Display for NthExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.a {
+            0 => return write!(f, "{}", self.b),
+            1 => write!(f, "n")?,
+            -1 => write!(f, "-n")?,
+            a => write!(f, "{}n", a)?,
+        }
+        if self.b != 0 {
+            write!(f, "{:+}", self.b)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AttrClause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}", self.attr)?;
+        if self.op != AttrOp::Exists {
+            write!(f, "{}{}", self.op, quote(&self.value))?;
+        }
+        if self.flags.case_insensitive {
+            write!(f, " i")?;
+        }
+        if self.flags.normalize_whitespace {
+            write!(f, " n")?;
         }
+        write!(f, "]")
+    }
+}
+
+/// The operator as written in a clause; empty for `[attr]`
+impl fmt::Display for AttrOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            AttrOp::Equals => "=",
+            AttrOp::StartsWith => "^=",
+            AttrOp::EndsWith => "$=",
+            AttrOp::Contains => "*=",
+            AttrOp::Matches => "~=",
+            AttrOp::NotEquals => "!=",
+            AttrOp::Exists => "",
+            AttrOp::GreaterThan => ">",
+            AttrOp::GreaterOrEqual => ">=",
+            AttrOp::LessThan => "<",
+            AttrOp::LessOrEqual => "<=",
+        };
+        write!(f, "{}", op)
     }
 }
 
@@ -184,29 +643,105 @@ fn has_ancestor_matching(node: roxmltree::Node, selector: &Selector) -> bool {
     }
 }
 
-/// Parser for CSS-like selector syntax
+/// Double-quote a value for use in a selector.
+/// Backslashes are only escaped where the parser would otherwise read an escape
+/// sequence, so regular expressions such as `\d+` stay readable.
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' if matches!(chars.peek(), None | Some('"' | '\'' | '\\')) => {
+                quoted.push_str("\\\\")
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Error from parsing a selector, pointing at the place where parsing failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectorError {
+    /// The selector as it was given
+    pub input: String,
+    /// Byte offset into `input` where parsing failed
+    pub offset: usize,
+    /// What the parser expected at `offset`, e.g. `']'` or `attribute name`
+    pub expected: Option<String>,
+    /// Description of the problem, without position information
+    pub message: String,
+}
+
+impl SelectorError {
+    /// Render the message followed by the selector and a caret under the failing position
+    pub fn render(&self) -> String {
+        let column = self.input[..self.offset].chars().count();
+        format!(
+            "{} at position {}\n  {}\n  {}^",
+            self.message,
+            self.offset,
+            self.input,
+            " ".repeat(column)
+        )
+    }
+}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render())
+    }
+}
+
+impl std::error::Error for SelectorError {}
+
+type ParseResult<T> = Result<T, SelectorError>;
+
+/// Pseudo-classes accepted by the parser, listed in "unknown pseudo-class" errors
+const PSEUDO_CLASS_NAMES: &str = ":has(), :not(), :is(), :where(), :first-child, :last-child, :only-child, :nth-child(), :nth-match(), :right-of(), :left-of(), :above(), :below(), :near() or :inside()";
+
+/// Parser for CSS-like selector syntax.
+/// Positions are byte offsets into `input`; nested selectors (e.g. inside `:has()`)
+/// are parsed by a parser over the same input limited to `end`, so errors always
+/// point into the selector as the user wrote it.
 struct SelectorParser<'a> {
     input: &'a str,
     pos: usize,
+    end: usize,
 }
 
 impl<'a> SelectorParser<'a> {
     fn new(input: &'a str) -> Self {
-        Self { input, pos: 0 }
+        Self {
+            input,
+            pos: 0,
+            end: input.len(),
+        }
+    }
+
+    /// Parser for the nested selector in `input[start..end]`
+    fn nested(input: &'a str, start: usize, end: usize) -> Self {
+        Self {
+            input,
+            pos: start,
+            end,
+        }
     }
 
-    fn parse(&mut self) -> Result<Selector, String> {
+    fn parse(&mut self) -> ParseResult<Selector> {
         self.skip_whitespace();
         let result = self.parse_or_expr()?;
         self.skip_whitespace();
-        if !self.is_eof() {
-            return Err(format!("Unexpected characters at position {}", self.pos));
+        if let Some(c) = self.peek() {
+            return Err(self.error(format!("Unexpected character '{}'", c)));
         }
         Ok(result)
     }
 
     /// Parse OR expression (comma-separated selectors)
-    fn parse_or_expr(&mut self) -> Result<Selector, String> {
+    fn parse_or_expr(&mut self) -> ParseResult<Selector> {
         let mut selectors = vec![];
 
         loop {
@@ -217,7 +752,7 @@ impl<'a> SelectorParser<'a> {
             // If we see a comma but no selector before it, that's an error
             if self.peek() == Some(',') {
                 // This means we have an empty selector (either leading or trailing comma)
-                return Err("Empty selector in OR expression".to_string());
+                return Err(self.expected("selector", "Empty selector in OR expression"));
             }
             selectors.push(self.parse_descendant_chain()?);
             self.skip_whitespace();
@@ -228,7 +763,7 @@ impl<'a> SelectorParser<'a> {
                 self.skip_whitespace();
                 if self.is_eof() || self.peek() == Some(',') {
                     // Trailing comma or double comma - error
-                    return Err("Empty selector in OR expression".to_string());
+                    return Err(self.expected("selector", "Empty selector in OR expression"));
                 }
             } else {
                 break;
@@ -236,7 +771,7 @@ impl<'a> SelectorParser<'a> {
         }
 
         if selectors.is_empty() {
-            return Err("Empty selector".to_string());
+            return Err(self.expected("selector", "Empty selector"));
         }
 
         if selectors.len() == 1 {
@@ -248,7 +783,7 @@ impl<'a> SelectorParser<'a> {
 
     /// Parse descendant chain (space-separated selectors)
     /// Builds a left-associative tree: A B C becomes ((A B) C)
-    fn parse_descendant_chain(&mut self) -> Result<Selector, String> {
+    fn parse_descendant_chain(&mut self) -> ParseResult<Selector> {
         // First parse a child combinator chain
         let mut left = self.parse_child_combinator()?;
 
@@ -266,7 +801,8 @@ impl<'a> SelectorParser<'a> {
             }
 
             // Check if the next character can start a selector
-            // A selector can start with '[' (attribute), ':' (:has, :not), or '>' is handled by child combinator
+            // A selector can start with '[' (attribute), ':' (:has, :not), while '>', '+' and '~'
+            // are handled by parse_child_combinator
             match self.peek() {
                 Some('[') | Some(':') => {
                     // This is a descendant combinator - parse the next selector
@@ -276,8 +812,8 @@ impl<'a> SelectorParser<'a> {
                         descendant: Box::new(right),
                     };
                 }
-                Some('>') => {
-                    // '>' is handled by parse_child_combinator, not here
+                Some('>') | Some('+') | Some('~') => {
+                    // Combinators are handled by parse_child_combinator, not here
                     // Restore position and let the child combinator parser handle it
                     self.pos = saved_pos;
                     break;
@@ -293,165 +829,290 @@ impl<'a> SelectorParser<'a> {
         Ok(left)
     }
 
-    /// Parse child combinator (parent > child)
-    /// Builds a left-associative tree: A > B > C becomes ((A > B) > C)
-    fn parse_child_combinator(&mut self) -> Result<Selector, String> {
+    /// Parse child (parent > child), adjacent sibling (prev + next) and general sibling (prev ~ next) combinators
+    /// Builds a left-associative tree: A > B + C becomes ((A > B) + C)
+    fn parse_child_combinator(&mut self) -> ParseResult<Selector> {
         let mut left = self.parse_complex_selector()?;
 
         loop {
             self.skip_whitespace();
 
-            // Check for child combinator >
-            if self.peek() == Some('>') {
-                self.advance(); // consume '>'
-                self.skip_whitespace();
-                let right = self.parse_complex_selector()?;
-
-                // Build left-associative: left becomes (left > right)
-                left = Selector::Child {
-                    parent: Box::new(left),
-                    child: Box::new(right),
-                };
-            } else {
-                break;
-            }
+            let combinator = match self.peek() {
+                Some(c @ ('>' | '+' | '~')) => c,
+                _ => break,
+            };
+            self.advance(); // consume the combinator
+            self.skip_whitespace();
+            let right = Box::new(self.parse_complex_selector()?);
+            let left_box = Box::new(left);
+
+            // Build left-associative: left becomes (left <combinator> right)
+            left = match combinator {
+                '>' => Selector::Child {
+                    parent: left_box,
+                    child: right,
+                },
+                '+' => Selector::AdjacentSibling {
+                    prev: left_box,
+                    next: right,
+                },
+                _ => Selector::GeneralSibling {
+                    prev: left_box,
+                    next: right,
+                },
+            };
         }
 
         Ok(left)
     }
 
-    /// Parse a complex selector that may have :has() or :not() at the end
-    fn parse_complex_selector(&mut self) -> Result<Selector, String> {
+    /// Parse a complex selector that may have :has(), :not() or positional pseudo-classes at the end
+    fn parse_complex_selector(&mut self) -> ParseResult<Selector> {
         self.skip_whitespace();
 
-        // Check if it starts with :has() or :not()
-        if self.peek() == Some(':') {
-            if self.input[self.pos..].starts_with(":has(") {
-                return self.parse_has_selector();
-            } else if self.input[self.pos..].starts_with(":not(") {
-                return self.parse_not_selector();
-            }
-        }
-
-        // Parse attribute clauses
+        // Parse attribute clauses (none if the compound starts with a pseudo-class)
         let clauses = self.parse_attr_clauses()?;
 
         self.skip_whitespace();
 
-        // Check for :has() or :not() after attribute clauses
-        if self.peek() == Some(':') {
-            let mut has_selector: Option<Box<Selector>> = None;
-            let mut not_selector: Option<Box<Selector>> = None;
-
-            // Parse any number of :has() and :not() suffixes
-            // Multiple :has() or :not() are allowed - last one wins
-            loop {
-                self.skip_whitespace();
-                if self.peek() != Some(':') {
-                    break;
-                }
-
-                if self.input[self.pos..].starts_with(":has(") {
-                    has_selector = Some(self.parse_has_selector_suffix()?);
-                } else if self.input[self.pos..].starts_with(":not(") {
-                    not_selector = Some(self.parse_not_selector_suffix()?);
-                } else {
-                    break;
-                }
-            }
+        let mut pseudos = vec![];
 
-            if clauses.is_empty() && has_selector.is_none() && not_selector.is_none() {
-                return Err("Expected attribute clause, :has() or :not()".to_string());
+        // Parse any number of :has(), :not(), positional and layout pseudo-classes.
+        // All of them must match (AND), e.g. `:has([text=A]):has([text=B])`.
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(':') {
+                break;
             }
 
-            if has_selector.is_some() || not_selector.is_some() {
-                Ok(Selector::Complex {
-                    attrs: clauses,
-                    has: has_selector,
-                    not: not_selector,
-                })
-            } else if clauses.is_empty() {
-                Err("Expected attribute clause".to_string())
+            if self.rest().starts_with(":has(") {
+                pseudos.push(PseudoClass::Has(self.parse_has_selector()?));
+            } else if self.rest().starts_with(":not(") {
+                pseudos.push(PseudoClass::Not(self.parse_not_selector()?));
+            } else if self.rest().starts_with(":is(") || self.rest().starts_with(":where(") {
+                pseudos.push(PseudoClass::Is(self.parse_is_selector()?));
+            } else if let Some(pseudo) = self.parse_positional_pseudo_class()? {
+                pseudos.push(pseudo);
             } else {
-                Ok(Selector::And(clauses))
+                return Err(self.unknown_pseudo_class());
             }
+        }
+
+        if !pseudos.is_empty() {
+            Ok(Selector::Complex {
+                attrs: clauses,
+                pseudos,
+            })
         } else if clauses.is_empty() {
-            Err("Expected attribute clause, :has() or :not()".to_string())
+            Err(self.expected(
+                "'[' or ':'",
+                match self.peek() {
+                    Some(c) => format!(
+                        "Expected attribute clause or pseudo-class but found '{}'",
+                        c
+                    ),
+                    None => "Expected attribute clause or pseudo-class but reached end of input"
+                        .to_string(),
+                },
+            ))
         } else {
             Ok(Selector::And(clauses))
         }
     }
 
-    /// Parse :has() selector (when it starts the expression)
-    fn parse_has_selector(&mut self) -> Result<Selector, String> {
-        self.expect_str(":has(")?;
-        let inner = self.parse_inner_selector()?;
+    /// Error for a ':' that does not start a known pseudo-class
+    fn unknown_pseudo_class(&self) -> SelectorError {
+        let name: String = self
+            .rest()
+            .chars()
+            .enumerate()
+            .take_while(|(i, c)| *i == 0 || c.is_alphanumeric() || *c == '-')
+            .map(|(_, c)| c)
+            .collect();
+        self.expected(
+            "pseudo-class",
+            format!(
+                "Unknown pseudo-class '{}' (expected {})",
+                name, PSEUDO_CLASS_NAMES
+            ),
+        )
+    }
+
+    /// Parse :first-child, :last-child, :only-child, :nth-child(An+B), :nth-match(n)
+    /// or a layout pseudo-class such as :right-of(sel).
+    /// Returns None if the input does not start with one of them.
+    fn parse_positional_pseudo_class(&mut self) -> ParseResult<Option<PseudoClass>> {
+        for (name, pseudo) in [
+            (":first-child", PseudoClass::FirstChild),
+            (":last-child", PseudoClass::LastChild),
+            (":only-child", PseudoClass::OnlyChild),
+        ] {
+            if let Some(rest) = self.rest().strip_prefix(name) {
+                // Make sure the name is not just a prefix of a longer identifier
+                if !rest.starts_with(|c: char| c.is_alphanumeric() || c == '-') {
+                    self.pos += name.len();
+                    return Ok(Some(pseudo));
+                }
+            }
+        }
+
+        if self.rest().starts_with(":nth-child(") {
+            self.expect_str(":nth-child(")?;
+            let (start, argument) = self.parse_pseudo_argument()?;
+            let expr = NthExpr::parse(&argument)
+                .map_err(|message| self.expected_at(start, "An+B, odd or even", message))?;
+            return Ok(Some(PseudoClass::NthChild(expr)));
+        }
+
+        for (name, relation) in LayoutRelation::NAMES {
+            if self.rest().starts_with(name) {
+                self.expect_str(name)?;
+                return self.parse_layout_argument(&name[..name.len() - 1], relation);
+            }
+        }
+
+        if self.rest().starts_with(":nth-match(") {
+            self.expect_str(":nth-match(")?;
+            let (start, argument) = self.parse_pseudo_argument()?;
+            return match argument.trim().parse::<usize>() {
+                Ok(n) if n >= 1 => Ok(Some(PseudoClass::NthMatch(n))),
+                _ => Err(self.expected_at(
+                    start,
+                    "positive integer",
+                    format!(
+                        "Invalid :nth-match() argument '{}' (expected a positive integer)",
+                        argument
+                    ),
+                )),
+            };
+        }
+
+        Ok(None)
+    }
+
+    /// Parse the `sel` or `sel, px` argument of a layout pseudo-class up to and including the closing ')'
+    fn parse_layout_argument(
+        &mut self,
+        name: &str,
+        relation: LayoutRelation,
+    ) -> ParseResult<Option<PseudoClass>> {
+        let (start, end) = self.scan_inner_argument();
         self.expect_char(')')?;
-        Ok(Selector::Has(inner))
+
+        // A trailing number is the distance of :near(); it can never be a selector
+        let argument = &self.input[start..end];
+        let (selector_end, relation) = match (relation, argument.rfind(',')) {
+            (LayoutRelation::Near(_), Some(comma))
+                if !argument[comma + 1..].trim().is_empty()
+                    && argument[comma + 1..]
+                        .trim()
+                        .chars()
+                        .all(|c| c.is_ascii_digit()) =>
+            {
+                let distance = argument[comma + 1..].trim();
+                let distance = distance.parse().map_err(|_| {
+                    self.expected_at(
+                        start + comma + 1,
+                        "distance in pixels",
+                        format!("Invalid {}() distance '{}'", name, distance),
+                    )
+                })?;
+                (start + comma, LayoutRelation::Near(distance))
+            }
+            _ => (end, relation),
+        };
+
+        let anchor = SelectorParser::nested(self.input, start, selector_end).parse()?;
+        Ok(Some(PseudoClass::Layout(relation, Box::new(anchor))))
     }
 
-    /// Parse :not() selector (when it starts the expression)
-    fn parse_not_selector(&mut self) -> Result<Selector, String> {
-        self.expect_str(":not(")?;
-        let inner = self.parse_inner_selector()?;
+    /// Read a plain pseudo-class argument up to and including the closing ')'.
+    /// Returns the offset where the argument starts and its text.
+    fn parse_pseudo_argument(&mut self) -> ParseResult<(usize, String)> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == ')' {
+                break;
+            }
+            self.advance();
+        }
+        let argument = self.input[start..self.pos].to_string();
         self.expect_char(')')?;
-        Ok(Selector::Not(inner))
+        Ok((start, argument))
     }
 
-    /// Parse :has() as a suffix to an existing selector
-    fn parse_has_selector_suffix(&mut self) -> Result<Box<Selector>, String> {
+    /// Parse :has() and return its inner selector
+    fn parse_has_selector(&mut self) -> ParseResult<Box<Selector>> {
         self.expect_str(":has(")?;
         let inner = self.parse_inner_selector()?;
         self.expect_char(')')?;
-        // Return the inner selector directly, not wrapped in Has
-        // The Complex variant already implies "has a descendant matching"
         Ok(inner)
     }
 
-    /// Parse :not() as a suffix to an existing selector
-    fn parse_not_selector_suffix(&mut self) -> Result<Box<Selector>, String> {
+    /// Parse :is() or its alias :where() and return its inner selector
+    fn parse_is_selector(&mut self) -> ParseResult<Box<Selector>> {
+        if self.rest().starts_with(":is(") {
+            self.expect_str(":is(")?;
+        } else {
+            self.expect_str(":where(")?;
+        }
+        let inner = self.parse_inner_selector()?;
+        self.expect_char(')')?;
+        Ok(inner)
+    }
+
+    /// Parse :not() and return its inner selector
+    fn parse_not_selector(&mut self) -> ParseResult<Box<Selector>> {
         self.expect_str(":not(")?;
         let inner = self.parse_inner_selector()?;
         self.expect_char(')')?;
-        // Return the inner selector directly, not wrapped in Not
-        // The Complex variant handles the negation logic
         Ok(inner)
     }
 
-    /// Parse selector inside :has() parentheses
-    fn parse_inner_selector(&mut self) -> Result<Box<Selector>, String> {
+    /// Parse the selector inside the parentheses of :has(), :not() or :is()
+    fn parse_inner_selector(&mut self) -> ParseResult<Box<Selector>> {
+        let (start, end) = self.scan_inner_argument();
+        let inner_selector = SelectorParser::nested(self.input, start, end).parse()?;
+
+        Ok(Box::new(inner_selector))
+    }
+
+    /// Advance to the parenthesis closing the current pseudo-class and return the byte range before it.
+    /// Parentheses inside quoted values do not count.
+    fn scan_inner_argument(&mut self) -> (usize, usize) {
         self.skip_whitespace();
 
         // Find the matching closing paren, accounting for nested parens
         let start = self.pos;
         let mut depth = 1;
-        while depth > 0 && !self.is_eof() {
-            match self.peek() {
-                Some('(') => {
+        let mut quote: Option<char> = None;
+        while let Some(c) = self.peek() {
+            match (quote, c) {
+                (Some(_), '\\') => {
+                    // Skip the escaped character as well
                     self.advance();
-                    depth += 1;
                 }
-                Some(')') => {
-                    if depth == 1 {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '(') => depth += 1,
+                (None, ')') => {
+                    depth -= 1;
+                    if depth == 0 {
                         break;
                     }
-                    self.advance();
-                    depth -= 1;
                 }
-                _ => self.advance(),
+                (None, _) => {}
             }
+            self.advance();
         }
 
-        let selector_str = &self.input[start..self.pos];
-        let inner_selector = Selector::parse(selector_str)
-            .map_err(|e| format!("Invalid selector inside :has(): {}", e))?;
-
-        Ok(Box::new(inner_selector))
+        (start, self.pos)
     }
 
     /// Parse multiple attribute clauses [attr=value][attr2=value]
     /// Stops if there's significant whitespace before the next `[` to allow for descendant combinator
-    fn parse_attr_clauses(&mut self) -> Result<Vec<AttrClause>, String> {
+    fn parse_attr_clauses(&mut self) -> ParseResult<Vec<AttrClause>> {
         let mut clauses = vec![];
 
         loop {
@@ -479,37 +1140,121 @@ impl<'a> SelectorParser<'a> {
         Ok(clauses)
     }
 
-    /// Parse a single [attr=value], [attr^=value], [attr$=value], or [attr*=value] clause
-    fn parse_attr_clause(&mut self) -> Result<AttrClause, String> {
+    /// Parse a single attribute clause such as [attr], [attr=value], [attr^=value] or [attr>=3]
+    fn parse_attr_clause(&mut self) -> ParseResult<AttrClause> {
         self.expect_char('[')?;
         self.skip_whitespace();
 
         let attr = self.parse_identifier()?;
 
         self.skip_whitespace();
-        let op = self.parse_operator()?;
-        self.skip_whitespace();
-
-        let value = self.parse_value()?;
+        // `[attr]` (optionally with flags) checks that the attribute is present and non-empty
+        let is_existence_check = match self.peek() {
+            Some(']') => true,
+            Some(c) => c.is_alphabetic(),
+            None => false,
+        };
+        let value_start;
+        let (op, value) = if is_existence_check {
+            value_start = self.pos;
+            (AttrOp::Exists, String::new())
+        } else {
+            let op = self.parse_operator()?;
+            self.skip_whitespace();
+            value_start = self.pos;
+            (op.clone(), self.parse_value()?)
+        };
 
         // CSS spec: substring operators (^=, $=, *=) require non-empty values
         match op {
-            AttrOp::StartsWith | AttrOp::EndsWith | AttrOp::Contains => {
+            AttrOp::StartsWith | AttrOp::EndsWith | AttrOp::Contains | AttrOp::Matches => {
                 if value.is_empty() {
-                    return Err(format!("Empty value not allowed for {:?} operator", op));
+                    return Err(self.expected_at(
+                        value_start,
+                        "value",
+                        format!("Empty value not allowed for '{}' operator", op),
+                    ));
+                }
+            }
+            AttrOp::GreaterThan
+            | AttrOp::GreaterOrEqual
+            | AttrOp::LessThan
+            | AttrOp::LessOrEqual => {
+                if value.trim().parse::<f64>().is_err() {
+                    return Err(self.expected_at(
+                        value_start,
+                        "number",
+                        format!(
+                            "Expected a number for '{}' operator but found '{}'",
+                            op, value
+                        ),
+                    ));
                 }
             }
-            AttrOp::Equals => {} // Empty value allowed for =
+            // Empty value allowed for = and !=
+            AttrOp::Equals | AttrOp::NotEquals | AttrOp::Exists => {}
         }
 
+        self.skip_whitespace();
+        let flags = self.parse_attr_flags()?;
+
+        // Compile the pattern once here so matching never fails
+        let regex = match op {
+            AttrOp::Matches => Some(AttrRegex(
+                RegexBuilder::new(&value)
+                    .case_insensitive(flags.case_insensitive)
+                    .build()
+                    .map_err(|e| {
+                        self.error_at(
+                            value_start,
+                            format!(
+                                "Invalid regular expression '{}' for ~= operator: {}",
+                                value, e
+                            ),
+                        )
+                    })?,
+            )),
+            _ => None,
+        };
+
         self.skip_whitespace();
         self.expect_char(']')?;
 
-        Ok(AttrClause { attr, op, value })
+        Ok(AttrClause {
+            attr,
+            op,
+            value,
+            flags,
+            regex,
+        })
+    }
+
+    /// Parse whitespace-separated flags (`i`, `n`) after an attribute value
+    fn parse_attr_flags(&mut self) -> ParseResult<AttrFlags> {
+        let mut flags = AttrFlags::default();
+
+        while self.peek().is_some_and(|c| c.is_alphabetic()) {
+            let start = self.pos;
+            let flag = self.parse_identifier()?;
+            match flag.as_str() {
+                "i" | "I" => flags.case_insensitive = true,
+                "n" | "N" => flags.normalize_whitespace = true,
+                _ => {
+                    return Err(self.expected_at(
+                        start,
+                        "'i' or 'n'",
+                        format!("Unknown attribute flag '{}' (expected i or n)", flag),
+                    ))
+                }
+            }
+            self.skip_whitespace();
+        }
+
+        Ok(flags)
     }
 
-    /// Parse the operator (=, ^=, $=, *=)
-    fn parse_operator(&mut self) -> Result<AttrOp, String> {
+    /// Parse the operator (=, ^=, $=, *=, ~=, !=, >, >=, <, <=)
+    fn parse_operator(&mut self) -> ParseResult<AttrOp> {
         match self.peek() {
             Some('^') => {
                 self.advance();
@@ -526,20 +1271,54 @@ impl<'a> SelectorParser<'a> {
                 self.expect_char('=')?;
                 Ok(AttrOp::Contains)
             }
+            Some('~') => {
+                self.advance();
+                self.expect_char('=')?;
+                Ok(AttrOp::Matches)
+            }
+            Some('!') => {
+                self.advance();
+                self.expect_char('=')?;
+                Ok(AttrOp::NotEquals)
+            }
+            Some('>') => {
+                self.advance();
+                if self.peek() == Some('=') {
+                    self.advance();
+                    Ok(AttrOp::GreaterOrEqual)
+                } else {
+                    Ok(AttrOp::GreaterThan)
+                }
+            }
+            Some('<') => {
+                self.advance();
+                if self.peek() == Some('=') {
+                    self.advance();
+                    Ok(AttrOp::LessOrEqual)
+                } else {
+                    Ok(AttrOp::LessThan)
+                }
+            }
             Some('=') => {
                 self.advance();
                 Ok(AttrOp::Equals)
             }
-            Some(c) => Err(format!(
-                "Expected operator (=, ^=, $=, *=) but found '{}' at position {}",
-                c, self.pos
+            Some(c) => Err(self.expected(
+                "operator",
+                format!(
+                    "Expected operator (=, ^=, $=, *=, ~=, !=, >, >=, <, <=) but found '{}'",
+                    c
+                ),
+            )),
+            None => Err(self.expected(
+                "operator",
+                "Expected operator (=, ^=, $=, *=, ~=, !=, >, >=, <, <=) but reached end of input",
             )),
-            None => Err("Expected operator (=, ^=, $=, *=) but reached end of input".to_string()),
         }
     }
 
     /// Parse an identifier (attribute name)
-    fn parse_identifier(&mut self) -> Result<String, String> {
+    fn parse_identifier(&mut self) -> ParseResult<String> {
         let start = self.pos;
 
         while let Some(c) = self.peek() {
@@ -551,14 +1330,14 @@ impl<'a> SelectorParser<'a> {
         }
 
         if start == self.pos {
-            return Err(format!("Expected identifier at position {}", self.pos));
+            return Err(self.expected("attribute name", "Expected attribute name"));
         }
 
         Ok(self.input[start..self.pos].to_string())
     }
 
     /// Parse a value (quoted or unquoted)
-    fn parse_value(&mut self) -> Result<String, String> {
+    fn parse_value(&mut self) -> ParseResult<String> {
         match self.peek() {
             Some('"') => self.parse_quoted_string('"'),
             Some('\'') => self.parse_quoted_string('\''),
@@ -567,13 +1346,15 @@ impl<'a> SelectorParser<'a> {
     }
 
     /// Parse a quoted string, supporting escaped quotes (\" or \')
-    fn parse_quoted_string(&mut self, quote: char) -> Result<String, String> {
+    fn parse_quoted_string(&mut self, quote: char) -> ParseResult<String> {
+        let start = self.pos;
         self.expect_char(quote)?;
         let mut result = String::new();
 
         while let Some(c) = self.peek() {
             if c == '\\' {
                 // Handle escape sequences
+                let escape_start = self.pos;
                 self.advance(); // consume backslash
                 match self.peek() {
                     Some('"') => {
@@ -595,10 +1376,7 @@ impl<'a> SelectorParser<'a> {
                         self.advance();
                     }
                     None => {
-                        return Err(format!(
-                            "Unterminated escape sequence at position {}",
-                            self.pos
-                        ));
+                        return Err(self.error_at(escape_start, "Unterminated escape sequence"));
                     }
                 }
             } else if c == quote {
@@ -610,15 +1388,16 @@ impl<'a> SelectorParser<'a> {
             }
         }
 
-        Err(format!(
-            "Unterminated string starting at position {}",
-            self.pos
+        Err(self.expected_at(
+            start,
+            &format!("closing {}", quote),
+            format!("Unterminated string (missing closing {})", quote),
         ))
     }
 
     /// Parse an unquoted value (stops at ] or , or whitespace)
     /// Note: The caller should validate empty values based on the operator
-    fn parse_unquoted_value(&mut self) -> Result<String, String> {
+    fn parse_unquoted_value(&mut self) -> ParseResult<String> {
         let start = self.pos;
 
         while let Some(c) = self.peek() {
@@ -634,40 +1413,81 @@ impl<'a> SelectorParser<'a> {
     }
 
     /// Expect a specific string
-    fn expect_str(&mut self, s: &str) -> Result<(), String> {
-        if self.input[self.pos..].starts_with(s) {
+    fn expect_str(&mut self, s: &str) -> ParseResult<()> {
+        if self.rest().starts_with(s) {
             self.pos += s.len();
             Ok(())
         } else {
-            Err(format!("Expected '{}' at position {}", s, self.pos))
+            Err(self.expected(&format!("'{}'", s), format!("Expected '{}'", s)))
         }
     }
 
     /// Expect a specific character
-    fn expect_char(&mut self, expected: char) -> Result<(), String> {
+    fn expect_char(&mut self, expected: char) -> ParseResult<()> {
+        let token = format!("'{}'", expected);
         match self.peek() {
             Some(c) if c == expected => {
                 self.advance();
                 Ok(())
             }
-            Some(c) => Err(format!(
-                "Expected '{}' but found '{}' at position {}",
-                expected, c, self.pos
+            Some(c) => {
+                Err(self.expected(&token, format!("Expected '{}' but found '{}'", expected, c)))
+            }
+            None => Err(self.expected(
+                &token,
+                format!("Expected '{}' but reached end of input", expected),
             )),
-            None => Err(format!("Expected '{}' but reached end of input", expected)),
         }
     }
 
-    /// Peek at the current character without consuming
-    fn peek(&self) -> Option<char> {
-        self.input.chars().nth(self.pos)
+    /// Error at `offset` without a specific expected token
+    fn error_at(&self, offset: usize, message: impl Into<String>) -> SelectorError {
+        SelectorError {
+            input: self.input.to_string(),
+            offset,
+            expected: None,
+            message: message.into(),
+        }
     }
 
-    /// Advance to the next character
-    fn advance(&mut self) {
-        if self.pos < self.input.len() {
-            self.pos += 1;
-        }
+    /// Error at the current position without a specific expected token
+    fn error(&self, message: impl Into<String>) -> SelectorError {
+        self.error_at(self.pos, message)
+    }
+
+    /// Error at `offset` where `expected` should have been
+    fn expected_at(
+        &self,
+        offset: usize,
+        expected: &str,
+        message: impl Into<String>,
+    ) -> SelectorError {
+        SelectorError {
+            expected: Some(expected.to_string()),
+            ..self.error_at(offset, message)
+        }
+    }
+
+    /// Error at the current position where `expected` should have been
+    fn expected(&self, expected: &str, message: impl Into<String>) -> SelectorError {
+        self.expected_at(self.pos, expected, message)
+    }
+
+    /// The unparsed part of the input
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..self.end]
+    }
+
+    /// Peek at the current character without consuming
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    /// Advance to the next character
+    fn advance(&mut self) {
+        if let Some(c) = self.peek() {
+            self.pos += c.len_utf8();
+        }
     }
 
     /// Skip whitespace characters
@@ -683,7 +1503,7 @@ impl<'a> SelectorParser<'a> {
 
     /// Check if we've reached the end of input
     fn is_eof(&self) -> bool {
-        self.pos >= self.input.len()
+        self.pos >= self.end
     }
 }
 
@@ -765,8 +1585,11 @@ mod tests {
     fn test_parse_has_selector() {
         let s = Selector::parse(":has([text=Submit])").unwrap();
         match s {
-            Selector::Has(_) => {}
-            _ => panic!("Expected Has selector"),
+            Selector::Complex { attrs, pseudos } => {
+                assert!(attrs.is_empty());
+                assert!(matches!(pseudos.as_slice(), [PseudoClass::Has(_)]));
+            }
+            _ => panic!("Expected Complex selector with :has()"),
         }
     }
 
@@ -774,10 +1597,9 @@ mod tests {
     fn test_parse_complex_with_has() {
         let s = Selector::parse("[class=List]:has([text=Item])").unwrap();
         match s {
-            Selector::Complex { attrs, has, not } => {
+            Selector::Complex { attrs, pseudos } => {
                 assert_eq!(attrs.len(), 1);
-                assert!(has.is_some());
-                assert!(not.is_none());
+                assert!(matches!(pseudos.as_slice(), [PseudoClass::Has(_)]));
             }
             _ => panic!("Expected Complex selector"),
         }
@@ -1199,18 +2021,22 @@ mod tests {
     fn test_parse_not_selector() {
         let s = Selector::parse(":not([clickable=false])").unwrap();
         match s {
-            Selector::Not(inner) => {
+            Selector::Complex { attrs, pseudos } => {
+                assert!(attrs.is_empty());
                 // The inner selector should be [clickable=false]
-                match inner.as_ref() {
-                    Selector::And(clauses) => {
-                        assert_eq!(clauses.len(), 1);
-                        assert_eq!(clauses[0].attr, "clickable");
-                        assert_eq!(clauses[0].value, "false");
-                    }
-                    _ => panic!("Expected And selector inside :not()"),
+                match pseudos.as_slice() {
+                    [PseudoClass::Not(inner)] => match inner.as_ref() {
+                        Selector::And(clauses) => {
+                            assert_eq!(clauses.len(), 1);
+                            assert_eq!(clauses[0].attr, "clickable");
+                            assert_eq!(clauses[0].value, "false");
+                        }
+                        _ => panic!("Expected And selector inside :not()"),
+                    },
+                    _ => panic!("Expected a single :not()"),
                 }
             }
-            _ => panic!("Expected Not selector"),
+            _ => panic!("Expected Complex selector with :not()"),
         }
     }
 
@@ -1218,13 +2044,12 @@ mod tests {
     fn test_parse_not_with_attrs() {
         let s = Selector::parse("[text*=Confirm]:not([clickable=false])").unwrap();
         match s {
-            Selector::Complex { attrs, has, not } => {
+            Selector::Complex { attrs, pseudos } => {
                 assert_eq!(attrs.len(), 1);
                 assert_eq!(attrs[0].attr, "text");
                 assert_eq!(attrs[0].op, AttrOp::Contains);
                 assert_eq!(attrs[0].value, "Confirm");
-                assert!(has.is_none());
-                assert!(not.is_some());
+                assert!(matches!(pseudos.as_slice(), [PseudoClass::Not(_)]));
             }
             _ => panic!("Expected Complex selector"),
         }
@@ -1531,11 +2356,16 @@ mod tests {
         // :not(:has([text=A])) - element that does NOT have a descendant with text=A
         let s = Selector::parse(":not(:has([text=Submit]))").unwrap();
         match s {
-            Selector::Not(inner) => match inner.as_ref() {
-                Selector::Has(_) => {}
-                _ => panic!("Expected Has selector inside Not"),
+            Selector::Complex { pseudos, .. } => match pseudos.as_slice() {
+                [PseudoClass::Not(inner)] => match inner.as_ref() {
+                    Selector::Complex { pseudos, .. } => {
+                        assert!(matches!(pseudos.as_slice(), [PseudoClass::Has(_)]));
+                    }
+                    _ => panic!("Expected :has() inside :not()"),
+                },
+                _ => panic!("Expected a single :not()"),
             },
-            _ => panic!("Expected Not selector"),
+            _ => panic!("Expected Complex selector"),
         }
 
         // Test matching
@@ -1557,11 +2387,16 @@ mod tests {
         // :has(:not([clickable=false])) - element that has a descendant that is NOT clickable=false
         let s = Selector::parse(":has(:not([clickable=false]))").unwrap();
         match s {
-            Selector::Has(inner) => match inner.as_ref() {
-                Selector::Not(_) => {}
-                _ => panic!("Expected Not selector inside Has"),
+            Selector::Complex { pseudos, .. } => match pseudos.as_slice() {
+                [PseudoClass::Has(inner)] => match inner.as_ref() {
+                    Selector::Complex { pseudos, .. } => {
+                        assert!(matches!(pseudos.as_slice(), [PseudoClass::Not(_)]));
+                    }
+                    _ => panic!("Expected :not() inside :has()"),
+                },
+                _ => panic!("Expected a single :has()"),
             },
-            _ => panic!("Expected Has selector"),
+            _ => panic!("Expected Complex selector"),
         }
 
         let xml = r##"<node class="Container"><node text="Button" clickable="true" /></node>"##;
@@ -1578,10 +2413,13 @@ mod tests {
         let result = Selector::parse("[class=A]:has([text=B]):has([text=C])");
         assert!(result.is_ok());
         match result.unwrap() {
-            Selector::Complex { attrs, has, not } => {
+            Selector::Complex { attrs, pseudos } => {
                 assert_eq!(attrs.len(), 1);
-                assert!(has.is_some());
-                assert!(not.is_none());
+                // Both :has() are kept
+                assert!(matches!(
+                    pseudos.as_slice(),
+                    [PseudoClass::Has(_), PseudoClass::Has(_)]
+                ));
             }
             _ => panic!("Expected Complex selector"),
         }
@@ -1593,10 +2431,13 @@ mod tests {
         let result = Selector::parse("[class=A]:not([text=B]):not([text=C])");
         assert!(result.is_ok());
         match result.unwrap() {
-            Selector::Complex { attrs, has, not } => {
+            Selector::Complex { attrs, pseudos } => {
                 assert_eq!(attrs.len(), 1);
-                assert!(has.is_none());
-                assert!(not.is_some());
+                // Both :not() are kept
+                assert!(matches!(
+                    pseudos.as_slice(),
+                    [PseudoClass::Not(_), PseudoClass::Not(_)]
+                ));
             }
             _ => panic!("Expected Complex selector"),
         }
@@ -1643,8 +2484,11 @@ mod tests {
 
     #[test]
     fn test_missing_operator_in_attr() {
-        // Missing operator should error
-        let result = Selector::parse("[text]");
+        // A value without an operator should error
+        // (a bare `[text]` is an existence check, see test_parse_exists_operator)
+        let result = Selector::parse("[text Submit]");
+        assert!(result.is_err());
+        let result = Selector::parse("[text \"Submit\"]");
         assert!(result.is_err());
     }
 
@@ -1852,6 +2696,8 @@ mod tests {
                         attr: "text".to_string(),
                         op: AttrOp::Equals,
                         value: "".to_string(),
+                        flags: AttrFlags::default(),
+                        regex: None,
                     }])
         );
     }
@@ -2230,4 +3076,978 @@ mod tests {
         let selector = Selector::parse("[text*=\"\\\"Hello\\\"\"]").unwrap();
         assert!(selector.matches(node));
     }
+
+    // Tests for the regex operator (~=)
+    #[test]
+    fn test_parse_regex_operator() {
+        let s = Selector::parse(r##"[text~="^Item \d+$"]"##).unwrap();
+        match s {
+            Selector::And(clauses) => {
+                assert_eq!(clauses[0].attr, "text");
+                assert_eq!(clauses[0].op, AttrOp::Matches);
+                assert_eq!(clauses[0].value, r##"^Item \d+$"##);
+            }
+            _ => panic!("Expected And selector"),
+        }
+    }
+
+    #[test]
+    fn test_matches_regex_operator() {
+        let xml = r##"<node><node text="Item 42" /><node text="Item X" /><node text="My Item 7" /></node>"##;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let nodes: Vec<_> = doc.root_element().children().collect();
+
+        let selector = Selector::parse(r##"[text~="^Item \d+$"]"##).unwrap();
+        assert!(selector.matches(nodes[0]));
+        assert!(!selector.matches(nodes[1]));
+        assert!(!selector.matches(nodes[2]));
+
+        // Without anchors the pattern may match anywhere in the value
+        let selector = Selector::parse(r##"[text~="Item \d"]"##).unwrap();
+        assert!(selector.matches(nodes[0]));
+        assert!(selector.matches(nodes[2]));
+    }
+
+    #[test]
+    fn test_matches_regex_price() {
+        let xml = r##"<node text="$12.99" />"##;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let node = doc.root_element();
+
+        let selector = Selector::parse(r##"[text~='^\$\d+\.\d{2}$']"##).unwrap();
+        assert!(selector.matches(node));
+    }
+
+    #[test]
+    fn test_regex_operator_with_other_clauses() {
+        let xml = r##"<node class="Row"><node class="TextView" text="Order #1234" /></node>"##;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let row = doc.root_element();
+
+        let selector = Selector::parse(r##"[class=Row]:has([text~="#\d{4}"])"##).unwrap();
+        assert!(selector.matches(row));
+    }
+
+    #[test]
+    fn test_regex_operator_invalid_pattern() {
+        let result = Selector::parse("[text~=\"(unclosed\"]");
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .message
+            .contains("Invalid regular expression"));
+    }
+
+    #[test]
+    fn test_regex_operator_empty_value() {
+        assert!(Selector::parse("[text~=\"\"]").is_err());
+    }
+
+    #[test]
+    fn test_regex_operator_equality() {
+        assert_eq!(
+            Selector::parse("[text~=a+]").unwrap(),
+            Selector::parse("[text~='a+']").unwrap()
+        );
+        assert_ne!(
+            Selector::parse("[text~=a+]").unwrap(),
+            Selector::parse("[text~=b+]").unwrap()
+        );
+    }
+
+    // Tests for attribute flags (i, n)
+    #[test]
+    fn test_parse_attr_flags() {
+        let s = Selector::parse("[text=\"submit\" i]").unwrap();
+        match s {
+            Selector::And(clauses) => {
+                assert_eq!(clauses[0].value, "submit");
+                assert!(clauses[0].flags.case_insensitive);
+                assert!(!clauses[0].flags.normalize_whitespace);
+            }
+            _ => panic!("Expected And selector"),
+        }
+
+        let s = Selector::parse("[text=submit i n]").unwrap();
+        match s {
+            Selector::And(clauses) => {
+                assert_eq!(clauses[0].value, "submit");
+                assert!(clauses[0].flags.case_insensitive);
+                assert!(clauses[0].flags.normalize_whitespace);
+            }
+            _ => panic!("Expected And selector"),
+        }
+    }
+
+    #[test]
+    fn test_parse_unknown_attr_flag() {
+        assert!(Selector::parse("[text=\"submit\" x]").is_err());
+        assert!(Selector::parse("[text=\"submit\" in]").is_err());
+    }
+
+    #[test]
+    fn test_matches_case_insensitive() {
+        let xml = r##"<node text="SUBMIT ORDER" />"##;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let node = doc.root_element();
+
+        assert!(!Selector::parse("[text=\"Submit Order\"]")
+            .unwrap()
+            .matches(node));
+        assert!(Selector::parse("[text=\"Submit Order\" i]")
+            .unwrap()
+            .matches(node));
+        assert!(Selector::parse("[text^=submit i]").unwrap().matches(node));
+        assert!(Selector::parse("[text$=Order i]").unwrap().matches(node));
+        assert!(Selector::parse("[text*=\"t o\" i]").unwrap().matches(node));
+        assert!(Selector::parse("[text~=\"^submit\" i]")
+            .unwrap()
+            .matches(node));
+    }
+
+    #[test]
+    fn test_matches_normalize_whitespace() {
+        let xml = "<node text=\"  Submit&#10;   order \" />";
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let node = doc.root_element();
+
+        assert!(!Selector::parse("[text=\"Submit order\"]")
+            .unwrap()
+            .matches(node));
+        assert!(Selector::parse("[text=\"Submit order\" n]")
+            .unwrap()
+            .matches(node));
+        assert!(Selector::parse("[text=\" Submit  order\" n]")
+            .unwrap()
+            .matches(node));
+        assert!(Selector::parse("[text~=\"^Submit order$\" n]")
+            .unwrap()
+            .matches(node));
+        assert!(Selector::parse("[text=\"SUBMIT ORDER\" i n]")
+            .unwrap()
+            .matches(node));
+    }
+
+    // Tests for existence, inequality and numeric operators
+    #[test]
+    fn test_parse_exists_operator() {
+        let s = Selector::parse("[content-desc]").unwrap();
+        match s {
+            Selector::And(clauses) => {
+                assert_eq!(clauses[0].attr, "content-desc");
+                assert_eq!(clauses[0].op, AttrOp::Exists);
+                assert_eq!(clauses[0].value, "");
+            }
+            _ => panic!("Expected And selector"),
+        }
+
+        let s = Selector::parse("[ text n ][class=Button]").unwrap();
+        match s {
+            Selector::And(clauses) => {
+                assert_eq!(clauses[0].op, AttrOp::Exists);
+                assert!(clauses[0].flags.normalize_whitespace);
+                assert_eq!(clauses[1].attr, "class");
+            }
+            _ => panic!("Expected And selector"),
+        }
+    }
+
+    #[test]
+    fn test_matches_exists_operator() {
+        let xml = r##"<node><node content-desc="Menu" /><node content-desc="" /><node text="x" /><node content-desc="  " /></node>"##;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let nodes: Vec<_> = doc.root_element().children().collect();
+
+        let selector = Selector::parse("[content-desc]").unwrap();
+        assert!(selector.matches(nodes[0]));
+        assert!(!selector.matches(nodes[1]));
+        assert!(!selector.matches(nodes[2]));
+        assert!(selector.matches(nodes[3]));
+
+        // With whitespace normalization, blank values count as empty
+        let selector = Selector::parse("[content-desc n]").unwrap();
+        assert!(!selector.matches(nodes[3]));
+    }
+
+    #[test]
+    fn test_matches_not_equals_operator() {
+        let xml =
+            r##"<node><node text="OK" /><node text="Cancel" /><node class="Empty" /></node>"##;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let nodes: Vec<_> = doc.root_element().children().collect();
+
+        let selector = Selector::parse("[text!=OK]").unwrap();
+        assert!(!selector.matches(nodes[0]));
+        assert!(selector.matches(nodes[1]));
+        assert!(selector.matches(nodes[2]));
+
+        let selector = Selector::parse("[text!=ok i]").unwrap();
+        assert!(!selector.matches(nodes[0]));
+
+        let selector = Selector::parse("[text!=\"\"]").unwrap();
+        assert!(selector.matches(nodes[0]));
+    }
+
+    #[test]
+    fn test_parse_numeric_operators() {
+        for (input, op) in [
+            ("[index>3]", AttrOp::GreaterThan),
+            ("[index>=3]", AttrOp::GreaterOrEqual),
+            ("[index<3]", AttrOp::LessThan),
+            ("[index <= 3]", AttrOp::LessOrEqual),
+        ] {
+            match Selector::parse(input).unwrap() {
+                Selector::And(clauses) => {
+                    assert_eq!(clauses[0].attr, "index");
+                    assert_eq!(clauses[0].op, op);
+                    assert_eq!(clauses[0].value, "3");
+                }
+                _ => panic!("Expected And selector"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_numeric_operator_requires_number() {
+        assert!(Selector::parse("[index>=abc]").is_err());
+        assert!(Selector::parse("[index>=\"\"]").is_err());
+        assert!(Selector::parse("[index>= 2.5]").is_ok());
+    }
+
+    #[test]
+    fn test_matches_numeric_operators() {
+        let xml = r##"<node><node index="0" /><node index="3" /><node index="10" /><node index="x" /></node>"##;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let nodes: Vec<_> = doc.root_element().children().collect();
+
+        let selector = Selector::parse("[index>=3]").unwrap();
+        assert!(!selector.matches(nodes[0]));
+        assert!(selector.matches(nodes[1]));
+        // Compared as numbers, not strings
+        assert!(selector.matches(nodes[2]));
+        assert!(!selector.matches(nodes[3]));
+
+        let selector = Selector::parse("[index>3]").unwrap();
+        assert!(!selector.matches(nodes[1]));
+        assert!(selector.matches(nodes[2]));
+
+        let selector = Selector::parse("[index<3]").unwrap();
+        assert!(selector.matches(nodes[0]));
+        assert!(!selector.matches(nodes[1]));
+
+        let selector = Selector::parse("[index<=3]").unwrap();
+        assert!(selector.matches(nodes[1]));
+        assert!(!selector.matches(nodes[2]));
+    }
+
+    #[test]
+    fn test_matches_derived_bounds_properties() {
+        let xml = r##"<node bounds="[100,200][400,300]" />"##;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let node = doc.root_element();
+
+        assert!(Selector::parse("[width=300]").unwrap().matches(node));
+        assert!(Selector::parse("[height=100]").unwrap().matches(node));
+        assert!(Selector::parse("[width>200][height<150]")
+            .unwrap()
+            .matches(node));
+        assert!(
+            Selector::parse("[left=100][top=200][right=400][bottom=300]")
+                .unwrap()
+                .matches(node)
+        );
+        assert!(!Selector::parse("[width>300]").unwrap().matches(node));
+    }
+
+    #[test]
+    fn test_derived_property_prefers_real_attribute() {
+        let xml = r##"<node width="5" bounds="[0,0][100,100]" />"##;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let node = doc.root_element();
+
+        assert!(Selector::parse("[width=5]").unwrap().matches(node));
+    }
+
+    #[test]
+    fn test_numeric_operator_not_confused_with_child_combinator() {
+        let xml = r##"<node class="List"><node index="4" text="Add" /></node>"##;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let item = doc.root_element().first_child().unwrap();
+
+        let selector = Selector::parse("[class=List] > [index>3]").unwrap();
+        assert!(selector.matches(item));
+        let selector = Selector::parse("[class=List]>[index>=4][text=Add]").unwrap();
+        assert!(selector.matches(item));
+    }
+
+    // Tests for positional pseudo-classes
+    const LIST_XML: &str = r##"<node class="List"><node text="A" /><node text="B" /><node text="C" /><node text="D" /><node text="E" /></node>"##;
+
+    fn list_items<'a, 'input>(
+        doc: &'a roxmltree::Document<'input>,
+    ) -> Vec<roxmltree::Node<'a, 'input>> {
+        doc.root_element().children().collect()
+    }
+
+    fn matching_texts(doc: &roxmltree::Document, selector: &str) -> Vec<String> {
+        let selector = Selector::parse(selector).unwrap();
+        doc.descendants()
+            .filter(|n| selector.matches(*n))
+            .filter_map(|n| n.attribute("text").map(|t| t.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_positional_pseudo_classes() {
+        let s = Selector::parse("[text=A]:first-child:nth-child(2n+1)").unwrap();
+        match s {
+            Selector::Complex { attrs, pseudos, .. } => {
+                assert_eq!(attrs.len(), 1);
+                assert_eq!(
+                    pseudos,
+                    vec![
+                        PseudoClass::FirstChild,
+                        PseudoClass::NthChild(NthExpr { a: 2, b: 1 })
+                    ]
+                );
+            }
+            _ => panic!("Expected Complex selector"),
+        }
+
+        let s = Selector::parse(":only-child").unwrap();
+        match s {
+            Selector::Complex { attrs, pseudos, .. } => {
+                assert!(attrs.is_empty());
+                assert_eq!(pseudos, vec![PseudoClass::OnlyChild]);
+            }
+            _ => panic!("Expected Complex selector"),
+        }
+    }
+
+    #[test]
+    fn test_parse_nth_expressions() {
+        assert_eq!(NthExpr::parse("3").unwrap(), NthExpr { a: 0, b: 3 });
+        assert_eq!(NthExpr::parse("odd").unwrap(), NthExpr { a: 2, b: 1 });
+        assert_eq!(NthExpr::parse("even").unwrap(), NthExpr { a: 2, b: 0 });
+        assert_eq!(NthExpr::parse("n").unwrap(), NthExpr { a: 1, b: 0 });
+        assert_eq!(NthExpr::parse("-n+3").unwrap(), NthExpr { a: -1, b: 3 });
+        assert_eq!(NthExpr::parse("3n - 1").unwrap(), NthExpr { a: 3, b: -1 });
+        assert!(NthExpr::parse("").is_err());
+        assert!(NthExpr::parse("abc").is_err());
+        assert!(NthExpr::parse("2n3").is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid_positional_pseudo_classes() {
+        assert!(Selector::parse(":nth-child(x)").is_err());
+        assert!(Selector::parse(":nth-child(2").is_err());
+        assert!(Selector::parse("[text=A]:nth-match(0)").is_err());
+        assert!(Selector::parse("[text=A]:nth-match(-1)").is_err());
+        assert!(Selector::parse("[text=A]:first-childish").is_err());
+    }
+
+    #[test]
+    fn test_matches_first_last_only_child() {
+        let doc = roxmltree::Document::parse(LIST_XML).unwrap();
+        let items = list_items(&doc);
+
+        let first = Selector::parse(":first-child").unwrap();
+        assert!(first.matches(items[0]));
+        assert!(!first.matches(items[1]));
+
+        let last = Selector::parse(":last-child").unwrap();
+        assert!(last.matches(items[4]));
+        assert!(!last.matches(items[3]));
+
+        let only = Selector::parse(":only-child").unwrap();
+        assert!(!only.matches(items[0]));
+        // The List itself is the only child of the document root
+        assert!(only.matches(doc.root_element()));
+    }
+
+    #[test]
+    fn test_matches_nth_child() {
+        let doc = roxmltree::Document::parse(LIST_XML).unwrap();
+
+        assert_eq!(
+            matching_texts(&doc, "[class=List] > :nth-child(2)"),
+            vec!["B"]
+        );
+        assert_eq!(
+            matching_texts(&doc, "[class=List] > :nth-child(odd)"),
+            vec!["A", "C", "E"]
+        );
+        assert_eq!(
+            matching_texts(&doc, "[class=List] > :nth-child(even)"),
+            vec!["B", "D"]
+        );
+        assert_eq!(
+            matching_texts(&doc, "[class=List] > :nth-child(-n+2)"),
+            vec!["A", "B"]
+        );
+        assert_eq!(
+            matching_texts(&doc, "[class=List] > :nth-child(n+4)"),
+            vec!["D", "E"]
+        );
+    }
+
+    #[test]
+    fn test_nth_child_ignores_text_nodes() {
+        let xml = "<node class=\"List\">\n  <node text=\"A\" />\n  <node text=\"B\" />\n</node>";
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        assert_eq!(matching_texts(&doc, ":nth-child(2)"), vec!["B"]);
+        assert_eq!(matching_texts(&doc, "[text]:last-child"), vec!["B"]);
+    }
+
+    #[test]
+    fn test_matches_nth_match() {
+        let xml = r##"<node><node class="Row"><node text="Add" id="1" /></node><node class="Row"><node text="Add" id="2" /></node><node class="Row"><node text="Add" id="3" /></node></node>"##;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+
+        let selector = Selector::parse("[text=Add]:nth-match(3)").unwrap();
+        let ids: Vec<_> = doc
+            .descendants()
+            .filter(|n| selector.matches(*n))
+            .filter_map(|n| n.attribute("id"))
+            .collect();
+        assert_eq!(ids, vec!["3"]);
+
+        // Out of range matches nothing
+        let selector = Selector::parse("[text=Add]:nth-match(4)").unwrap();
+        assert_eq!(
+            doc.descendants().filter(|n| selector.matches(*n)).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_nth_match_counts_rest_of_compound() {
+        let doc = roxmltree::Document::parse(LIST_XML).unwrap();
+        // Second element that is not "B"
+        assert_eq!(
+            matching_texts(&doc, "[text]:not([text=B]):nth-match(2)"),
+            vec!["C"]
+        );
+        // Positional pseudo-classes are applied before counting
+        assert_eq!(
+            matching_texts(&doc, "[text]:nth-child(odd):nth-match(2)"),
+            vec!["C"]
+        );
+    }
+
+    #[test]
+    fn test_nth_match_ignores_combinators() {
+        let doc = roxmltree::Document::parse(
+            r##"<node class="Root"><node class="Toolbar"><node text="Add" id="toolbar" /></node><node class="List"><node text="Add" id="first" /><node text="Add" id="second" /></node></node>"##,
+        )
+        .unwrap();
+        // The 2nd [text=Add] on the screen, which happens to be the 1st one in the list
+        assert_eq!(
+            matching_ids(&doc, "[class=List] > [text=Add]:nth-match(2)"),
+            vec!["first"]
+        );
+        // The 2nd match of the whole selector, as picked by --index 1
+        assert_eq!(
+            matching_ids(&doc, ":is([class=List] > [text=Add]):nth-match(2)"),
+            vec!["second"]
+        );
+    }
+
+    const FORM_XML: &str = r##"<node class="Form"><node class="TextView" text="Name" /><node class="EditText" id="name" /><node class="TextView" text="Email" /><node class="EditText" id="email" /><node class="Button" text="Submit" /></node>"##;
+
+    fn matching_ids(doc: &roxmltree::Document, selector: &str) -> Vec<String> {
+        let selector = Selector::parse(selector).unwrap();
+        doc.descendants()
+            .filter(|n| selector.matches(*n))
+            .filter_map(|n| n.attribute("id").map(|id| id.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_sibling_combinators() {
+        let s = Selector::parse("[text=Email] + [class=EditText]").unwrap();
+        match s {
+            Selector::AdjacentSibling { prev, next } => {
+                assert!(matches!(*prev, Selector::And(_)));
+                assert!(matches!(*next, Selector::And(_)));
+            }
+            _ => panic!("Expected AdjacentSibling selector"),
+        }
+
+        let s = Selector::parse("[text=Email]~[class=EditText]").unwrap();
+        assert!(matches!(s, Selector::GeneralSibling { .. }));
+    }
+
+    #[test]
+    fn test_parse_mixed_combinators_left_associative() {
+        // A > B + C becomes ((A > B) + C)
+        let s = Selector::parse("[class=Form] > [text=Email] + [class=EditText]").unwrap();
+        match s {
+            Selector::AdjacentSibling { prev, .. } => {
+                assert!(matches!(*prev, Selector::Child { .. }));
+            }
+            _ => panic!("Expected AdjacentSibling selector"),
+        }
+    }
+
+    #[test]
+    fn test_matches_adjacent_sibling() {
+        let doc = roxmltree::Document::parse(FORM_XML).unwrap();
+        assert_eq!(
+            matching_ids(&doc, "[text=Email] + [class=EditText]"),
+            vec!["email"]
+        );
+        // Only the element right after matches
+        assert!(matching_ids(&doc, "[text=Name] + [text=Email]").is_empty());
+        assert!(matching_ids(&doc, "[text=Email] + [class=TextView]").is_empty());
+    }
+
+    #[test]
+    fn test_matches_general_sibling() {
+        let doc = roxmltree::Document::parse(FORM_XML).unwrap();
+        assert_eq!(
+            matching_ids(&doc, "[text=Name] ~ [class=EditText]"),
+            vec!["name", "email"]
+        );
+        // Earlier siblings do not match
+        assert!(matching_ids(&doc, "[text=Submit] ~ [class=EditText]").is_empty());
+    }
+
+    #[test]
+    fn test_sibling_combinator_with_descendant_and_child() {
+        let xml = r##"<node class="Screen"><node class="Form"><node text="Email" /><node class="EditText" id="inner" /></node></node>"##;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        assert_eq!(
+            matching_ids(&doc, "[class=Screen] [text=Email] + [class=EditText]"),
+            vec!["inner"]
+        );
+        assert_eq!(
+            matching_ids(&doc, "[class=Form] > [text=Email] ~ [class=EditText]"),
+            vec!["inner"]
+        );
+        assert!(matching_ids(&doc, "[class=Other] [text=Email] + [class=EditText]").is_empty());
+    }
+
+    #[test]
+    fn test_sibling_combinator_missing_right_side() {
+        assert!(Selector::parse("[text=Email] +").is_err());
+        assert!(Selector::parse("[text=Email] ~").is_err());
+        assert!(Selector::parse("+ [text=Email]").is_err());
+    }
+
+    #[test]
+    fn test_plus_inside_nth_child_is_not_a_combinator() {
+        let doc = roxmltree::Document::parse(FORM_XML).unwrap();
+        assert_eq!(
+            matching_ids(&doc, "[class=Form] > :nth-child(2n+2)"),
+            vec!["name", "email"]
+        );
+    }
+
+    // A settings screen whose rows are flattened: labels and switches are siblings
+    const SETTINGS_XML: &str = r##"<node class="List" bounds="[0,0][1080,600]"><node text="Wi-Fi" bounds="[40,0][400,100]" /><node class="Switch" id="wifi" bounds="[900,20][1040,80]" /><node text="Bluetooth" bounds="[40,100][400,200]" /><node class="Switch" id="bluetooth" bounds="[900,120][1040,180]" /><node text="Username" bounds="[40,300][400,350]" /><node class="EditText" id="username" bounds="[40,360][1040,420]" /><node class="Button" id="icon" bounds="[0,0][30,30]" /></node>"##;
+
+    #[test]
+    fn test_parse_layout_pseudo_classes() {
+        let s = Selector::parse("[class=Switch]:right-of([text=Wi-Fi])").unwrap();
+        match s {
+            Selector::Complex { pseudos, .. } => {
+                assert!(matches!(
+                    pseudos.as_slice(),
+                    [PseudoClass::Layout(LayoutRelation::RightOf, _)]
+                ));
+            }
+            _ => panic!("Expected Complex selector"),
+        }
+
+        let s = Selector::parse(":near([text=A],[text=B])").unwrap();
+        match s {
+            Selector::Complex { pseudos, .. } => match pseudos.as_slice() {
+                [PseudoClass::Layout(LayoutRelation::Near(50), anchor)] => {
+                    assert!(matches!(**anchor, Selector::Or(_)));
+                }
+                _ => panic!("Expected :near() with the default distance"),
+            },
+            _ => panic!("Expected Complex selector"),
+        }
+
+        let s = Selector::parse(":near([text=A], 120)").unwrap();
+        match s {
+            Selector::Complex { pseudos, .. } => assert!(matches!(
+                pseudos.as_slice(),
+                [PseudoClass::Layout(LayoutRelation::Near(120), _)]
+            )),
+            _ => panic!("Expected Complex selector"),
+        }
+    }
+
+    #[test]
+    fn test_parse_layout_pseudo_class_errors() {
+        assert!(Selector::parse(":below()").is_err());
+        assert!(Selector::parse(":below([text=A]").is_err());
+        assert!(Selector::parse(":inside([text=A], 10)").is_err());
+    }
+
+    #[test]
+    fn test_matches_right_of_and_left_of() {
+        let doc = roxmltree::Document::parse(SETTINGS_XML).unwrap();
+        assert_eq!(
+            matching_ids(&doc, "[class=Switch]:right-of([text=Wi-Fi])"),
+            vec!["wifi"]
+        );
+        assert_eq!(
+            matching_ids(&doc, "[class=Switch]:right-of([text=Bluetooth])"),
+            vec!["bluetooth"]
+        );
+        assert!(matching_ids(&doc, "[class=Switch]:left-of([text=Wi-Fi])").is_empty());
+        assert_eq!(
+            matching_ids(&doc, "[class=Button]:left-of([text=Wi-Fi])"),
+            vec!["icon"]
+        );
+    }
+
+    #[test]
+    fn test_matches_below_and_above() {
+        let doc = roxmltree::Document::parse(SETTINGS_XML).unwrap();
+        assert_eq!(
+            matching_ids(&doc, "[class=EditText]:below([text=Username])"),
+            vec!["username"]
+        );
+        assert!(matching_ids(&doc, "[class=EditText]:above([text=Username])").is_empty());
+        // Switches are not horizontally aligned with the label
+        assert!(matching_ids(&doc, "[class=Switch]:below([text=Wi-Fi])").is_empty());
+        assert_eq!(
+            matching_ids(&doc, "[class=Switch]:above([class=EditText])"),
+            vec!["wifi", "bluetooth"]
+        );
+    }
+
+    #[test]
+    fn test_matches_near() {
+        let doc = roxmltree::Document::parse(SETTINGS_XML).unwrap();
+        assert_eq!(
+            matching_ids(&doc, "[class=EditText]:near([text=Username])"),
+            vec!["username"]
+        );
+        assert!(matching_ids(&doc, "[class=Switch]:near([text=Wi-Fi])").is_empty());
+        assert_eq!(
+            matching_ids(&doc, "[class=Switch]:near([text=Wi-Fi], 500)"),
+            vec!["wifi", "bluetooth"]
+        );
+    }
+
+    #[test]
+    fn test_matches_inside() {
+        let doc = roxmltree::Document::parse(SETTINGS_XML).unwrap();
+        assert_eq!(
+            matching_ids(&doc, "[class=Switch]:inside([class=List])"),
+            vec!["wifi", "bluetooth"]
+        );
+        assert!(matching_ids(&doc, "[class=Switch]:inside([text=Wi-Fi])").is_empty());
+    }
+
+    #[test]
+    fn test_layout_pseudo_class_never_matches_the_anchor_itself() {
+        let doc = roxmltree::Document::parse(SETTINGS_XML).unwrap();
+        let selector = Selector::parse("[text=Wi-Fi]:near([text=Wi-Fi])").unwrap();
+        assert_eq!(
+            doc.descendants().filter(|n| selector.matches(*n)).count(),
+            0
+        );
+    }
+
+    const TABLE_XML: &str = r##"<node class="Table"><node class="Row" id="milk-1"><node text="Milk" /><node text="1" /></node><node class="Row" id="milk-2"><node text="Milk" /><node text="2" /></node><node class="Row" id="milk-2-disabled" enabled="false"><node text="Milk" /><node text="2" /></node><node class="Row" id="eggs-2"><node text="Eggs" /><node text="2" /></node></node>"##;
+
+    #[test]
+    fn test_multiple_has_are_combined_with_and() {
+        let doc = roxmltree::Document::parse(TABLE_XML).unwrap();
+        assert_eq!(
+            matching_ids(&doc, r#"[class=Row]:has([text=Milk]):has([text="2"])"#),
+            vec!["milk-2", "milk-2-disabled"]
+        );
+        assert_eq!(
+            matching_ids(
+                &doc,
+                r#"[class=Row]:has([text=Milk]):has([text="2"]):not([enabled=false])"#
+            ),
+            vec!["milk-2"]
+        );
+    }
+
+    #[test]
+    fn test_multiple_not_are_combined_with_and() {
+        let doc = roxmltree::Document::parse(TABLE_XML).unwrap();
+        assert_eq!(
+            matching_ids(
+                &doc,
+                "[class=Row]:not(:has([text=Eggs])):not(:has([text=1]))"
+            ),
+            vec!["milk-2", "milk-2-disabled"]
+        );
+    }
+
+    #[test]
+    fn test_leading_pseudo_class_chain() {
+        let doc = roxmltree::Document::parse(TABLE_XML).unwrap();
+        assert_eq!(
+            matching_ids(&doc, ":has([text=Eggs]):not([class=Table])"),
+            vec!["eggs-2"]
+        );
+        assert_eq!(
+            matching_ids(&doc, ":not([class=Table]):has([text=Milk]):has([text=1])"),
+            vec!["milk-1"]
+        );
+    }
+
+    const TOOLBAR_XML: &str = r##"<node class="Screen"><node class="android.widget.Toolbar"><node text="Save" id="save-text" /><node content-desc="Save" id="save-icon" /><node text="Share" id="share" /></node><node text="Save" id="save-body" /></node>"##;
+
+    #[test]
+    fn test_parse_is_selector() {
+        let s = Selector::parse(":is([text=Save],[content-desc=Save])").unwrap();
+        match s {
+            Selector::Complex { attrs, pseudos } => {
+                assert!(attrs.is_empty());
+                match pseudos.as_slice() {
+                    [PseudoClass::Is(inner)] => {
+                        assert!(matches!(inner.as_ref(), Selector::Or(v) if v.len() == 2))
+                    }
+                    _ => panic!("Expected a single :is()"),
+                }
+            }
+            _ => panic!("Expected Complex selector"),
+        }
+        // :where() is an alias of :is()
+        assert_eq!(
+            Selector::parse(":where([text=Save],[content-desc=Save])").unwrap(),
+            Selector::parse(":is([text=Save],[content-desc=Save])").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_matches_is_after_combinator() {
+        let doc = roxmltree::Document::parse(TOOLBAR_XML).unwrap();
+        assert_eq!(
+            matching_ids(
+                &doc,
+                "[class$=Toolbar] > :is([text=Save],[content-desc=Save])"
+            ),
+            vec!["save-text", "save-icon"]
+        );
+        assert_eq!(
+            matching_ids(&doc, ":where([text=Save],[text=Share]) ~ [text=Share]"),
+            vec!["share"]
+        );
+    }
+
+    #[test]
+    fn test_matches_is_inside_compound() {
+        let doc = roxmltree::Document::parse(TOOLBAR_XML).unwrap();
+        assert_eq!(
+            matching_ids(
+                &doc,
+                "[id^=save]:is([text=Save],[content-desc=Save]):last-child"
+            ),
+            vec!["save-body"]
+        );
+        assert_eq!(
+            matching_ids(&doc, ":is([class=Screen] [text=Save]):not([id=save-text])"),
+            vec!["save-body"]
+        );
+    }
+
+    #[test]
+    fn test_is_selector_errors() {
+        assert!(Selector::parse(":is()").is_err());
+        assert!(Selector::parse(":is([text=Save]").is_err());
+        assert!(Selector::parse(":is([text=Save],)").is_err());
+    }
+
+    fn parse_error(selector: &str) -> SelectorError {
+        Selector::parse(selector).unwrap_err()
+    }
+
+    #[test]
+    fn test_error_unterminated_string_points_at_quote() {
+        let e = parse_error(r#"[text="Submit]"#);
+        assert_eq!(e.offset, 6);
+        assert_eq!(e.expected.as_deref(), Some("closing \""));
+        assert_eq!(e.input, r#"[text="Submit]"#);
+    }
+
+    #[test]
+    fn test_error_unknown_pseudo_class() {
+        let e = parse_error("[text=A]:foo([text=B])");
+        assert_eq!(e.offset, 8);
+        assert_eq!(e.expected.as_deref(), Some("pseudo-class"));
+        assert!(e.message.contains("':foo'"));
+    }
+
+    #[test]
+    fn test_error_offsets_inside_nested_selectors() {
+        // Errors inside :has() point into the full selector
+        let e = parse_error("[class=X]:has([text=B] > :bogus)");
+        assert_eq!(e.offset, 25);
+        let e = parse_error("[class=X]:not([text^=])");
+        assert_eq!(e.offset, 21);
+        assert_eq!(e.expected.as_deref(), Some("value"));
+        let e = parse_error("[class=X]:has([text=B]");
+        assert_eq!(e.offset, 22);
+        assert_eq!(e.expected.as_deref(), Some("')'"));
+    }
+
+    #[test]
+    fn test_error_expected_tokens() {
+        assert_eq!(parse_error("[text").expected.as_deref(), Some("operator"));
+        assert_eq!(
+            parse_error("[=A]").expected.as_deref(),
+            Some("attribute name")
+        );
+        assert_eq!(
+            parse_error("[text=A],").expected.as_deref(),
+            Some("selector")
+        );
+        assert_eq!(
+            parse_error("[text=A] >").expected.as_deref(),
+            Some("'[' or ':'")
+        );
+        assert_eq!(parse_error("[index>=abc]").offset, 8);
+    }
+
+    #[test]
+    fn test_error_render_caret() {
+        let e = parse_error("[text=A]]");
+        assert_eq!(
+            e.render(),
+            "Unexpected character ']' at position 8\n  [text=A]]\n          ^"
+        );
+    }
+
+    #[test]
+    fn test_error_render_caret_after_non_ascii() {
+        // The offset is in bytes, the caret is placed by characters
+        let e = parse_error("[text=Ä]]");
+        assert_eq!(e.offset, 9);
+        assert!(e.render().ends_with("\n          ^"));
+    }
+
+    #[test]
+    fn test_parse_non_ascii_values() {
+        let s = Selector::parse("[text=Größe] > [text=\"日本語\"]").unwrap();
+        match s {
+            Selector::Child { parent, child } => {
+                match *parent {
+                    Selector::And(clauses) => assert_eq!(clauses[0].value, "Größe"),
+                    _ => panic!("Expected And selector"),
+                }
+                match *child {
+                    Selector::And(clauses) => assert_eq!(clauses[0].value, "日本語"),
+                    _ => panic!("Expected And selector"),
+                }
+            }
+            _ => panic!("Expected Child selector"),
+        }
+    }
+
+    #[test]
+    fn test_parentheses_in_quoted_values_inside_has() {
+        let s = Selector::parse(r#"[class=Row]:has([text=")"])"#).unwrap();
+        let xml = r##"<node class="Row"><node text=")" /></node>"##;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        assert!(s.matches(doc.root_element()));
+    }
+
+    #[test]
+    fn test_display_canonical_form() {
+        let cases = [
+            ("[text=Submit]", r#"[text="Submit"]"#),
+            (
+                "[class=Button]  [ text = 'OK' I ]",
+                r#"[class="Button"] [text="OK" i]"#,
+            ),
+            ("[text='Say \"hi\"']", r#"[text="Say \"hi\""]"#),
+            (r#"[text~="^Item \d+$"]"#, r#"[text~="^Item \d+$"]"#),
+            (
+                "[content-desc][width>=300]",
+                r#"[content-desc][width>="300"]"#,
+            ),
+            ("[text=A] , [text=B]", r#"[text="A"],[text="B"]"#),
+            (
+                "[a=1]>[b=2]+[c=3]~[d=4]",
+                r#"[a="1"] > [b="2"] + [c="3"] ~ [d="4"]"#,
+            ),
+            (
+                ":where([text=A],[text=B]):nth-child(odd)",
+                r#":is([text="A"],[text="B"]):nth-child(2n+1)"#,
+            ),
+            (
+                ":nth-child(- n + 3):nth-match( 2 )",
+                ":nth-child(-n+3):nth-match(2)",
+            ),
+            (":nth-child(even):last-child", ":nth-child(2n):last-child"),
+            (
+                "[a]:near([b], 50):near([c],20)",
+                r#"[a]:near([b]):near([c], 20)"#,
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(Selector::parse(input).unwrap().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let selectors = [
+            r#"[text="Submit"]"#,
+            r#"[text^=Sub][text$=mit i][text*="ubm" n][text!=""][text="" i n]"#,
+            r#"[text~="^\d+ (items|item)$" i]"#,
+            r#"[text="back\slash\\"][text='quote " and \' and \\']"#,
+            "[index>2][index>=2.5][index<-1][index<=0]",
+            "[text=A],[text=B],:has([text=C])",
+            "[class=List] > [class=Item]:has([text=A]):not([enabled=false]) [text=Buy]",
+            "[text=A] [text=B] > [text=C] + [text=D] ~ [text=E]",
+            ":has([a=1] > [b=2]):not(:has([c=3])):is([d=4],:is([e=5]) [f=6])",
+            ":first-child:last-child:only-child:nth-child(3):nth-child(n+2):nth-child(-2n-1)",
+            "[text=Add]:nth-match(3)",
+            "[class$=Switch]:right-of([text=Wi-Fi]):left-of(:has([a=b])):above([a],[b])",
+            "[a]:below([b]):inside([c] > [d]):near([e]):near([f],[g], 12)",
+            r#"[text="a)b"]:has([text="(,]"])"#,
+        ];
+        for input in selectors {
+            let selector = Selector::parse(input).unwrap();
+            let text = selector.to_string();
+            assert_eq!(
+                Selector::parse(&text).unwrap(),
+                selector,
+                "{} -> {}",
+                input,
+                text
+            );
+            // Canonical text is a fixed point
+            assert_eq!(Selector::parse(&text).unwrap().to_string(), text);
+        }
+    }
+
+    #[test]
+    fn test_display_or_operand_of_combinator() {
+        let or = Selector::parse("[text=A],[text=B]").unwrap();
+        let child = Selector::parse("[text=C]").unwrap();
+        let selector = Selector::Child {
+            parent: Box::new(or),
+            child: Box::new(child),
+        };
+        assert_eq!(
+            selector.to_string(),
+            r#":is([text="A"],[text="B"]) > [text="C"]"#
+        );
+        assert!(Selector::parse(&selector.to_string()).is_ok());
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("plain"), r#""plain""#);
+        assert_eq!(quote(r#"a "b""#), r#""a \"b\"""#);
+        assert_eq!(quote(r"\d+"), r#""\d+""#);
+        assert_eq!(quote(r"end\"), r#""end\\""#);
+        assert_eq!(quote(r"a\'b"), r#""a\\'b""#);
+    }
 }
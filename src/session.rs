@@ -0,0 +1,477 @@
+use crate::adb_utils::{adb_failure, adb_open_shell, AdbOutput, ShellChannel};
+use crate::backend::{parse_screen_size, screencap, DeviceBackend};
+use crate::error::BochiError;
+use crate::ui_element::extract_hierarchy;
+use std::cell::{Cell, RefCell};
+use std::io::{self, Read, Write};
+use std::process::Child;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// File the hierarchy is dumped to when `uiautomator dump /dev/tty` does not work
+const DUMP_FILE: &str = "/sdcard/window_dump.xml";
+
+/// Longest a single command may run before the session is given up
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A device driven through one long-lived `sh`.
+///
+/// Every command is written to the shell's stdin followed by a `printf` of a numbered end
+/// marker with the exit status, and its output (stderr merged into stdout) is read up to
+/// that marker. This saves the process or connection setup of a separate `adb shell` per
+/// tap, swipe or hierarchy dump.
+pub struct ShellSession {
+    serial: Option<String>,
+    io: RefCell<SessionIo>,
+    tty_dump_unsupported: Cell<bool>,
+    command_timeout: Duration,
+}
+
+struct SessionIo {
+    input: Box<dyn Write>,
+    /// Chunks of the shell's output, read on a separate thread so that waiting for them
+    /// can time out
+    output: Receiver<io::Result<Vec<u8>>>,
+    /// The `adb shell` process, when the shell was not started through the server socket
+    child: Option<Child>,
+    /// Output read past the end of the previous command
+    buffer: Vec<u8>,
+    commands: usize,
+    /// Set when a command timed out and the shell is in an unknown state
+    stuck: bool,
+}
+
+impl ShellSession {
+    /// Start a shell on the device with the given serial, or on the only connected one
+    pub fn open(serial: Option<&str>) -> Result<ShellSession, BochiError> {
+        let (input, output, child): (Box<dyn Write>, Box<dyn Read + Send>, _) =
+            match adb_open_shell(serial)? {
+                ShellChannel::Socket(stream) => {
+                    let reader = stream.try_clone().map_err(|e| {
+                        BochiError::Other(format!("Failed to start adb shell: {}", e))
+                    })?;
+                    (Box::new(stream), Box::new(reader), None)
+                }
+                ShellChannel::Process(mut child) => {
+                    let stdin = child.stdin.take().expect("stdin is piped");
+                    let stdout = child.stdout.take().expect("stdout is piped");
+                    (Box::new(stdin), Box::new(stdout), Some(child))
+                }
+            };
+        let mut session = ShellSession::with_io(input, output, child);
+        session.serial = serial.map(str::to_string);
+        Ok(session)
+    }
+
+    fn with_io(
+        input: Box<dyn Write>,
+        mut output: Box<dyn Read + Send>,
+        child: Option<Child>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || loop {
+            let mut chunk = vec![0u8; 16 * 1024];
+            match output.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    chunk.truncate(n);
+                    if sender.send(Ok(chunk)).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    break;
+                }
+            }
+        });
+        ShellSession {
+            serial: None,
+            io: RefCell::new(SessionIo {
+                input,
+                output: receiver,
+                child,
+                buffer: Vec::new(),
+                commands: 0,
+                stuck: false,
+            }),
+            tty_dump_unsupported: Cell::new(false),
+            command_timeout: COMMAND_TIMEOUT,
+        }
+    }
+
+    /// Run a command line in the shell and wait for it to finish.
+    /// stderr is merged into `stdout` so that messages stay in order. Arguments taken from
+    /// user input must be quoted with `shell_quote`.
+    pub fn run(&self, command: &str) -> Result<AdbOutput, BochiError> {
+        let mut io = self.io.borrow_mut();
+        if io.stuck {
+            return Err(BochiError::Other(
+                "adb shell session failed: a previous command did not finish".to_string(),
+            ));
+        }
+        io.commands += 1;
+        let marker = format!("__bochi_done_{}__", io.commands);
+        // The newline before `}` ends a trailing comment; stdin is kept for the next commands
+        let line = format!(
+            "{{ {}\n}} </dev/null 2>&1; printf '\\n{} %d\\n' $?\n",
+            command, marker
+        );
+        io.input
+            .write_all(line.as_bytes())
+            .and_then(|_| io.input.flush())
+            .map_err(|e| session_error(&mut io, e))?;
+
+        let deadline = Instant::now() + self.command_timeout;
+        loop {
+            if let Some(output) = take_output(&mut io.buffer, &marker) {
+                return Ok(output);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match io.output.recv_timeout(remaining) {
+                Ok(Ok(chunk)) => io.buffer.extend_from_slice(&chunk),
+                Ok(Err(e)) => return Err(session_error(&mut io, e)),
+                Err(RecvTimeoutError::Disconnected) => {
+                    let e = io::Error::new(io::ErrorKind::UnexpectedEof, "shell exited");
+                    return Err(session_error(&mut io, e));
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    // The shell may still be running the command or waiting for more input
+                    io.stuck = true;
+                    if let Some(child) = io.child.as_mut() {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    }
+                    return Err(BochiError::Other(format!(
+                        "adb shell session failed: no response after {} s to: {}",
+                        self.command_timeout.as_secs_f64(),
+                        command
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Run an `input` subcommand, mapping a failure to `InputFailed` with `name`
+    fn input(&self, args: &[&str], name: &str) -> Result<(), BochiError> {
+        let output = self.run(&format!("input {}", shell_join(args)))?;
+
+        if !output.success {
+            return Err(adb_failure(&output.stdout, |msg| {
+                BochiError::InputFailed(format!("{} command failed: {}", name, msg))
+            }));
+        }
+
+        Ok(())
+    }
+
+    /// Run a dump command and extract the XML printed by it (or by a following `cat`)
+    fn dump(&self, command: &str) -> Result<String, BochiError> {
+        let output = self.run(command)?;
+
+        if !output.success {
+            return Err(adb_failure(&output.stdout, |msg| {
+                BochiError::DumpFailed(format!("uiautomator dump failed: {}", msg))
+            }));
+        }
+
+        let stdout = String::from_utf8(output.stdout)
+            .map_err(|e| BochiError::DumpFailed(format!("Invalid UTF-8 in dump output: {}", e)))?;
+        match extract_hierarchy(&stdout) {
+            Some(xml) => Ok(xml.to_string()),
+            None => Err(BochiError::DumpFailed(format!(
+                "uiautomator dump printed no UI hierarchy: {}",
+                stdout.trim()
+            ))),
+        }
+    }
+
+    fn dump_via_file(&self) -> Result<String, BochiError> {
+        self.dump(&format!(
+            "uiautomator dump {} >/dev/null && cat {}",
+            DUMP_FILE, DUMP_FILE
+        ))
+    }
+}
+
+impl DeviceBackend for ShellSession {
+    /// Dumps to `/dev/tty` like `get_ui_hierarchy`, switching to a file on `/sdcard` for
+    /// the rest of the session once that is the way that works
+    fn ui_hierarchy(&self) -> Result<String, BochiError> {
+        if !self.tty_dump_unsupported.get() {
+            match self.dump("uiautomator dump /dev/tty") {
+                Ok(xml) => return Ok(xml),
+                Err(e @ (BochiError::DeviceOffline(_) | BochiError::DeviceUnauthorized(_))) => {
+                    return Err(e)
+                }
+                Err(_) => {
+                    let xml = self.dump_via_file()?;
+                    self.tty_dump_unsupported.set(true);
+                    return Ok(xml);
+                }
+            }
+        }
+        self.dump_via_file()
+    }
+
+    fn tap(&self, x: i32, y: i32) -> Result<(), BochiError> {
+        self.input(&["tap", &x.to_string(), &y.to_string()], "Tap")
+    }
+
+    fn swipe(
+        &self,
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+        duration_ms: u64,
+    ) -> Result<(), BochiError> {
+        let args = [x1, y1, x2, y2].map(|n| n.to_string());
+        let duration = duration_ms.to_string();
+        self.input(
+            &["swipe", &args[0], &args[1], &args[2], &args[3], &duration],
+            "Swipe",
+        )
+    }
+
+    fn input_text(&self, text: &str) -> Result<(), BochiError> {
+        self.input(&["text", text], "Input text")
+    }
+
+    fn screen_size(&self) -> Result<(i32, i32), BochiError> {
+        let output = self.shell(&["wm", "size"]).map_err(|e| match e {
+            BochiError::Other(msg) => {
+                BochiError::Other(format!("Failed to get screen size: {}", msg))
+            }
+            e => e,
+        })?;
+        parse_screen_size(&output).ok_or_else(|| {
+            BochiError::Other(format!("Could not parse screen size from: {}", output))
+        })
+    }
+
+    fn shell(&self, args: &[&str]) -> Result<String, BochiError> {
+        let output = self.run(&shell_join(args))?;
+
+        if !output.success {
+            return Err(adb_failure(&output.stdout, BochiError::Other));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// Uses a separate `adb exec-out` so that the PNG does not go through the text session
+    fn screenshot(&self) -> Result<Vec<u8>, BochiError> {
+        screencap(self.serial.as_deref())
+    }
+}
+
+impl Drop for ShellSession {
+    fn drop(&mut self) {
+        let io = self.io.get_mut();
+        let _ = io.input.write_all(b"exit\n").and_then(|_| io.input.flush());
+        if let Some(child) = io.child.as_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Quote an argument for `sh` so that it is passed on as one word, whatever it contains
+pub fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-+=.,:/@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Quote each argument and join them into a command line
+pub(crate) fn shell_join(args: &[&str]) -> String {
+    args.iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Map a broken session to an error, using the stderr of the `adb shell` process
+/// (e.g. "device offline") when there is one
+fn session_error(io: &mut SessionIo, e: io::Error) -> BochiError {
+    let fallback = |msg: String| BochiError::Other(format!("adb shell session failed: {}", msg));
+    match io.child.as_mut() {
+        Some(child) => {
+            let _ = child.wait();
+            let mut stderr = Vec::new();
+            if let Some(mut pipe) = child.stderr.take() {
+                let _ = pipe.read_to_end(&mut stderr);
+            }
+            if stderr.iter().all(u8::is_ascii_whitespace) {
+                fallback(e.to_string())
+            } else {
+                adb_failure(&stderr, fallback)
+            }
+        }
+        None => fallback(e.to_string()),
+    }
+}
+
+/// Split the output of a command off the front of `buffer` once the line with its end
+/// marker and exit status has been read completely
+fn take_output(buffer: &mut Vec<u8>, marker: &str) -> Option<AdbOutput> {
+    let needle = format!("\n{} ", marker);
+    let start = buffer
+        .windows(needle.len())
+        .position(|window| window == needle.as_bytes())?;
+    let status_start = start + needle.len();
+    let line_end = status_start + buffer[status_start..].iter().position(|&b| b == b'\n')?;
+    let status = String::from_utf8_lossy(&buffer[status_start..line_end])
+        .trim()
+        .parse::<i32>()
+        .unwrap_or(-1);
+    let stdout = buffer[..start].to_vec();
+    buffer.drain(..=line_end);
+    Some(AdbOutput {
+        success: status == 0,
+        stdout,
+        stderr: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Command, Stdio};
+
+    /// A session on a local `sh`, standing in for the one on the device
+    fn local_session() -> ShellSession {
+        let mut child = Command::new("sh")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        ShellSession::with_io(Box::new(stdin), Box::new(stdout), Some(child))
+    }
+
+    #[test]
+    fn test_take_output() {
+        let mut buffer = b"Physical size: 1080x1920\n\n__bochi_done_1__ 0\nnext".to_vec();
+        let output = take_output(&mut buffer, "__bochi_done_1__").unwrap();
+        assert!(output.success);
+        assert_eq!(output.stdout, b"Physical size: 1080x1920\n");
+        assert_eq!(buffer, b"next");
+    }
+
+    #[test]
+    fn test_take_output_incomplete() {
+        let mut buffer = b"partial\n__bochi_done_1__ 1".to_vec();
+        assert_eq!(take_output(&mut buffer, "__bochi_done_1__"), None);
+        buffer.extend(b"\n");
+        let output = take_output(&mut buffer, "__bochi_done_1__").unwrap();
+        assert!(!output.success);
+        assert_eq!(output.stdout, b"partial");
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_run_commands_in_one_shell() {
+        let session = local_session();
+        let output = session.run("x=42; echo $x").unwrap();
+        assert!(output.success);
+        assert_eq!(output.stdout, b"42\n");
+
+        // Shell state is kept between commands
+        assert_eq!(session.run("echo $x").unwrap().stdout, b"42\n");
+
+        // Output without a trailing newline and the exit status
+        let output = session.run("printf abc; false").unwrap();
+        assert!(!output.success);
+        assert_eq!(output.stdout, b"abc");
+
+        // stderr is merged, and commands reading stdin do not consume the next commands
+        let output = session.run("echo oops >&2; cat").unwrap();
+        assert_eq!(output.stdout, b"oops\n");
+        assert_eq!(
+            session.run("echo still here").unwrap().stdout,
+            b"still here\n"
+        );
+    }
+
+    #[test]
+    fn test_device_commands() {
+        let session = local_session();
+        // Stand-ins for the device tools
+        session
+            .run(
+                r#"uiautomator() { echo '<?xml version="1.0"?><hierarchy rotation="0"><node bounds="[0,0][10,10]" /></hierarchy>'; echo "UI hierchary dumped to: $2"; }
+wm() { echo "Physical size: 1080x2400"; }
+input() { echo "Error: Unknown command: $1" >&2; return 1; }"#,
+            )
+            .unwrap();
+
+        assert_eq!(
+            session.ui_hierarchy().unwrap(),
+            r#"<?xml version="1.0"?><hierarchy rotation="0"><node bounds="[0,0][10,10]" /></hierarchy>"#
+        );
+        assert_eq!(session.screen_size().unwrap(), (1080, 2400));
+        assert_eq!(
+            session.tap(5, 5).unwrap_err(),
+            BochiError::InputFailed("Tap command failed: Error: Unknown command: tap".to_string())
+        );
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("com.example:id/user"), "com.example:id/user");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("don't"), r"'don'\''t'");
+    }
+
+    #[test]
+    fn test_arguments_are_quoted() {
+        let session = local_session();
+        // Stand-in for `input` that keeps its arguments, one per line
+        session
+            .run(r#"input() { args=$(printf '%s\n' "$@"); }"#)
+            .unwrap();
+
+        // An unbalanced quote must not leave the shell waiting for the rest of the string
+        session.input_text("don't").unwrap();
+        assert_eq!(
+            session.run(r#"echo "$args""#).unwrap().stdout,
+            b"text\ndon't\n"
+        );
+        assert_eq!(session.shell(&["echo", "a;b"]).unwrap(), "a;b\n");
+        assert_eq!(
+            session.shell(&["echo", "$HOME `id` & x"]).unwrap(),
+            "$HOME `id` & x\n"
+        );
+    }
+
+    #[test]
+    fn test_command_timeout() {
+        let mut session = local_session();
+        session.command_timeout = Duration::from_millis(200);
+        let error = session.run("echo \"unterminated").unwrap_err();
+        assert!(
+            matches!(error, BochiError::Other(ref msg) if msg.contains("no response after 0.2 s"))
+        );
+        // The session is not used again once it may be stuck
+        assert!(session.run("echo hi").is_err());
+    }
+
+    #[test]
+    fn test_closed_session() {
+        let session = local_session();
+        let error = session.run("exit 3").unwrap_err();
+        assert!(
+            matches!(error, BochiError::Other(ref msg) if msg.starts_with("adb shell session failed"))
+        );
+    }
+}
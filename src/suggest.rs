@@ -0,0 +1,248 @@
+use crate::selector::{quote, AttrClause, AttrOp, PseudoClass, Selector};
+use crate::ui_element::{node_to_xml_string, parse_hierarchy};
+use serde_json::{json, Value};
+use std::cmp::Ordering;
+
+/// Maximum number of suggestions attached to a timeout error
+pub const SUGGESTION_LIMIT: usize = 3;
+
+/// Minimum similarity (0.0 to 1.0) for an element to be suggested
+const MIN_SIMILARITY: f64 = 0.6;
+
+/// An existing element that resembles what the selector looks for
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    /// Ready-to-use selector matching the element, e.g. `[text="Submit order"]`
+    pub selector: String,
+    /// XML of the suggested element
+    pub xml: String,
+    /// Similarity between the wanted and the actual value, from 0.0 to 1.0
+    pub score: f64,
+}
+
+impl Suggestion {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "selector": self.selector,
+            "xml": self.xml,
+            "score": self.score,
+        })
+    }
+}
+
+/// Attributes compared by suggestions, with the attribute names accepted in selectors
+const SUGGESTED_ATTRIBUTES: [(&str, &[&str]); 3] = [
+    ("text", &["text"]),
+    (
+        "content-desc",
+        &[
+            "content-desc",
+            "contentDescription",
+            "content-description",
+            "content_desc",
+        ],
+    ),
+    ("resource-id", &["resource-id", "resourceId", "resource_id"]),
+];
+
+/// Find elements of the hierarchy whose text, content-desc or resource-id resemble the
+/// values the selector asks for. Returns the closest ones first.
+pub fn suggest(xml: &str, selector: &Selector, limit: usize) -> Vec<Suggestion> {
+    let Ok(doc) = parse_hierarchy(xml) else {
+        return Vec::new();
+    };
+
+    let mut clauses = Vec::new();
+    collect_clauses(selector, &mut clauses);
+
+    let mut suggestions: Vec<Suggestion> = Vec::new();
+    for node in doc.descendants().filter(|n| n.is_element()) {
+        for clause in &clauses {
+            let Some((attr, _)) = SUGGESTED_ATTRIBUTES
+                .iter()
+                .find(|(_, aliases)| aliases.contains(&clause.attr.as_str()))
+            else {
+                continue;
+            };
+            let Some(actual) = node.attribute(*attr).filter(|v| !v.trim().is_empty()) else {
+                continue;
+            };
+            let score = if *attr == "resource-id" {
+                similarity(id_name(&clause.value), id_name(actual))
+            } else {
+                similarity(&clause.value, actual)
+            };
+            if score < MIN_SIMILARITY {
+                continue;
+            }
+
+            let suggestion = Suggestion {
+                selector: format!("[{}={}]", attr, quote(actual)),
+                xml: node_to_xml_string(node),
+                score,
+            };
+            match suggestions
+                .iter_mut()
+                .find(|s| s.selector == suggestion.selector)
+            {
+                Some(existing) if existing.score < score => *existing = suggestion,
+                Some(_) => {}
+                None => suggestions.push(suggestion),
+            }
+        }
+    }
+
+    // Stable sort: equally close elements stay in document order
+    suggestions.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+    suggestions.truncate(limit);
+    suggestions
+}
+
+/// Text lines appended to error messages
+pub fn suggestion_lines(suggestions: &[Suggestion]) -> Vec<String> {
+    if suggestions.is_empty() {
+        return Vec::new();
+    }
+    let mut lines = vec!["Did you mean:".to_string()];
+    lines.extend(
+        suggestions
+            .iter()
+            .map(|s| format!("  {}  {}", s.selector, s.xml)),
+    );
+    lines
+}
+
+/// Collect the clauses comparing an attribute with a literal value.
+/// Clauses inside :not() are skipped since their values are the ones to avoid.
+fn collect_clauses<'s>(selector: &'s Selector, clauses: &mut Vec<&'s AttrClause>) {
+    let add = |attrs: &'s [AttrClause], clauses: &mut Vec<&'s AttrClause>| {
+        clauses.extend(attrs.iter().filter(|c| {
+            matches!(
+                c.op,
+                AttrOp::Equals | AttrOp::StartsWith | AttrOp::EndsWith | AttrOp::Contains
+            ) && !c.value.is_empty()
+        }))
+    };
+    match selector {
+        Selector::And(attrs) => add(attrs, clauses),
+        Selector::Or(selectors) => selectors.iter().for_each(|s| collect_clauses(s, clauses)),
+        Selector::Complex { attrs, pseudos } => {
+            add(attrs, clauses);
+            for pseudo in pseudos {
+                if let PseudoClass::Is(inner) = pseudo {
+                    collect_clauses(inner, clauses);
+                }
+            }
+        }
+        // Only the element the command acts on is of interest
+        Selector::Child { child: subject, .. }
+        | Selector::Descendant {
+            descendant: subject,
+            ..
+        }
+        | Selector::AdjacentSibling { next: subject, .. }
+        | Selector::GeneralSibling { next: subject, .. } => collect_clauses(subject, clauses),
+    }
+}
+
+/// Case-insensitive similarity of two strings from 0.0 to 1.0.
+/// One string containing the other counts as a close match.
+fn similarity(wanted: &str, actual: &str) -> f64 {
+    let wanted = wanted.trim().to_lowercase();
+    let actual = actual.trim().to_lowercase();
+    let edit_score = strsim::normalized_damerau_levenshtein(&wanted, &actual);
+    let contains = wanted.chars().count() >= 3
+        && actual.chars().count() >= 3
+        && (actual.contains(&wanted) || wanted.contains(&actual));
+    if contains {
+        edit_score.max(0.8)
+    } else {
+        edit_score
+    }
+}
+
+/// The part of a resource-id after `:id/`, e.g. `submit` for `com.example:id/submit`
+fn id_name(resource_id: &str) -> &str {
+    resource_id.rsplit('/').next().unwrap_or(resource_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = r##"<?xml version="1.0" encoding="UTF-8"?><hierarchy rotation="0"><node class="Form" bounds="[0,0][1080,1920]"><node class="Button" text="Submit order" resource-id="com.example:id/submit_button" bounds="[0,200][200,300]" /><node class="Button" text="Cancel" content-desc="Cancel order" bounds="[200,200][400,300]" /><node class="TextView" text="Log in" bounds="[0,300][200,400]" /></node></hierarchy>"##;
+
+    fn suggestions_for(selector: &str) -> Vec<String> {
+        suggest(XML, &Selector::parse(selector).unwrap(), SUGGESTION_LIMIT)
+            .into_iter()
+            .map(|s| s.selector)
+            .collect()
+    }
+
+    #[test]
+    fn test_suggest_typo_in_text() {
+        assert_eq!(suggestions_for("[text=Cancle]"), vec![r#"[text="Cancel"]"#]);
+        assert_eq!(suggestions_for("[text=Login]"), vec![r#"[text="Log in"]"#]);
+    }
+
+    #[test]
+    fn test_suggest_changed_text() {
+        assert_eq!(
+            suggestions_for("[class=Button][text=Submit]"),
+            vec![r#"[text="Submit order"]"#]
+        );
+    }
+
+    #[test]
+    fn test_suggest_content_desc_alias() {
+        assert_eq!(
+            suggestions_for("[contentDescription=\"Cancel ordr\"]"),
+            vec![r#"[content-desc="Cancel order"]"#]
+        );
+    }
+
+    #[test]
+    fn test_suggest_resource_id_suffix() {
+        assert_eq!(
+            suggestions_for("[resource-id=com.example:id/submit_btn]"),
+            vec![r#"[resource-id="com.example:id/submit_button"]"#]
+        );
+        assert_eq!(
+            suggestions_for("[resource-id$=submitbutton]"),
+            vec![r#"[resource-id="com.example:id/submit_button"]"#]
+        );
+    }
+
+    #[test]
+    fn test_suggest_subject_of_combinator_only() {
+        assert_eq!(
+            suggestions_for("[text=Cancel] > [text=Log-in]"),
+            vec![r#"[text="Log in"]"#]
+        );
+    }
+
+    #[test]
+    fn test_suggest_nothing_similar() {
+        assert!(suggestions_for("[text=Settings]").is_empty());
+        assert!(suggestions_for("[class=Button]").is_empty());
+        assert!(suggestions_for(":not([text=Cancle])").is_empty());
+    }
+
+    #[test]
+    fn test_suggest_quotes_values() {
+        let xml =
+            r##"<hierarchy><node text="Say &quot;hi&quot;" bounds="[0,0][1,1]" /></hierarchy>"##;
+        let suggestions = suggest(xml, &Selector::parse("[text='Say hi']").unwrap(), 3);
+        assert_eq!(suggestions[0].selector, r#"[text="Say \"hi\""]"#);
+        assert!(Selector::parse(&suggestions[0].selector).is_ok());
+    }
+
+    #[test]
+    fn test_suggestion_lines() {
+        let suggestions = suggest(XML, &Selector::parse("[text=Cancle]").unwrap(), 3);
+        let lines = suggestion_lines(&suggestions);
+        assert_eq!(lines[0], "Did you mean:");
+        assert!(lines[1].starts_with(r#"  [text="Cancel"]  <node class="Button""#));
+        assert!(suggestion_lines(&[]).is_empty());
+    }
+}
@@ -1,12 +1,162 @@
-use crate::adb_utils::{format_adb_error, get_adb_command};
+use crate::adb_utils::{adb_exec_out, adb_failure, adb_read_file, adb_shell};
+use crate::error::BochiError;
 use crate::selector::Selector;
 use regex::Regex;
 use roxmltree::{Document, Node};
-
-#[derive(Debug)]
+use std::fs;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+/// An element of the UI hierarchy with its parsed attributes.
+/// Missing string attributes are empty and missing flags are false.
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct UiElement {
     pub bounds: (i32, i32, i32, i32),
     pub raw_xml: String,
+    /// All XML attributes of the node, in document order
+    pub attributes: Vec<(String, String)>,
+    pub text: String,
+    pub resource_id: String,
+    pub class: String,
+    pub package: String,
+    pub content_desc: String,
+    /// Position among its siblings as reported by uiautomator (the `index` attribute)
+    pub index: Option<usize>,
+    pub checkable: bool,
+    pub checked: bool,
+    pub clickable: bool,
+    pub enabled: bool,
+    pub focusable: bool,
+    pub focused: bool,
+    pub scrollable: bool,
+    pub long_clickable: bool,
+    pub password: bool,
+    pub selected: bool,
+    /// Position of the element in the `UiTree` of its hierarchy (document order)
+    pub tree_index: usize,
+    /// Tree index of the parent element, None for top-level elements
+    pub parent: Option<usize>,
+    /// Tree indices of the child elements, in document order
+    pub children: Vec<usize>,
+}
+
+impl UiElement {
+    /// Build an element from a hierarchy node.
+    /// Bounds are (0, 0, 0, 0) if the node has no valid bounds.
+    fn from_node(node: Node, indices: &TreeIndices) -> UiElement {
+        let text = |name: &str| node.attribute(name).unwrap_or_default().to_string();
+        let flag = |name: &str| node.attribute(name) == Some("true");
+        UiElement {
+            bounds: node_bounds(node).unwrap_or_default(),
+            raw_xml: node_to_xml_string(node),
+            attributes: node_attributes(node),
+            text: text("text"),
+            resource_id: text("resource-id"),
+            class: text("class"),
+            package: text("package"),
+            content_desc: text("content-desc"),
+            index: node.attribute("index").and_then(|i| i.parse().ok()),
+            checkable: flag("checkable"),
+            checked: flag("checked"),
+            clickable: flag("clickable"),
+            enabled: flag("enabled"),
+            focusable: flag("focusable"),
+            focused: flag("focused"),
+            scrollable: flag("scrollable"),
+            long_clickable: flag("long-clickable"),
+            password: flag("password"),
+            selected: flag("selected"),
+            tree_index: indices.get(node).unwrap_or_default(),
+            parent: node.parent_element().and_then(|p| indices.get(p)),
+            children: node
+                .children()
+                .filter_map(|child| indices.get(child))
+                .collect(),
+        }
+    }
+
+    /// Center point of the element's bounds, used as the tap position
+    pub fn center(&self) -> (i32, i32) {
+        let (x1, y1, x2, y2) = self.bounds;
+        ((x1 + x2) / 2, (y1 + y2) / 2)
+    }
+}
+
+/// All elements of a UI hierarchy in document order, linked through
+/// `parent` and `children` tree indices.
+/// The `<hierarchy>` element wrapping a uiautomator dump is not part of the tree.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UiTree {
+    /// Elements indexed by their `tree_index`
+    pub elements: Vec<UiElement>,
+    indices: TreeIndices,
+}
+
+impl UiTree {
+    /// Parse a UI hierarchy dump into a tree
+    pub fn parse(xml: &str) -> Result<UiTree, BochiError> {
+        Ok(UiTree::from_document(&parse_hierarchy(xml)?))
+    }
+
+    pub fn from_document(doc: &Document) -> UiTree {
+        let indices = TreeIndices::new(doc);
+        let elements = doc
+            .descendants()
+            .filter(|n| indices.get(*n).is_some())
+            .map(|n| UiElement::from_node(n, &indices))
+            .collect();
+        UiTree { elements, indices }
+    }
+
+    /// The element of a node of the document this tree was built from
+    pub fn element(&self, node: Node) -> Option<&UiElement> {
+        self.elements.get(self.indices.get(node)?)
+    }
+
+    /// Top-level elements
+    pub fn roots(&self) -> impl Iterator<Item = &UiElement> {
+        self.elements.iter().filter(|e| e.parent.is_none())
+    }
+
+    pub fn parent(&self, element: &UiElement) -> Option<&UiElement> {
+        self.elements.get(element.parent?)
+    }
+
+    pub fn children<'t>(&'t self, element: &'t UiElement) -> impl Iterator<Item = &'t UiElement> {
+        element
+            .children
+            .iter()
+            .filter_map(|&i| self.elements.get(i))
+    }
+
+    /// Parent, grandparent, ... up to the top-level element
+    pub fn ancestors<'t>(&'t self, element: &UiElement) -> impl Iterator<Item = &'t UiElement> {
+        std::iter::successors(self.parent(element), |e| self.parent(e))
+    }
+}
+
+/// Dense tree indices of the elements of a document, looked up by roxmltree node id
+#[derive(Debug, Clone, Default, PartialEq)]
+struct TreeIndices(Vec<Option<usize>>);
+
+impl TreeIndices {
+    fn new(doc: &Document) -> TreeIndices {
+        let mut indices = vec![None; doc.descendants().count()];
+        let wrapper = doc.root_element();
+        let elements = doc
+            .descendants()
+            .filter(|n| n.is_element())
+            .filter(|n| *n != wrapper || wrapper.tag_name().name() != "hierarchy");
+        for (index, node) in elements.enumerate() {
+            indices[node.id().get_usize()] = Some(index);
+        }
+        TreeIndices(indices)
+    }
+
+    fn get(&self, node: Node) -> Option<usize> {
+        self.0.get(node.id().get_usize()).copied().flatten()
+    }
 }
 
 /// Check if an element is visible within the given screen dimensions
@@ -20,40 +170,113 @@ pub fn is_element_visible(element: &UiElement, screen_width: i32, screen_height:
     has_horizontal_overlap && has_vertical_overlap
 }
 
-pub fn get_ui_hierarchy(serial: Option<&str>) -> Result<String, String> {
-    let output = get_adb_command(serial)
-        .map_err(|e| format_adb_error(&e))?
-        .args(["shell", "uiautomator", "dump", "/sdcard/window_dump.xml"])
-        .output()
-        .map_err(|e| format_adb_error(&e))?;
+/// Fetch the current UI hierarchy XML from the device.
+///
+/// The dump is streamed back through `adb exec-out` in a single round trip. Devices
+/// where that does not work fall back to dumping into a file on `/sdcard` and reading
+/// it back; once the fallback succeeds it is used for the rest of the process.
+pub fn get_ui_hierarchy(serial: Option<&str>) -> Result<String, BochiError> {
+    static EXEC_OUT_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+
+    if !EXEC_OUT_UNSUPPORTED.load(Ordering::Relaxed) {
+        match dump_via_exec_out(serial) {
+            Ok(xml) => return Ok(xml),
+            // The fallback would fail in the same way
+            Err(
+                e @ (BochiError::AdbNotFound
+                | BochiError::DeviceOffline(_)
+                | BochiError::DeviceUnauthorized(_)),
+            ) => return Err(e),
+            Err(_) => {
+                // Only give up on exec-out once the fallback works, so that a transient
+                // dump failure (e.g. during an animation) does not disable it
+                let xml = dump_via_file(serial)?;
+                EXEC_OUT_UNSUPPORTED.store(true, Ordering::Relaxed);
+                return Ok(xml);
+            }
+        }
+    }
+    dump_via_file(serial)
+}
+
+/// Dump to `/dev/tty` and read the XML from the stdout of `adb exec-out`
+fn dump_via_exec_out(serial: Option<&str>) -> Result<String, BochiError> {
+    let output = adb_exec_out(serial, &["uiautomator", "dump", "/dev/tty"])?;
 
-    if !output.status.success() {
-        return Err(format!(
-            "uiautomator dump failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ));
+    if !output.success {
+        return Err(adb_failure(&output.stderr, |msg| {
+            BochiError::DumpFailed(format!("uiautomator dump failed: {}", msg))
+        }));
     }
 
-    let output = get_adb_command(serial)
-        .map_err(|e| format_adb_error(&e))?
-        .args(["shell", "cat", "/sdcard/window_dump.xml"])
-        .output()
-        .map_err(|e| format_adb_error(&e))?;
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|e| BochiError::DumpFailed(format!("Invalid UTF-8 in dump output: {}", e)))?;
+    match extract_hierarchy(&stdout) {
+        Some(xml) => Ok(xml.to_string()),
+        None => Err(BochiError::DumpFailed(format!(
+            "uiautomator dump printed no UI hierarchy: {}",
+            stdout.trim()
+        ))),
+    }
+}
+
+/// Dump to a file on the device and read it back with `cat`
+fn dump_via_file(serial: Option<&str>) -> Result<String, BochiError> {
+    let output = adb_shell(serial, &["uiautomator", "dump", "/sdcard/window_dump.xml"])?;
+
+    if !output.success {
+        return Err(adb_failure(&output.stderr, |msg| {
+            BochiError::DumpFailed(format!("uiautomator dump failed: {}", msg))
+        }));
+    }
+
+    let output = adb_read_file(serial, "/sdcard/window_dump.xml")?;
 
-    if !output.status.success() {
-        return Err(format!(
-            "Failed to read dump file: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ));
+    if !output.success {
+        return Err(adb_failure(&output.stderr, |msg| {
+            BochiError::DumpFailed(format!("Failed to read dump file: {}", msg))
+        }));
     }
 
-    String::from_utf8(output.stdout).map_err(|e| format!("Invalid UTF-8 in dump file: {}", e))
+    String::from_utf8(output.stdout)
+        .map_err(|e| BochiError::DumpFailed(format!("Invalid UTF-8 in dump file: {}", e)))
+}
+
+/// The XML document in the output of `uiautomator dump /dev/tty`, which is followed
+/// by a "UI hierchary dumped to: /dev/tty" notice on the same stream
+pub(crate) fn extract_hierarchy(output: &str) -> Option<&str> {
+    let start = output.find("<?xml").or_else(|| output.find("<hierarchy"))?;
+    let end = match output.rfind("</hierarchy>") {
+        Some(close) => close + "</hierarchy>".len(),
+        None => {
+            // An empty hierarchy is a self-closing element
+            let tag = start + output[start..].find("<hierarchy")?;
+            tag + output[tag..].find("/>")? + "/>".len()
+        }
+    };
+    Some(&output[start..end])
+}
+
+/// Read a previously captured uiautomator dump from a file, or from stdin if path is "-"
+pub fn read_ui_hierarchy_file(path: &str) -> Result<String, BochiError> {
+    if path == "-" {
+        let mut xml = String::new();
+        io::stdin().read_to_string(&mut xml).map_err(|e| {
+            BochiError::Other(format!("Failed to read UI hierarchy from stdin: {}", e))
+        })?;
+        Ok(xml)
+    } else {
+        fs::read_to_string(path).map_err(|e| {
+            BochiError::Other(format!("Failed to read UI hierarchy file {}: {}", path, e))
+        })
+    }
 }
 
 pub fn parse_bounds(bounds_str: &str) -> Option<(i32, i32, i32, i32)> {
+    // Compiled once: layout pseudo-classes parse the bounds of many nodes per match
+    static BOUNDS_RE: OnceLock<Regex> = OnceLock::new();
     // Using r##"..."## for regex pattern with backslashes
-    let pattern = r##"\[(\d+),(\d+)\]\[(\d+),(\d+)\]"##;
-    let re = Regex::new(pattern).unwrap();
+    let re = BOUNDS_RE.get_or_init(|| Regex::new(r##"\[(\d+),(\d+)\]\[(\d+),(\d+)\]"##).unwrap());
     if let Some(caps) = re.captures(bounds_str) {
         let x1: i32 = caps.get(1)?.as_str().parse().ok()?;
         let y1: i32 = caps.get(2)?.as_str().parse().ok()?;
@@ -65,7 +288,7 @@ pub fn parse_bounds(bounds_str: &str) -> Option<(i32, i32, i32, i32)> {
     }
 }
 
-fn node_to_xml_string(node: roxmltree::Node) -> String {
+pub fn node_to_xml_string(node: roxmltree::Node) -> String {
     if !node.is_element() {
         return String::new();
     }
@@ -134,67 +357,69 @@ fn node_to_xml_string_with_descendants(node: roxmltree::Node) -> String {
     }
 }
 
-pub fn find_elements(xml: &str, selector: &Selector) -> Result<Vec<UiElement>, String> {
-    let doc = Document::parse(xml).map_err(|e| format!("Failed to parse XML: {}", e))?;
-    let mut elements = Vec::new();
-
-    collect_matching_elements(doc.root(), selector, &mut elements);
-
-    Ok(elements)
-}
-
-fn collect_matching_elements(node: Node, selector: &Selector, elements: &mut Vec<UiElement>) {
-    if node.is_element() && selector.matches(node) {
-        if let Some(bounds_str) = node.attribute("bounds") {
-            if let Some(bounds) = parse_bounds(bounds_str) {
-                let raw_xml = node_to_xml_string(node);
-                elements.push(UiElement { bounds, raw_xml });
-            }
-        }
-    }
-
-    // Recursively check children
-    for child in node.children() {
-        collect_matching_elements(child, selector, elements);
-    }
+/// Parse a UI hierarchy dump
+pub fn parse_hierarchy(xml: &str) -> Result<Document<'_>, BochiError> {
+    Document::parse(xml).map_err(|e| BochiError::XmlParse(format!("Failed to parse XML: {}", e)))
 }
 
-fn collect_matching_elements_with_descendants(
-    node: Node,
-    selector: &Selector,
-    elements: &mut Vec<UiElement>,
-) {
-    if node.is_element() && selector.matches(node) {
-        if let Some(bounds_str) = node.attribute("bounds") {
-            if let Some(bounds) = parse_bounds(bounds_str) {
-                let raw_xml = node_to_xml_string_with_descendants(node);
-                elements.push(UiElement { bounds, raw_xml });
-            }
-        }
-    }
-
-    // Recursively check children
-    for child in node.children() {
-        collect_matching_elements_with_descendants(child, selector, elements);
-    }
+/// Elements matching the selector, in document order.
+/// Nodes without valid bounds are skipped.
+pub fn find_elements(xml: &str, selector: &Selector) -> Result<Vec<UiElement>, BochiError> {
+    let doc = parse_hierarchy(xml)?;
+    let tree = UiTree::from_document(&doc);
+    Ok(matching_nodes(&doc, selector)
+        .filter_map(|node| tree.element(node).cloned())
+        .collect())
 }
 
+/// Like `find_elements`, but the `raw_xml` of each element includes its descendants
 pub fn find_elements_with_descendants(
     xml: &str,
     selector: &Selector,
-) -> Result<Vec<UiElement>, String> {
-    let doc = Document::parse(xml).map_err(|e| format!("Failed to parse XML: {}", e))?;
-    let mut elements = Vec::new();
+) -> Result<Vec<UiElement>, BochiError> {
+    let doc = parse_hierarchy(xml)?;
+    let tree = UiTree::from_document(&doc);
+    Ok(matching_nodes(&doc, selector)
+        .filter_map(|node| {
+            let element = tree.element(node)?;
+            Some(UiElement {
+                raw_xml: node_to_xml_string_with_descendants(node),
+                ..element.clone()
+            })
+        })
+        .collect())
+}
+
+fn node_attributes(node: Node) -> Vec<(String, String)> {
+    node.attributes()
+        .map(|attr| (attr.name().to_string(), attr.value().to_string()))
+        .collect()
+}
 
-    collect_matching_elements_with_descendants(doc.root(), selector, &mut elements);
+fn node_bounds(node: Node) -> Option<(i32, i32, i32, i32)> {
+    parse_bounds(node.attribute("bounds")?)
+}
 
-    Ok(elements)
+/// Nodes with valid bounds matching the selector, in document order
+fn matching_nodes<'a, 'input: 'a>(
+    doc: &'a Document<'input>,
+    selector: &'a Selector,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    doc.descendants()
+        .filter(|node| node.is_element() && selector.matches(*node) && node_bounds(*node).is_some())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn element_with_bounds(bounds: (i32, i32, i32, i32)) -> UiElement {
+        UiElement {
+            bounds,
+            ..Default::default()
+        }
+    }
+
     #[test]
     fn test_parse_bounds_valid() {
         let result = parse_bounds("[100,200][300,400]");
@@ -207,115 +432,176 @@ mod tests {
         assert_eq!(parse_bounds(""), None);
     }
 
+    #[test]
+    fn test_element_center() {
+        let element = element_with_bounds((100, 200, 301, 401));
+        assert_eq!(element.center(), (200, 300));
+    }
+
     #[test]
     fn test_is_element_visible_fully_inside() {
-        let element = UiElement {
-            bounds: (100, 100, 200, 200),
-            raw_xml: String::new(),
-        };
+        let element = element_with_bounds((100, 100, 200, 200));
         assert!(is_element_visible(&element, 500, 500));
     }
 
     #[test]
     fn test_is_element_visible_partially_inside() {
         // Partially visible on the right edge
-        let element = UiElement {
-            bounds: (450, 100, 550, 200),
-            raw_xml: String::new(),
-        };
+        let element = element_with_bounds((450, 100, 550, 200));
         assert!(is_element_visible(&element, 500, 500));
 
         // Partially visible on the bottom edge
-        let element = UiElement {
-            bounds: (100, 450, 200, 550),
-            raw_xml: String::new(),
-        };
+        let element = element_with_bounds((100, 450, 200, 550));
         assert!(is_element_visible(&element, 500, 500));
     }
 
     #[test]
     fn test_is_element_visible_completely_outside() {
         // Completely to the right
-        let element = UiElement {
-            bounds: (600, 100, 700, 200),
-            raw_xml: String::new(),
-        };
+        let element = element_with_bounds((600, 100, 700, 200));
         assert!(!is_element_visible(&element, 500, 500));
 
         // Completely to the bottom
-        let element = UiElement {
-            bounds: (100, 600, 200, 700),
-            raw_xml: String::new(),
-        };
+        let element = element_with_bounds((100, 600, 200, 700));
         assert!(!is_element_visible(&element, 500, 500));
 
         // Completely to the left (negative coordinates)
-        let element = UiElement {
-            bounds: (-100, 100, -50, 200),
-            raw_xml: String::new(),
-        };
+        let element = element_with_bounds((-100, 100, -50, 200));
         assert!(!is_element_visible(&element, 500, 500));
 
         // Completely to the top (negative coordinates)
-        let element = UiElement {
-            bounds: (100, -100, 200, -50),
-            raw_xml: String::new(),
-        };
+        let element = element_with_bounds((100, -100, 200, -50));
         assert!(!is_element_visible(&element, 500, 500));
     }
 
     #[test]
     fn test_is_element_visible_exactly_at_edge() {
         // Right edge exactly at 0 (no overlap)
-        let element = UiElement {
-            bounds: (-100, 100, 0, 200),
-            raw_xml: String::new(),
-        };
+        let element = element_with_bounds((-100, 100, 0, 200));
         assert!(!is_element_visible(&element, 500, 500));
 
         // Bottom edge exactly at 0 (no overlap)
-        let element = UiElement {
-            bounds: (100, -100, 200, 0),
-            raw_xml: String::new(),
-        };
+        let element = element_with_bounds((100, -100, 200, 0));
         assert!(!is_element_visible(&element, 500, 500));
 
         // Left edge exactly at screen width (no overlap)
-        let element = UiElement {
-            bounds: (500, 100, 600, 200),
-            raw_xml: String::new(),
-        };
+        let element = element_with_bounds((500, 100, 600, 200));
         assert!(!is_element_visible(&element, 500, 500));
 
         // Top edge exactly at screen height (no overlap)
-        let element = UiElement {
-            bounds: (100, 500, 200, 600),
-            raw_xml: String::new(),
-        };
+        let element = element_with_bounds((100, 500, 200, 600));
         assert!(!is_element_visible(&element, 500, 500));
     }
 
     #[test]
     fn test_is_element_visible_edge_cases() {
         // Element at (0,0) with size 0
-        let element = UiElement {
-            bounds: (0, 0, 0, 0),
-            raw_xml: String::new(),
-        };
+        let element = element_with_bounds((0, 0, 0, 0));
         assert!(!is_element_visible(&element, 500, 500));
 
         // Element exactly filling the screen
-        let element = UiElement {
-            bounds: (0, 0, 500, 500),
-            raw_xml: String::new(),
-        };
+        let element = element_with_bounds((0, 0, 500, 500));
         assert!(is_element_visible(&element, 500, 500));
 
         // Element larger than screen
-        let element = UiElement {
-            bounds: (-100, -100, 600, 600),
-            raw_xml: String::new(),
-        };
+        let element = element_with_bounds((-100, -100, 600, 600));
         assert!(is_element_visible(&element, 500, 500));
     }
+
+    #[test]
+    fn test_extract_hierarchy() {
+        let xml = r#"<?xml version='1.0' encoding='UTF-8' standalone='yes' ?><hierarchy rotation="0"><node text="A" /></hierarchy>"#;
+        let output = format!("{}UI hierchary dumped to: /dev/tty\n", xml);
+        assert_eq!(extract_hierarchy(&output), Some(xml));
+        assert_eq!(extract_hierarchy(xml), Some(xml));
+
+        let empty =
+            r#"<?xml version='1.0' encoding='UTF-8' standalone='yes' ?><hierarchy rotation="0" />"#;
+        let output = format!("{}UI hierchary dumped to: /dev/tty\n", empty);
+        assert_eq!(extract_hierarchy(&output), Some(empty));
+    }
+
+    #[test]
+    fn test_extract_hierarchy_without_xml() {
+        assert_eq!(
+            extract_hierarchy("ERROR: null root node returned by UiTestAutomationBridge.\n"),
+            None
+        );
+        assert_eq!(extract_hierarchy(""), None);
+    }
+
+    const TREE_XML: &str = r##"<?xml version="1.0" encoding="UTF-8"?><hierarchy rotation="0"><node index="0" text="" resource-id="" class="android.widget.FrameLayout" package="com.example" content-desc="" checkable="false" checked="false" clickable="false" enabled="true" focusable="false" focused="false" scrollable="false" long-clickable="false" password="false" selected="false" bounds="[0,0][1080,1920]"><node index="0" text="Name" class="android.widget.TextView" package="com.example" bounds="[0,0][1080,100]" /><node index="1" text="" resource-id="com.example:id/name" class="android.widget.EditText" package="com.example" content-desc="Your name" checkable="true" checked="true" clickable="true" enabled="true" focusable="true" focused="true" scrollable="false" long-clickable="true" password="true" selected="true" bounds="[0,100][1080,200]" /></node><node index="1" class="android.widget.Toast" bounds="[0,1800][1080,1900]" /></hierarchy>"##;
+
+    #[test]
+    fn test_tree_links() {
+        let tree = UiTree::parse(TREE_XML).unwrap();
+        assert_eq!(tree.elements.len(), 4);
+        for (i, element) in tree.elements.iter().enumerate() {
+            assert_eq!(element.tree_index, i);
+        }
+
+        let frame = &tree.elements[0];
+        assert_eq!(frame.parent, None);
+        assert_eq!(frame.children, vec![1, 2]);
+        assert_eq!(tree.parent(&tree.elements[2]), Some(frame));
+        let children: Vec<&str> = tree.children(frame).map(|e| e.text.as_str()).collect();
+        assert_eq!(children, vec!["Name", ""]);
+        let ancestors: Vec<usize> = tree
+            .ancestors(&tree.elements[2])
+            .map(|e| e.tree_index)
+            .collect();
+        assert_eq!(ancestors, vec![0]);
+
+        let roots: Vec<&str> = tree.roots().map(|e| e.class.as_str()).collect();
+        assert_eq!(
+            roots,
+            vec!["android.widget.FrameLayout", "android.widget.Toast"]
+        );
+    }
+
+    #[test]
+    fn test_typed_fields() {
+        let tree = UiTree::parse(TREE_XML).unwrap();
+        let field = &tree.elements[2];
+        assert_eq!(field.resource_id, "com.example:id/name");
+        assert_eq!(field.class, "android.widget.EditText");
+        assert_eq!(field.package, "com.example");
+        assert_eq!(field.content_desc, "Your name");
+        assert_eq!(field.index, Some(1));
+        assert_eq!(field.bounds, (0, 100, 1080, 200));
+        assert!(field.checkable && field.checked && field.clickable && field.enabled);
+        assert!(field.focusable && field.focused && field.long_clickable);
+        assert!(field.password && field.selected && !field.scrollable);
+
+        // Missing attributes are empty or false
+        let toast = &tree.elements[3];
+        assert_eq!(toast.text, "");
+        assert_eq!(toast.resource_id, "");
+        assert!(!toast.enabled);
+        assert_eq!(tree.elements[1].index, Some(0));
+    }
+
+    #[test]
+    fn test_find_elements_tree_indices() {
+        let selector = Selector::parse("[class$=EditText]").unwrap();
+        let found = find_elements(TREE_XML, &selector).unwrap();
+        let tree = UiTree::parse(TREE_XML).unwrap();
+        assert_eq!(found, vec![tree.elements[2].clone()]);
+        assert_eq!(found[0].parent, Some(0));
+
+        let with_descendants =
+            find_elements_with_descendants(TREE_XML, &Selector::parse("[index=0]").unwrap())
+                .unwrap();
+        assert_eq!(with_descendants.len(), 2);
+        assert!(with_descendants[0].raw_xml.ends_with("</node>"));
+        assert_eq!(with_descendants[0].children, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_find_elements_skips_nodes_without_bounds() {
+        let xml = r##"<hierarchy><node text="A"><node text="A" bounds="[0,0][1,1]" /></node></hierarchy>"##;
+        let found = find_elements(xml, &Selector::parse("[text=A]").unwrap()).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].tree_index, 1);
+    }
 }